@@ -67,6 +67,57 @@ pub fn get_trip_name(trip_id: usize) -> String {
         .clone()
 }
 
+/// The extended `route_type` of the trip, e.g. `3` for buses. See `RouteType::basic` for the mode.
+#[wasm_bindgen]
+pub fn get_trip_route_type(trip_id: usize) -> u16 {
+    let dataset = GTFS_DATASET.read().unwrap();
+    dataset
+        .get_route(dataset.get_trip(trip_id).route_id)
+        .route_type
+        .to_gtfs()
+}
+
+/// The basic `route_type` closest to the one of the trip, to choose its icon.
+#[wasm_bindgen]
+pub fn get_trip_mode(trip_id: usize) -> u16 {
+    let dataset = GTFS_DATASET.read().unwrap();
+    dataset
+        .get_route(dataset.get_trip(trip_id).route_id)
+        .route_type
+        .basic()
+        .to_gtfs()
+}
+
+/// Hex color of the line of the trip, without `#`.
+#[wasm_bindgen]
+pub fn get_trip_color(trip_id: usize) -> String {
+    let dataset = GTFS_DATASET.read().unwrap();
+    dataset
+        .get_route(dataset.get_trip(trip_id).route_id)
+        .route_color
+        .clone()
+}
+
+/// Hex color of the text drawn on the line color of the trip, without `#`.
+#[wasm_bindgen]
+pub fn get_trip_text_color(trip_id: usize) -> String {
+    let dataset = GTFS_DATASET.read().unwrap();
+    dataset
+        .get_route(dataset.get_trip(trip_id).route_id)
+        .route_text_color
+        .clone()
+}
+
+/// Empty if the feed doesn't say which agency runs the trip.
+#[wasm_bindgen]
+pub fn get_trip_agency_name(trip_id: usize) -> String {
+    let dataset = GTFS_DATASET.read().unwrap();
+    dataset
+        .get_route_agency(dataset.get_trip(trip_id).route_id)
+        .map(|agency| agency.agency_name.clone())
+        .unwrap_or_default()
+}
+
 #[wasm_bindgen]
 pub fn try_navigate() -> JsValue {
     trace!("Starting navigation from venice to nave de vero");
@@ -110,6 +161,7 @@ fn navigate(dataset: &GtfsData, from: &LatLng, to: &LatLng) -> Vec<Solution> {
         max_changes: 4,
         start_time: default_start_time(),
         num_solutions_to_find: 3,
+        avoid_stairs: false,
     };
     RaptorNavigator::navigate_blocking(&dataset, params)
 }
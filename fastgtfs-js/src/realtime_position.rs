@@ -25,13 +25,15 @@ pub fn get_near_trips(
     date: &str,
     number: usize,
 ) -> JsValue {
-    let start_day = GtfsTime::from_date(&date);
-
-    let mut start_date = GtfsTime::new_from_midnight(seconds_since_midnight as i64);
-    start_date.set_day_from(&start_day);
+    let ds = GTFS_DATASET.read().unwrap();
+    let start_day = GtfsTime::from_date(&date).service_date();
+    let start_date = GtfsTime::from_service_date(
+        start_day,
+        seconds_since_midnight as i64,
+        ds.get_timezone(),
+    );
     let position = LatLng { lat, lng };
 
-    let ds = GTFS_DATASET.read().unwrap();
     let trips: Vec<&Trip> =
         ds.get_near_trips_near_stops(&start_date, &position, number, 10_000_000);
     let res: Vec<usize> = trips
@@ -0,0 +1,183 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::gtfs_data::{FareAttribute, GtfsData, RouteId};
+use crate::navigator_models::{BusSolutionComponent, Solution, SolutionComponent};
+
+/// Price of a `Solution`, computed with `fare_attributes.txt` and `fare_rules.txt`.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SolutionFare {
+    pub total_price: f64,
+    pub currency_type: String,
+    /// One for each bus component of the solution, in order.
+    pub legs: Vec<LegFare>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct LegFare {
+    /// Index of the bus component in `Solution.components`.
+    pub component_index: usize,
+    pub fare_id: usize,
+    /// Zero when the leg is covered by the ticket bought for a previous one (a transfer).
+    pub price: f64,
+}
+
+/// `route_id`, `origin_id` and `destination_id` of a row of `fare_rules.txt`.
+type RuleKey<'a> = (Option<RouteId>, Option<&'a str>, Option<&'a str>);
+
+/// A bus leg, as seen by the fare rules.
+struct Ride<'a> {
+    component_index: usize,
+    route_id: RouteId,
+    origin_zone: &'a str,
+    destination_zone: &'a str,
+    zones: BTreeSet<&'a str>,
+    /// Timestamp of the departure.
+    departure: i64,
+}
+
+/// Computes the cheapest way to pay for `solution`.
+///
+/// The bus legs are split in groups of consecutive legs, and each group is paid with a single
+/// ticket, allowed to cover all of them by its rules, its `transfers` and its `transfer_duration`.
+/// Returns `None` if the dataset has no fares, or if a leg can't be paid with any of them.
+/// Prices in different currencies are not converted: feeds usually have only one.
+pub fn calculate_fare(dataset: &GtfsData, solution: &Solution) -> Option<SolutionFare> {
+    if dataset.fares.is_empty() {
+        return None;
+    }
+    let rides = solution
+        .components
+        .iter()
+        .enumerate()
+        .filter_map(|(inx, component)| match component {
+            SolutionComponent::Bus(b) => Some(create_ride(dataset, inx, b)),
+            SolutionComponent::Walk(_) => None,
+        })
+        .collect::<Vec<Ride>>();
+
+    // best[i]: (price, start of the last group, fare of the last group) to pay the first i rides.
+    let mut best: Vec<Option<(f64, usize, usize)>> = vec![None; rides.len() + 1];
+    best[0] = Some((0.0, 0, 0));
+    for end in 1..=rides.len() {
+        for start in 0..end {
+            let (prec_price, _, _) = match best[start] {
+                Some(prec) => prec,
+                None => continue,
+            };
+            let cheapest_fare = dataset
+                .fares
+                .iter()
+                .filter(|fare| covers(fare, &rides[start..end]))
+                .min_by(|a, b| a.price.partial_cmp(&b.price).unwrap());
+            if let Some(fare) = cheapest_fare {
+                let price = prec_price + fare.price;
+                if best[end].map_or(true, |(best_price, _, _)| price < best_price) {
+                    best[end] = Some((price, start, fare.fare_id));
+                }
+            }
+        }
+    }
+
+    let (total_price, _, _) = best[rides.len()]?;
+    let mut legs = vec![];
+    let mut end = rides.len();
+    while end > 0 {
+        let (_, start, fare_id) = best[end].unwrap();
+        let fare = &dataset.fares[fare_id];
+        for (inx, ride) in rides[start..end].iter().enumerate().rev() {
+            legs.push(LegFare {
+                component_index: ride.component_index,
+                fare_id,
+                price: if inx == 0 { fare.price } else { 0.0 },
+            });
+        }
+        end = start;
+    }
+    legs.reverse();
+
+    let currency_type = legs
+        .first()
+        .map(|leg| dataset.fares[leg.fare_id].currency_type.clone())
+        .unwrap_or_else(|| dataset.fares[0].currency_type.clone());
+    Some(SolutionFare {
+        total_price,
+        currency_type,
+        legs,
+    })
+}
+
+fn create_ride<'a>(
+    dataset: &'a GtfsData,
+    component_index: usize,
+    bus: &BusSolutionComponent,
+) -> Ride<'a> {
+    let zone = |inx: usize| {
+        dataset
+            .get_stop(bus.path.stop_times[inx].stop_id)
+            .zone_id
+            .as_str()
+    };
+    Ride {
+        component_index,
+        route_id: bus.route.route_id,
+        origin_zone: zone(bus.from_inx),
+        destination_zone: zone(bus.to_inx),
+        zones: (bus.from_inx..=bus.to_inx).map(zone).collect(),
+        departure: bus.departure_time().timestamp(),
+    }
+}
+
+/// Whether a single ticket of `fare` pays for all the `rides`, changing vehicle between them.
+///
+/// Each row of `fare_rules.txt` is a rule on its own, matching when all its fields do: the route
+/// of every ride, the zone of the first origin and the one of the last destination. The rows with
+/// `contains_id` and the same route, origin and destination are a single rule, that also needs
+/// the zones passed to be exactly theirs.
+fn covers(fare: &FareAttribute, rides: &[Ride]) -> bool {
+    let (first, last) = (rides.first().unwrap(), rides.last().unwrap());
+    let changes = rides.len() - 1;
+    if fare.transfers.map_or(false, |t| changes > t as usize) {
+        return false;
+    }
+    if let Some(transfer_duration) = fare.transfer_duration {
+        if last.departure - first.departure > transfer_duration {
+            return false;
+        }
+    }
+    if fare.rules.is_empty() {
+        return true;
+    }
+
+    let matches = |&(route_id, origin_id, destination_id): &RuleKey| {
+        route_id.map_or(true, |route_id| {
+            rides.iter().all(|r| r.route_id == route_id)
+        }) && origin_id.map_or(true, |zone| zone == first.origin_zone)
+            && destination_id.map_or(true, |zone| zone == last.destination_zone)
+    };
+    let mut contains: BTreeMap<RuleKey, BTreeSet<&str>> = BTreeMap::new();
+    for rule in &fare.rules {
+        let key = (
+            rule.route_id,
+            rule.origin_id.as_deref(),
+            rule.destination_id.as_deref(),
+        );
+        match rule.contains_id.as_deref() {
+            Some(zone) => {
+                contains.entry(key).or_default().insert(zone);
+            }
+            None if matches(&key) => return true,
+            None => {}
+        }
+    }
+    // Stops without a zone don't count.
+    let zones = rides
+        .iter()
+        .flat_map(|r| r.zones.iter().copied())
+        .filter(|zone| !zone.is_empty())
+        .collect::<BTreeSet<&str>>();
+    contains
+        .iter()
+        .any(|(key, contains)| matches(key) && *contains == zones)
+}
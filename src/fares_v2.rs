@@ -0,0 +1,541 @@
+use std::collections::{BTreeSet, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::gtfs_data::{GtfsData, RouteId, StopId};
+use crate::navigator_models::{BusSolutionComponent, Solution, SolutionComponent};
+
+/// The files of GTFS-Fares v2: `fare_media.txt`, `fare_products.txt`, `fare_leg_rules.txt`,
+/// `fare_transfer_rules.txt`, `areas.txt`, `stop_areas.txt`, `networks.txt` and
+/// `route_networks.txt`. All the ids are indexes in the vectors.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct FaresV2 {
+    pub fare_media: Vec<FareMedia>,
+    pub fare_products: Vec<FareProduct>,
+    pub fare_leg_rules: Vec<FareLegRule>,
+    pub fare_transfer_rules: Vec<FareTransferRule>,
+    pub areas: Vec<Area>,
+    pub networks: Vec<Network>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FareMedia {
+    pub fare_media_id: usize,
+    pub fare_media_name: String,
+    pub fare_media_type: FareMediaType,
+}
+
+/// Values of `fare_media_type` in `fare_media.txt`.
+#[derive(Hash, Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum FareMediaType {
+    /// No fare media involved, e.g. paying cash to the driver.
+    None,
+    PaperTicket,
+    TransitCard,
+    ContactlessEmv,
+    MobileApp,
+}
+
+impl Default for FareMediaType {
+    fn default() -> Self {
+        FareMediaType::None
+    }
+}
+
+impl FareMediaType {
+    pub fn from_gtfs(value: &str) -> Option<FareMediaType> {
+        match value.trim() {
+            "0" => Some(FareMediaType::None),
+            "1" => Some(FareMediaType::PaperTicket),
+            "2" => Some(FareMediaType::TransitCard),
+            "3" => Some(FareMediaType::ContactlessEmv),
+            "4" => Some(FareMediaType::MobileApp),
+            _ => None,
+        }
+    }
+
+    pub fn to_gtfs(&self) -> u8 {
+        match self {
+            FareMediaType::None => 0,
+            FareMediaType::PaperTicket => 1,
+            FareMediaType::TransitCard => 2,
+            FareMediaType::ContactlessEmv => 3,
+            FareMediaType::MobileApp => 4,
+        }
+    }
+}
+
+/// All the rows of `fare_products.txt` with the same `fare_product_id`.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FareProduct {
+    pub fare_product_id: usize,
+    pub fare_product_name: String,
+    pub prices: Vec<FareProductPrice>,
+}
+
+/// The price of a product for a rider category, bought with a fare media.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FareProductPrice {
+    /// Empty for the prices that hold for every rider category.
+    pub rider_category_id: String,
+    /// `None` if the price doesn't depend on the fare media.
+    pub fare_media_id: Option<usize>,
+    /// Negative for discounts.
+    pub amount: f64,
+    pub currency: String,
+}
+
+/// A row of `fare_leg_rules.txt`. Empty fields match the legs not matched by other rules, or
+/// any leg when the feed uses `rule_priority`.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FareLegRule {
+    pub leg_group_id: Option<String>,
+    pub network_id: Option<usize>,
+    pub from_area_id: Option<usize>,
+    pub to_area_id: Option<usize>,
+    pub fare_product_id: usize,
+    /// Among the matching rules, only the ones with the highest priority apply.
+    pub rule_priority: Option<i32>,
+}
+
+/// A row of `fare_transfer_rules.txt`.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FareTransferRule {
+    pub from_leg_group_id: Option<String>,
+    pub to_leg_group_id: Option<String>,
+    /// How many consecutive transfers are allowed, for transfers within the same leg group.
+    /// `Some(-1)` means unlimited.
+    pub transfer_count: Option<i32>,
+    /// Seconds, measured as in `duration_limit_type`.
+    pub duration_limit: Option<i64>,
+    pub duration_limit_type: DurationLimitType,
+    pub fare_transfer_type: FareTransferType,
+    /// `None` if the transfer is free.
+    pub fare_product_id: Option<usize>,
+}
+
+/// Values of `duration_limit_type` in `fare_transfer_rules.txt`: which times of the current and
+/// next leg `duration_limit` is measured between.
+#[derive(Hash, Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum DurationLimitType {
+    DepartureToArrival,
+    DepartureToDeparture,
+    ArrivalToDeparture,
+    ArrivalToArrival,
+}
+
+impl Default for DurationLimitType {
+    fn default() -> Self {
+        DurationLimitType::DepartureToArrival
+    }
+}
+
+impl DurationLimitType {
+    pub fn from_gtfs(value: &str) -> Option<DurationLimitType> {
+        match value.trim() {
+            "0" => Some(DurationLimitType::DepartureToArrival),
+            "1" => Some(DurationLimitType::DepartureToDeparture),
+            "2" => Some(DurationLimitType::ArrivalToDeparture),
+            "3" => Some(DurationLimitType::ArrivalToArrival),
+            _ => None,
+        }
+    }
+
+    pub fn to_gtfs(&self) -> u8 {
+        match self {
+            DurationLimitType::DepartureToArrival => 0,
+            DurationLimitType::DepartureToDeparture => 1,
+            DurationLimitType::ArrivalToDeparture => 2,
+            DurationLimitType::ArrivalToArrival => 3,
+        }
+    }
+}
+
+/// Values of `fare_transfer_type` in `fare_transfer_rules.txt`, with A and B the products of the
+/// two legs and AB the product of the transfer.
+#[derive(Hash, Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum FareTransferType {
+    /// A + AB: the second leg is not paid.
+    FirstLegPlusTransfer,
+    /// A + AB + B.
+    BothLegsPlusTransfer,
+    /// AB: only the transfer is paid.
+    TransferOnly,
+}
+
+impl Default for FareTransferType {
+    fn default() -> Self {
+        FareTransferType::FirstLegPlusTransfer
+    }
+}
+
+impl FareTransferType {
+    pub fn from_gtfs(value: &str) -> Option<FareTransferType> {
+        match value.trim() {
+            "0" => Some(FareTransferType::FirstLegPlusTransfer),
+            "1" => Some(FareTransferType::BothLegsPlusTransfer),
+            "2" => Some(FareTransferType::TransferOnly),
+            _ => None,
+        }
+    }
+
+    pub fn to_gtfs(&self) -> u8 {
+        match self {
+            FareTransferType::FirstLegPlusTransfer => 0,
+            FareTransferType::BothLegsPlusTransfer => 1,
+            FareTransferType::TransferOnly => 2,
+        }
+    }
+}
+
+/// An area of `areas.txt`, with its stops from `stop_areas.txt`.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Area {
+    pub area_id: usize,
+    pub area_name: String,
+    pub stop_ids: BTreeSet<StopId>,
+}
+
+/// A network of `networks.txt`, with its routes from `route_networks.txt` or `routes.txt`.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Network {
+    pub network_id: usize,
+    pub network_name: String,
+    pub route_ids: BTreeSet<RouteId>,
+}
+
+/// A way to pay for a `Solution`, for a rider category and a fare media.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FareCandidate {
+    /// Empty for the riders without a specific category.
+    pub rider_category_id: String,
+    pub fare_media_id: Option<usize>,
+    pub total_price: f64,
+    pub currency: String,
+    /// One for each bus component of the solution, in order.
+    pub legs: Vec<LegProduct>,
+    /// One for each change of vehicle where a transfer rule applies.
+    pub transfers: Vec<TransferProduct>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct LegProduct {
+    /// Index of the bus component in `Solution.components`.
+    pub component_index: usize,
+    pub fare_product_id: usize,
+    /// What is paid for this leg: zero when a transfer makes it free.
+    pub price: f64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TransferProduct {
+    pub from_component_index: usize,
+    pub to_component_index: usize,
+    pub fare_product_id: Option<usize>,
+    pub price: f64,
+}
+
+/// A bus leg, as seen by the fare rules.
+struct Leg {
+    component_index: usize,
+    networks: HashSet<usize>,
+    from_areas: HashSet<usize>,
+    to_areas: HashSet<usize>,
+    /// Timestamps.
+    departure: i64,
+    arrival: i64,
+}
+
+/// The product chosen for a leg: its rule, and its price.
+type LegChoice<'a> = (&'a FareLegRule, &'a FareProductPrice);
+
+/// Evaluates the products of GTFS-Fares v2 for each leg and each transfer of `solution`.
+///
+/// Returns one candidate for each rider category and fare media that can pay all the legs,
+/// sorted by rider category and price. Each leg uses the cheapest product its rules allow, and
+/// each change of vehicle the cheapest of the matching transfer rules (or none, if that costs
+/// less).
+pub fn calculate_fare_candidates(dataset: &GtfsData, solution: &Solution) -> Vec<FareCandidate> {
+    let fares = &dataset.fares_v2;
+    let legs = solution
+        .components
+        .iter()
+        .enumerate()
+        .filter_map(|(inx, component)| match component {
+            SolutionComponent::Bus(b) => Some(create_leg(fares, inx, b)),
+            SolutionComponent::Walk(_) => None,
+        })
+        .collect::<Vec<Leg>>();
+    if legs.is_empty() || fares.fare_leg_rules.is_empty() {
+        return vec![];
+    }
+    let rules_per_leg = legs
+        .iter()
+        .map(|leg| matching_leg_rules(fares, leg))
+        .collect::<Vec<Vec<&FareLegRule>>>();
+
+    let prices = fares.fare_products.iter().flat_map(|p| &p.prices);
+    let categories = prices
+        .clone()
+        .map(|p| p.rider_category_id.as_str())
+        .collect::<BTreeSet<&str>>();
+    let media = prices
+        .map(|p| p.fare_media_id)
+        .collect::<BTreeSet<Option<usize>>>();
+
+    let mut candidates = vec![];
+    for &category in &categories {
+        for &fare_media_id in &media {
+            let pricing = Pricing {
+                fares,
+                category,
+                fare_media_id,
+            };
+            let choices = rules_per_leg
+                .iter()
+                .map(|rules| pricing.cheapest_leg_product(rules))
+                .collect::<Option<Vec<LegChoice>>>();
+            if let Some(choices) = choices {
+                candidates.push(pricing.create_candidate(&legs, &choices));
+            }
+        }
+    }
+    candidates.sort_by(|a, b| {
+        a.rider_category_id
+            .cmp(&b.rider_category_id)
+            .then(a.total_price.partial_cmp(&b.total_price).unwrap())
+    });
+    candidates
+}
+
+fn create_leg(fares: &FaresV2, component_index: usize, bus: &BusSolutionComponent) -> Leg {
+    let areas_of = |inx: usize| {
+        let stop_id = bus.path.stop_times[inx].stop_id;
+        fares
+            .areas
+            .iter()
+            .filter(|a| a.stop_ids.contains(&stop_id))
+            .map(|a| a.area_id)
+            .collect()
+    };
+    Leg {
+        component_index,
+        networks: fares
+            .networks
+            .iter()
+            .filter(|n| n.route_ids.contains(&bus.route.route_id))
+            .map(|n| n.network_id)
+            .collect(),
+        from_areas: areas_of(bus.from_inx),
+        to_areas: areas_of(bus.to_inx),
+        departure: bus.departure_time().timestamp(),
+        arrival: bus.arrival_time().timestamp(),
+    }
+}
+
+/// With `rule_priority`, empty fields match anything and only the highest priority rules are
+/// kept. Without it, empty fields match only when no rule has the value of the leg.
+fn matching_leg_rules<'a>(fares: &'a FaresV2, leg: &Leg) -> Vec<&'a FareLegRule> {
+    let rules = &fares.fare_leg_rules;
+    let in_set = |field: Option<usize>, values: &HashSet<usize>| {
+        field.map_or(false, |value| values.contains(&value))
+    };
+
+    if rules.iter().any(|r| r.rule_priority.is_some()) {
+        let matching = rules
+            .iter()
+            .filter(|r| {
+                (r.network_id.is_none() || in_set(r.network_id, &leg.networks))
+                    && (r.from_area_id.is_none() || in_set(r.from_area_id, &leg.from_areas))
+                    && (r.to_area_id.is_none() || in_set(r.to_area_id, &leg.to_areas))
+            })
+            .collect::<Vec<&FareLegRule>>();
+        let max_priority = matching.iter().map(|r| r.rule_priority.unwrap_or(0)).max();
+        return matching
+            .into_iter()
+            .filter(|r| Some(r.rule_priority.unwrap_or(0)) == max_priority)
+            .collect();
+    }
+
+    let explicit_network = rules.iter().any(|r| in_set(r.network_id, &leg.networks));
+    let explicit_from = rules
+        .iter()
+        .any(|r| in_set(r.from_area_id, &leg.from_areas));
+    let explicit_to = rules.iter().any(|r| in_set(r.to_area_id, &leg.to_areas));
+    let matches = |field: Option<usize>, values: &HashSet<usize>, explicit: bool| {
+        if explicit {
+            in_set(field, values)
+        } else {
+            field.is_none()
+        }
+    };
+    rules
+        .iter()
+        .filter(|r| {
+            matches(r.network_id, &leg.networks, explicit_network)
+                && matches(r.from_area_id, &leg.from_areas, explicit_from)
+                && matches(r.to_area_id, &leg.to_areas, explicit_to)
+        })
+        .collect()
+}
+
+/// Prices the products for a rider category and a fare media.
+struct Pricing<'a> {
+    fares: &'a FaresV2,
+    category: &'a str,
+    fare_media_id: Option<usize>,
+}
+
+impl<'a> Pricing<'a> {
+    /// Prices without a category hold when the category has no specific one.
+    fn product_price(&self, fare_product_id: usize) -> Option<&'a FareProductPrice> {
+        let usable = self.fares.fare_products[fare_product_id]
+            .prices
+            .iter()
+            .filter(|p| p.fare_media_id.is_none() || p.fare_media_id == self.fare_media_id)
+            .collect::<Vec<&FareProductPrice>>();
+        let cheapest = |category: &str| {
+            usable
+                .iter()
+                .filter(|p| p.rider_category_id == category)
+                .min_by(|a, b| a.amount.partial_cmp(&b.amount).unwrap())
+                .copied()
+        };
+        cheapest(self.category).or_else(|| cheapest(""))
+    }
+
+    fn cheapest_leg_product(&self, rules: &[&'a FareLegRule]) -> Option<LegChoice<'a>> {
+        rules
+            .iter()
+            .filter_map(|&rule| {
+                self.product_price(rule.fare_product_id)
+                    .map(|price| (rule, price))
+            })
+            .min_by(|(_, a), (_, b)| a.amount.partial_cmp(&b.amount).unwrap())
+    }
+
+    fn create_candidate(&self, legs: &[Leg], choices: &[LegChoice]) -> FareCandidate {
+        let mut leg_products = legs
+            .iter()
+            .zip(choices)
+            .map(|(leg, (rule, price))| LegProduct {
+                component_index: leg.component_index,
+                fare_product_id: rule.fare_product_id,
+                price: price.amount,
+            })
+            .collect::<Vec<LegProduct>>();
+        let mut transfers = vec![];
+
+        // The first leg of the current chain of transfers, and how many transfers it has.
+        let mut chain = (0, 0);
+        for to in 1..legs.len() {
+            let from = to - 1;
+            let paid = (leg_products[from].price, leg_products[to].price);
+            let (rule, price) = match self.cheapest_transfer(legs, choices, chain, to, paid) {
+                Some(transfer) => transfer,
+                None => {
+                    chain = (to, 0);
+                    continue;
+                }
+            };
+            match rule.fare_transfer_type {
+                FareTransferType::FirstLegPlusTransfer => leg_products[to].price = 0.0,
+                FareTransferType::BothLegsPlusTransfer => {}
+                FareTransferType::TransferOnly => {
+                    leg_products[from].price = 0.0;
+                    leg_products[to].price = 0.0;
+                }
+            }
+            transfers.push(TransferProduct {
+                from_component_index: legs[from].component_index,
+                to_component_index: legs[to].component_index,
+                fare_product_id: rule.fare_product_id,
+                price,
+            });
+            chain.1 += 1;
+        }
+
+        let total_price = leg_products.iter().map(|l| l.price).sum::<f64>()
+            + transfers.iter().map(|t| t.price).sum::<f64>();
+        FareCandidate {
+            rider_category_id: self.category.to_string(),
+            fare_media_id: self.fare_media_id,
+            total_price,
+            currency: choices[0].1.currency.clone(),
+            legs: leg_products,
+            transfers,
+        }
+    }
+
+    /// The transfer rule to use when changing from the leg before `to` to `to`, with its price,
+    /// if any rule applies and it doesn't cost more than paying the legs separately.
+    /// `chain` is the first leg of the current chain of transfers, and how many transfers it
+    /// has: durations are measured from its first leg. `paid` is what the two legs cost so far.
+    fn cheapest_transfer(
+        &self,
+        legs: &[Leg],
+        choices: &[LegChoice],
+        (chain_start, chain_transfers): (usize, i32),
+        to: usize,
+        (from_paid, to_paid): (f64, f64),
+    ) -> Option<(&'a FareTransferRule, f64)> {
+        let from = to - 1;
+        let from_group = choices[from].0.leg_group_id.as_ref();
+        let to_group = choices[to].0.leg_group_id.as_ref();
+        let rules = &self.fares.fare_transfer_rules;
+        let explicit_from = rules
+            .iter()
+            .any(|r| r.from_leg_group_id.is_some() && r.from_leg_group_id.as_ref() == from_group);
+        let explicit_to = rules
+            .iter()
+            .any(|r| r.to_leg_group_id.is_some() && r.to_leg_group_id.as_ref() == to_group);
+        let matches = |field: Option<&String>, group: Option<&String>, explicit: bool| {
+            if explicit {
+                field == group
+            } else {
+                field.is_none()
+            }
+        };
+
+        let (current, next) = (&legs[chain_start], &legs[to]);
+        rules
+            .iter()
+            .filter(|r| {
+                matches(r.from_leg_group_id.as_ref(), from_group, explicit_from)
+                    && matches(r.to_leg_group_id.as_ref(), to_group, explicit_to)
+            })
+            .filter(|r| {
+                r.transfer_count
+                    .map_or(true, |count| count == -1 || chain_transfers < count)
+            })
+            .filter(|r| {
+                r.duration_limit.map_or(true, |limit| {
+                    let duration = match r.duration_limit_type {
+                        DurationLimitType::DepartureToArrival => next.arrival - current.departure,
+                        DurationLimitType::DepartureToDeparture => {
+                            next.departure - current.departure
+                        }
+                        DurationLimitType::ArrivalToDeparture => next.departure - current.arrival,
+                        DurationLimitType::ArrivalToArrival => next.arrival - current.arrival,
+                    };
+                    duration <= limit
+                })
+            })
+            .filter_map(|r| {
+                let price = match r.fare_product_id {
+                    None => 0.0,
+                    Some(product) => self.product_price(product)?.amount,
+                };
+                // What this transfer costs, compared with paying the legs separately.
+                let extra = match r.fare_transfer_type {
+                    FareTransferType::FirstLegPlusTransfer => price - to_paid,
+                    FareTransferType::BothLegsPlusTransfer => price,
+                    FareTransferType::TransferOnly => price - to_paid - from_paid,
+                };
+                Some((r, price, extra))
+            })
+            .filter(|(_, _, extra)| *extra <= 0.0)
+            .min_by(|(_, _, a), (_, _, b)| a.partial_cmp(b).unwrap())
+            .map(|(r, price, _)| (r, price))
+    }
+}
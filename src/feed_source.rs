@@ -0,0 +1,138 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{Cursor, Error, ErrorKind, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use zip::ZipArchive;
+
+/// Where the `.txt` files of a single GTFS feed are read from.
+///
+/// A feed can be a folder with the extracted files, a `.zip` archive on disk, or a `.zip`
+/// archive already in memory (e.g. just downloaded).
+/// Entries inside an archive can be either at the root or inside a single sub folder.
+#[derive(Clone)]
+pub enum FeedSource {
+    Folder(PathBuf),
+    ZipFile(PathBuf),
+    ZipBytes(Arc<Vec<u8>>),
+}
+
+impl FeedSource {
+    /// Paths ending with `.zip` are considered archives, everything else a folder.
+    pub fn from_path(path: &str) -> FeedSource {
+        let path_buf = PathBuf::from(path);
+        let is_zip = path_buf
+            .extension()
+            .map(|ext| ext.eq_ignore_ascii_case("zip"))
+            .unwrap_or(false);
+        if is_zip {
+            FeedSource::ZipFile(path_buf)
+        } else {
+            FeedSource::Folder(path_buf)
+        }
+    }
+
+    pub fn from_zip_bytes(bytes: Vec<u8>) -> FeedSource {
+        FeedSource::ZipBytes(Arc::new(bytes))
+    }
+
+    /// The path on disk of this feed, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            FeedSource::Folder(p) | FeedSource::ZipFile(p) => Some(p),
+            FeedSource::ZipBytes(_) => None,
+        }
+    }
+
+    /// Returns a reader for `file_name` (e.g. `stops.txt`).
+    /// The error kind is `NotFound` when the feed doesn't contain the file.
+    pub fn open(&self, file_name: &str) -> Result<Box<dyn Read>, Error> {
+        match self {
+            FeedSource::Folder(folder) => Ok(Box::new(File::open(folder.join(file_name))?)),
+            FeedSource::ZipFile(path) => {
+                let content = read_zip_entry(File::open(path)?, file_name)?;
+                Ok(Box::new(Cursor::new(content)))
+            }
+            FeedSource::ZipBytes(bytes) => {
+                let content = read_zip_entry(Cursor::new(bytes.as_slice()), file_name)?;
+                Ok(Box::new(Cursor::new(content)))
+            }
+        }
+    }
+}
+
+impl FeedSource {
+    /// A hash of the content of the feed: of its `.txt` files for a folder, of the whole
+    /// archive otherwise. Used to tell whether a feed changed when it has no `feed_version`.
+    pub fn content_hash(&self) -> Result<u64, Error> {
+        let mut hasher = DefaultHasher::new();
+        match self {
+            FeedSource::Folder(folder) => {
+                let mut files = fs::read_dir(folder)?
+                    .map(|entry| entry.map(|e| e.path()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                files.retain(|p| p.extension().map_or(false, |ext| ext == "txt"));
+                files.sort();
+                for file in files {
+                    hasher.write(
+                        file.file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .as_bytes(),
+                    );
+                    hasher.write(&fs::read(&file)?);
+                }
+            }
+            FeedSource::ZipFile(path) => hasher.write(&fs::read(path)?),
+            FeedSource::ZipBytes(bytes) => hasher.write(bytes),
+        }
+        Ok(hasher.finish())
+    }
+}
+
+impl fmt::Debug for FeedSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedSource::Folder(p) => write!(f, "Folder({})", p.display()),
+            FeedSource::ZipFile(p) => write!(f, "ZipFile({})", p.display()),
+            FeedSource::ZipBytes(b) => write!(f, "ZipBytes({} bytes)", b.len()),
+        }
+    }
+}
+
+impl fmt::Display for FeedSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedSource::Folder(p) | FeedSource::ZipFile(p) => write!(f, "{}", p.display()),
+            FeedSource::ZipBytes(b) => write!(f, "<zip of {} bytes>", b.len()),
+        }
+    }
+}
+
+fn read_zip_entry<R: Read + Seek>(reader: R, file_name: &str) -> Result<Vec<u8>, Error> {
+    let mut archive = ZipArchive::new(reader)?;
+    // Some agencies zip the folder instead of its content.
+    let nested_suffix = format!("/{}", file_name);
+    let entry_name = archive
+        .file_names()
+        .find(|name| *name == file_name)
+        .or_else(|| {
+            archive
+                .file_names()
+                .find(|name| name.ends_with(&nested_suffix) && !name.starts_with("__MACOSX"))
+        })
+        .map(|name| name.to_string())
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("{} not found in archive", file_name),
+            )
+        })?;
+    let mut entry = archive.by_name(&entry_name)?;
+    let mut content = Vec::with_capacity(entry.size() as usize);
+    entry.read_to_end(&mut content)?;
+    Ok(content)
+}
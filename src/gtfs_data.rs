@@ -2,7 +2,8 @@ extern crate flexbuffers;
 extern crate serde;
 
 use cached::{proc_macro::cached, SizedCache};
-use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, TimeZone, Timelike, Utc};
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
 use geo::algorithm::euclidean_distance::EuclideanDistance;
 use geo::algorithm::geodesic_distance::GeodesicDistance;
 use geo::{Coordinate, Point};
@@ -16,11 +17,16 @@ use std::cmp::Ordering;
 use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt;
 use std::fmt::Formatter;
+use std::hash::{Hash, Hasher};
 #[cfg(not(target_arch = "wasm32"))]
 use std::time::SystemTime;
 #[cfg(target_arch = "wasm32")]
 use wasm_timer::SystemTime;
 
+use crate::fares_v2::FaresV2;
+use crate::pathways::{Level, Pathway};
+use crate::service_calendar::ServiceCalendar;
+use crate::translations::{TranslationTable, Translations};
 use crate::wasm_aware_rayon_iterators::{
     IntoParallelIteratorIfPossible, ParallelIteratorIfPossible,
 };
@@ -30,13 +36,41 @@ use crate::wasm_aware_rayon_iterators::{
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct GtfsData {
     pub dataset_id: u32,
+    /// One for each parsed feed, in the order of the sources of the parser.
+    pub feeds: Vec<FeedInfo>,
+    pub agencies: Vec<Agency>,
     pub routes: Vec<Route>,
     pub trips: Vec<Trip>,
     pub shapes: Vec<Shape>,
     pub stops: Vec<Stop>,
     pub services: Vec<Service>,
+    /// The dates on which each of `services` runs. Rebuild it when changing `services`.
+    pub service_calendar: ServiceCalendar,
     pub stop_times: Vec<StopTimes>,
     pub walk_times: Vec<StopWalkTime>,
+    pub transfers: Vec<Transfer>,
+    pub pathways: Vec<Pathway>,
+    pub levels: Vec<Level>,
+    pub fares: Vec<FareAttribute>,
+    pub fares_v2: FaresV2,
+    /// From `translations.txt`. See `Stop::stop_name_in`, `Route::route_long_name_in` and
+    /// `Trip::trip_headsign_in`.
+    pub translations: Translations,
+    /// Index for `lookup_by_source_id`. It is not serialized: rebuild it with
+    /// `index_source_ids` when changing the entities.
+    #[serde(skip)]
+    pub source_ids: HashMap<(u64, SourceIdKind, String), usize>,
+}
+
+/// The entities with an id in the feed, for `GtfsData::lookup_by_source_id`.
+#[derive(Hash, Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum SourceIdKind {
+    Agency,
+    Stop,
+    Route,
+    Trip,
+    Service,
+    Shape,
 }
 
 impl GtfsData {
@@ -52,6 +86,65 @@ impl GtfsData {
         &self.routes[id]
     }
 
+    pub fn get_agency(&self, id: usize) -> &Agency {
+        &self.agencies[id]
+    }
+
+    /// The agency operating `route_id`, if the feed says which one.
+    pub fn get_route_agency(&self, route_id: RouteId) -> Option<&Agency> {
+        self.get_route(route_id)
+            .agency_id
+            .map(|agency_id| self.get_agency(agency_id))
+    }
+
+    /// The timezone of the agency of `route_id`, in which the times of its trips are.
+    /// UTC if the route has no agency.
+    pub fn get_route_timezone(&self, route_id: RouteId) -> Tz {
+        self.get_route_agency(route_id)
+            .map_or(Tz::UTC, |agency| agency.agency_timezone)
+    }
+
+    /// The timezone of the first agency, for the times not related to a route.
+    pub fn get_timezone(&self) -> Tz {
+        self.agencies
+            .first()
+            .map_or(Tz::UTC, |agency| agency.agency_timezone)
+    }
+
+    /// The timezone of the clocks at `stop_id`: its `stop_timezone`, or the one of its routes.
+    pub fn get_stop_timezone(&self, stop_id: StopId) -> Tz {
+        let stop = self.get_stop(stop_id);
+        stop.stop_timezone
+            .or_else(|| {
+                stop.routes
+                    .iter()
+                    .next()
+                    .map(|&route_id| self.get_route_timezone(route_id))
+            })
+            .unwrap_or_else(|| self.get_timezone())
+    }
+
+    /// The feeds that are not valid anymore on the service date of `time`: the data should be
+    /// updated.
+    pub fn expired_feeds(&self, time: &GtfsTime) -> Vec<&FeedInfo> {
+        self.feeds
+            .iter()
+            .filter(|feed| {
+                feed.feed_end_date
+                    .as_ref()
+                    .map_or(false, |end| end.service_date() < time.service_date())
+            })
+            .collect()
+    }
+
+    pub fn get_agency_routes(&self, agency_id: AgencyId) -> Vec<&Route> {
+        self.get_agency(agency_id)
+            .routes
+            .iter()
+            .map(|&route_id| self.get_route(route_id))
+            .collect()
+    }
+
     pub fn get_stop_times(&self, id: usize) -> &StopTimes {
         &self.stop_times[id]
     }
@@ -79,8 +172,73 @@ impl GtfsData {
         &self.shapes[id]
     }
 
+    /// The index of the entity of `kind` with id `source_id` in the feed `dataset_index` (see
+    /// `FeedInfo::dataset_index`). The same id can be used by different feeds.
+    pub fn lookup_by_source_id(
+        &self,
+        dataset_index: u64,
+        kind: SourceIdKind,
+        source_id: &str,
+    ) -> Option<usize> {
+        self.source_ids
+            .get(&(dataset_index, kind, source_id.to_string()))
+            .copied()
+    }
+
+    /// Rebuilds `source_ids` from the entities. When ids are repeated, the first entity is the
+    /// one indexed.
+    pub fn index_source_ids(&mut self) {
+        let agencies = self.agencies.iter().map(|a| {
+            let key = (a.dataset_index, SourceIdKind::Agency, &a.source_id);
+            (key, a.agency_id)
+        });
+        let stops = self.stops.iter().map(|s| {
+            let key = (s.dataset_index, SourceIdKind::Stop, &s.source_id);
+            (key, s.stop_id)
+        });
+        let routes = self.routes.iter().map(|r| {
+            let key = (r.dataset_index, SourceIdKind::Route, &r.source_id);
+            (key, r.route_id)
+        });
+        let trips = self.trips.iter().map(|t| {
+            let key = (t.dataset_index, SourceIdKind::Trip, &t.source_id);
+            (key, t.trip_id)
+        });
+        let services = self.services.iter().map(|s| {
+            let key = (s.dataset_index, SourceIdKind::Service, &s.source_id);
+            (key, s.service_id)
+        });
+        let shapes = self.shapes.iter().map(|s| {
+            let key = (s.dataset_index, SourceIdKind::Shape, &s.source_id);
+            (key, s.shape_id)
+        });
+
+        let mut source_ids = HashMap::new();
+        for ((dataset_index, kind, source_id), inx) in agencies
+            .chain(stops)
+            .chain(routes)
+            .chain(trips)
+            .chain(services)
+            .chain(shapes)
+        {
+            // Synthetic shapes have no id.
+            if !source_id.is_empty() {
+                source_ids
+                    .entry((dataset_index, kind, source_id.clone()))
+                    .or_insert(inx);
+            }
+        }
+        self.source_ids = source_ids;
+    }
+
     /// returns the first trip that has `stop` (with inx after `start_stop_inx`)
     /// after `min_time` (not in excluded_trips).
+    /// Returns the first trip departing from `stop_id` after `min_time`, the index of the stop,
+    /// and the start time of the run (different from `trip.start_time` for frequency based trips).
+    /// The trips of the service days before and after the one of `min_time` are considered too,
+    /// and the start time is relative to the service day of `min_time`: a run starting at
+    /// `25:00:00` on the day before starts at `01:00:00`.
+    /// `trips` must be sorted by start time.
     pub fn trip_after_time(
         &self,
         trips: &[TripId],
@@ -89,54 +247,131 @@ impl GtfsData {
         start_stop_inx: StopIndex,
         stop_times_id: StopTimesId,
         banned_trip_ids: &HashSet<TripId>,
-    ) -> Option<(&Trip, StopIndex)> {
-        // TODO: This function should not use `since_midnight`, otherwise cross day trips are broken.
+    ) -> Option<(&Trip, StopIndex, i64)> {
         if trips.is_empty() || *min_time == GtfsTime::new_infinite() {
             return None;
         }
+        let route_id = self.get_trip(trips[0]).route_id;
+        let min_time = &min_time.in_timezone(self.get_route_timezone(route_id));
         let stop_times = &self.get_stop_times(stop_times_id).stop_times;
-        let trips_duration = stop_times.last().unwrap().time;
+        let trips_duration = stop_times.last().unwrap().arrival_time;
 
         // indexes of the stops in `stop_times` matching `stop_id`
         let inxes_for_stop = stop_times
             .iter()
             .enumerate()
             .skip(start_stop_inx)
-            .filter(|(_inx, stop_time)| stop_time.stop_id == stop_id)
+            .filter(|(_inx, stop_time)| {
+                stop_time.stop_id == stop_id && stop_time.pickup_type.is_available()
+            })
             .map(|(inx, _)| inx)
             .collect::<Vec<usize>>();
-
-        trips
-            .iter()
-            .filter(|t_id| !banned_trip_ids.contains(t_id))
-            .map(|&t_id| self.get_trip(t_id))
-            .filter(|t| {
-                t.stop_times_id == stop_times_id
-                    && self.is_trip_active_on_time(t, min_time, None)
-                    && t.start_time + trips_duration >= min_time.since_midnight() as i64
-            })
-            .find_map(|trip| {
-                // this returns the first for which the content is a Some result.
-                inxes_for_stop
-                    .iter()
-                    .filter(|&&inx| {
-                        stop_times[inx].time + trip.start_time > min_time.since_midnight() as i64
-                    })
-                    .map(|&inx| (trip, inx))
-                    .next()
-            })
+        let first_departure_offset = stop_times[*inxes_for_stop.first()?].departure_time;
+
+        // The first departure of the trips running on `day`, before `before` if given, as
+        // (trip, stop index, run start, departure from the stop). Times are since the start of
+        // `day`.
+        let first_departure_on = |day: &GtfsTime, before: Option<i64>| {
+            let min_time_since_midnight = min_time.timestamp() - day.timestamp();
+            let candidate_trips = trips
+                .iter()
+                .filter(|t_id| !banned_trip_ids.contains(t_id))
+                .map(|&t_id| self.get_trip(t_id))
+                .filter(|t| {
+                    t.stop_times_id == stop_times_id
+                        && t.last_run_start() + trips_duration >= min_time_since_midnight
+                        && self.is_service_active_on_day(t, day)
+                });
+
+            let mut best: Option<(&Trip, StopIndex, i64, i64)> = None;
+            for trip in candidate_trips {
+                let bound = best.map(|(_, _, _, departure)| departure).or(before);
+                // Runs can't start before their trip: the following trips can't do better.
+                if let Some(bound) = bound {
+                    if trip.start_time + first_departure_offset >= bound {
+                        break;
+                    }
+                }
+                // The first stop index for which there is a run departing after `min_time`.
+                let candidate = inxes_for_stop.iter().find_map(|&inx| {
+                    let departure_offset = stop_times[inx].departure_time;
+                    trip.first_run_starting_from(min_time_since_midnight - departure_offset + 1)
+                        .map(|run_start| (trip, inx, run_start, run_start + departure_offset))
+                });
+                if let Some(candidate) = candidate {
+                    if bound.map_or(true, |bound| candidate.3 < bound) {
+                        best = Some(candidate);
+                    }
+                }
+            }
+            best
+        };
+
+        let mut best: Option<(&Trip, StopIndex, i64, i64)> = None;
+        for (day, shift) in service_days_around(min_time) {
+            let before = best.map(|(_, _, _, departure)| departure - shift);
+            if let Some((trip, inx, run_start, departure)) = first_departure_on(&day, before) {
+                best = Some((trip, inx, run_start + shift, departure + shift));
+            }
+        }
+        best.map(|(trip, inx, run_start, _)| (trip, inx, run_start))
     }
 
     // TODO: This is pretty slow. It should be opitimized
+    /// The nearest stop or station to `pos`. Entrances, nodes and boarding areas are skipped.
     pub fn find_nearest_stop(&self, pos: &LatLng) -> &Stop {
         let coord = pos.as_point();
         let item = self
             .stops
             .par_iter_if_possible()
+            .filter(|s| {
+                matches!(
+                    s.location_type,
+                    LocationType::StopOrPlatform | LocationType::Station
+                )
+            })
             .min_by_key(|s| s.stop_pos.distance_meters_to_point(&coord) as i64);
         item.unwrap()
     }
 
+    pub fn get_stop_children(&self, stop_id: StopId) -> Vec<&Stop> {
+        self.get_stop(stop_id)
+            .children
+            .iter()
+            .map(|&child_id| self.get_stop(child_id))
+            .collect()
+    }
+
+    /// The station `stop_id` is in (following `parent_station`), itself if it is a station.
+    pub fn get_station(&self, stop_id: StopId) -> Option<&Stop> {
+        let mut stop = self.get_stop(stop_id);
+        loop {
+            if stop.location_type == LocationType::Station {
+                return Some(stop);
+            }
+            stop = self.get_stop(stop.parent_station?);
+        }
+    }
+
+    /// All the platforms of the station of `stop_id`, that can be used in its place.
+    /// Only `stop_id` if it is not in a station, or the station has no platforms.
+    pub fn get_station_platforms(&self, stop_id: StopId) -> Vec<StopId> {
+        let platforms = match self.get_station(stop_id) {
+            Some(station) => self
+                .get_stop_children(station.stop_id)
+                .into_iter()
+                .filter(|child| child.location_type == LocationType::StopOrPlatform)
+                .map(|child| child.stop_id)
+                .collect(),
+            None => vec![],
+        };
+        if platforms.is_empty() {
+            vec![stop_id]
+        } else {
+            platforms
+        }
+    }
+
     pub fn get_stops_in_range(&self, pos: LatLng, meters: f64) -> Vec<usize> {
         let coord = pos.as_point();
         self.stops
@@ -164,43 +399,45 @@ impl GtfsData {
     /// returns the number of seconds since midnight this trip departs and arrives.
     pub fn get_trip_departure_arrival_times(&self, trip: &Trip) -> (i64, i64) {
         let stop_times = self.get_stop_times(trip.stop_times_id);
-        let trip_duration = stop_times.stop_times.last().unwrap().time;
-        (trip.start_time, trip.start_time + trip_duration)
+        let trip_duration = stop_times.stop_times.last().unwrap().arrival_time;
+        (trip.start_time, trip.last_run_start() + trip_duration)
     }
     /// returns true if this trip is active within `[time, time + within_hours]`,
-    /// taking care of the service and its exceptions
+    /// taking care of the service and its exceptions.
+    /// The trips of the service day before can still be running (e.g. at `25:30:00`), and the
+    /// ones of the day after can start within the window.
     pub fn is_trip_active_on_time(
         &self,
         trip: &Trip,
         time: &GtfsTime,
         within_seconds: Option<i64>,
     ) -> bool {
-        if trip.service_id.is_none() {
-            error!("Trip without service id! {}", trip.trip_short_name);
-            return true;
-        }
         let seconds_in_h = 60 * 60;
         let within_seconds = within_seconds.unwrap_or(24 * seconds_in_h);
-        let target_time = time.since_midnight() as i64;
-
-        // It starts after our window.
-        if trip.start_time > target_time + within_seconds {
-            return false;
-        }
-        let (_, arrival_time) = self.get_trip_departure_arrival_times(trip);
-        // It finishes before our window.
-        if arrival_time < target_time {
-            return false;
-        }
-
-        let service = self.get_service(trip.service_id.unwrap());
+        let time = &time.in_timezone(self.get_route_timezone(trip.route_id));
+        let (departure_time, arrival_time) = self.get_trip_departure_arrival_times(trip);
+
+        service_days_around(time).iter().any(|(day, _)| {
+            let target_time = time.timestamp() - day.timestamp();
+            // It doesn't start after our window, nor finish before it.
+            departure_time <= target_time + within_seconds
+                && arrival_time >= target_time
+                && self.is_service_active_on_day(trip, day)
+        })
+    }
 
-        for exception in &service.exceptions {
-            if exception.date.is_same_day(&time) {
-                return exception.running;
+    /// Whether the service of `trip` runs on the service date of `day` (in its timezone),
+    /// taking care of the exceptions.
+    pub fn is_service_active_on_day(&self, trip: &Trip, day: &GtfsTime) -> bool {
+        let service_id = match trip.service_id {
+            Some(service_id) => service_id,
+            None => {
+                error!("Trip without service id! {}", trip.trip_short_name);
+                return true;
             }
-        }
-        service.days[time.day_of_week() as usize]
+        };
+        self.service_calendar
+            .is_active(service_id, day.service_date())
     }
 
     /// Returns true if `route_id` has at least one actrive trip on `[day, day + within_seconds]`
@@ -317,16 +554,65 @@ impl GtfsData {
         within_sec: i64,
     ) -> Option<(TripId, StopIndex)> {
         let stop_times = &self.get_stop_times(trip.stop_times_id).stop_times;
-        let upper_time = GtfsTime::new_from_timestamp(date.timestamp + within_sec);
-        for &inx in stop_indexes {
-            let time = date.new_replacing_time(stop_times[inx].time + trip.start_time);
-            if date <= time && time <= upper_time {
-                return Some((trip.trip_id, inx));
+        let date = date.in_timezone(self.get_route_timezone(trip.route_id));
+        let upper_time = date.timestamp + within_sec;
+        for (day, _) in service_days_around(&date) {
+            if !self.is_service_active_on_day(trip, &day) {
+                continue;
+            }
+            let since_day_start = date.timestamp - day.timestamp;
+            for &inx in stop_indexes {
+                let departure_offset = stop_times[inx].departure_time;
+                let run_start =
+                    match trip.first_run_starting_from(since_day_start - departure_offset) {
+                        Some(run_start) => run_start,
+                        None => continue,
+                    };
+                if day.timestamp + run_start + departure_offset <= upper_time {
+                    return Some((trip.trip_id, inx));
+                }
             }
         }
         None
     }
 
+    /// For each stop of `stop_times`, the index of its point in the shape `shape_id`.
+    /// See `Shape::stop_point_index`.
+    pub fn get_stop_points_in_shape(&self, shape_id: usize, stop_times: &StopTimes) -> Vec<usize> {
+        let shape = self.get_shape(shape_id);
+        let mut start_from = 0;
+        stop_times
+            .stop_times
+            .iter()
+            .map(|st| {
+                let stop_pos = &self.get_stop(st.stop_id).stop_pos;
+                start_from = shape.stop_point_index(st, stop_pos, start_from);
+                start_from
+            })
+            .collect()
+    }
+
+    /// The points of the shape `shape_id` from the `from_inx`-th to the `to_inx`-th stop of
+    /// `stop_times`.
+    pub fn get_shape_between_stops(
+        &self,
+        shape_id: usize,
+        stop_times: &StopTimes,
+        from_inx: StopIndex,
+        to_inx: StopIndex,
+    ) -> Vec<LatLng> {
+        let shape = self.get_shape(shape_id);
+        let stop_points = self.get_stop_points_in_shape(shape_id, stop_times);
+        let (from_point, to_point) = (stop_points[from_inx], stop_points[to_inx]);
+        if shape.points.is_empty() || from_point >= to_point {
+            debug!("Error in getting shape between two stops");
+            return vec![];
+        }
+        shape.points[from_point..=to_point].to_vec()
+    }
+
+    /// Points of the shape between the nearest ones to `from` and `to`. See
+    /// `get_shape_between_stops`, that works also when the shape passes twice near them.
     pub fn get_shape_between(&self, shape_id: usize, from: &LatLng, to: &LatLng) -> Vec<LatLng> {
         let shape = self.get_shape(shape_id);
         let from_inx = nearest_index(&shape.points, from);
@@ -382,15 +668,94 @@ impl StopTimes {
     }
 }
 
-#[derive(Hash, Eq, PartialEq, Debug, Serialize, Deserialize, Clone)]
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
 pub struct StopTime {
     pub stop_id: usize,
-    pub time: i64, // in seconds
+    /// Seconds after the trip start. Different from `departure_time` when the vehicle dwells.
+    pub arrival_time: i64,
+    pub departure_time: i64,
+    /// `stop_sequence` of the row in `stop_times.txt`. Increasing, but not necessarily consecutive.
+    pub stop_sequence: u32,
+    /// Whether passengers can get on here.
+    pub pickup_type: PickupDropOffType,
+    /// Whether passengers can get off here.
+    pub drop_off_type: PickupDropOffType,
+    /// Distance from the start of the shape of the trip, in the unit of `Shape::dist_traveled`.
+    pub shape_dist_traveled: Option<f64>,
+}
+
+// Distances are never NaN: they are checked by the parser.
+impl Eq for StopTime {}
+
+impl Hash for StopTime {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.stop_id.hash(state);
+        self.arrival_time.hash(state);
+        self.departure_time.hash(state);
+        self.stop_sequence.hash(state);
+        self.pickup_type.hash(state);
+        self.drop_off_type.hash(state);
+        self.shape_dist_traveled.map(f64::to_bits).hash(state);
+    }
+}
+
+/// Values of `pickup_type` and `drop_off_type` in `stop_times.txt`.
+#[derive(Hash, Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum PickupDropOffType {
+    Regular,
+    NotAvailable,
+    /// The passenger must phone the agency to arrange it.
+    PhoneAgency,
+    /// The passenger must coordinate with the driver.
+    CoordinateWithDriver,
+}
+
+impl Default for PickupDropOffType {
+    fn default() -> Self {
+        PickupDropOffType::Regular
+    }
+}
+
+impl PickupDropOffType {
+    /// Empty values are `Regular`, as by specification.
+    pub fn from_gtfs(value: &str) -> Option<PickupDropOffType> {
+        match value.trim() {
+            "" | "0" => Some(PickupDropOffType::Regular),
+            "1" => Some(PickupDropOffType::NotAvailable),
+            "2" => Some(PickupDropOffType::PhoneAgency),
+            "3" => Some(PickupDropOffType::CoordinateWithDriver),
+            _ => None,
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        *self != PickupDropOffType::NotAvailable
+    }
+
+    pub fn to_gtfs(&self) -> u8 {
+        match self {
+            PickupDropOffType::Regular => 0,
+            PickupDropOffType::NotAvailable => 1,
+            PickupDropOffType::PhoneAgency => 2,
+            PickupDropOffType::CoordinateWithDriver => 3,
+        }
+    }
+
+    pub fn needs_call_ahead(&self) -> bool {
+        matches!(
+            self,
+            PickupDropOffType::PhoneAgency | PickupDropOffType::CoordinateWithDriver
+        )
+    }
 }
 
 impl StopTime {
-    pub fn offset_with_trip(&self, trip_start_time_since_midnight: i64) -> i64 {
-        self.time + trip_start_time_since_midnight
+    pub fn arrival_offset_with_trip(&self, trip_start_time_since_midnight: i64) -> i64 {
+        self.arrival_time + trip_start_time_since_midnight
+    }
+
+    pub fn departure_offset_with_trip(&self, trip_start_time_since_midnight: i64) -> i64 {
+        self.departure_time + trip_start_time_since_midnight
     }
 }
 
@@ -414,11 +779,90 @@ impl PartialEq for GtfsData {
 
 impl Eq for GtfsData {}
 
+/// An agency of `agency.txt`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Agency {
+    pub agency_id: usize,
+    /// `agency_id` in the feed. It can be empty.
+    pub source_id: String,
+    pub agency_name: String,
+    pub agency_url: String,
+    pub agency_timezone: Tz,
+    pub agency_lang: String,
+    pub agency_phone: String,
+    pub agency_fare_url: String,
+
+    pub routes: BTreeSet<RouteId>,
+    pub dataset_index: u64,
+}
+
+impl Default for Agency {
+    fn default() -> Self {
+        Agency {
+            agency_id: 0,
+            source_id: Default::default(),
+            agency_name: Default::default(),
+            agency_url: Default::default(),
+            agency_timezone: Tz::UTC,
+            agency_lang: Default::default(),
+            agency_phone: Default::default(),
+            agency_fare_url: Default::default(),
+            routes: Default::default(),
+            dataset_index: 0,
+        }
+    }
+}
+
+/// The `feed_info.txt` of a feed. All the fields are empty if the feed doesn't have it.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FeedInfo {
+    /// The same as `Agency::dataset_index` for the agencies of this feed.
+    pub dataset_index: u64,
+    pub feed_publisher_name: String,
+    pub feed_publisher_url: String,
+    pub feed_lang: String,
+    pub default_lang: String,
+    pub feed_start_date: Option<GtfsTime>,
+    pub feed_end_date: Option<GtfsTime>,
+    pub feed_version: String,
+    pub feed_contact_email: String,
+    pub feed_contact_url: String,
+    /// The `FeedSource::content_hash` of feeds without a `feed_version`, to know when they change.
+    #[serde(default)]
+    pub content_hash: Option<u64>,
+}
+
+impl FeedInfo {
+    /// Whether the service date of `time` is between `feed_start_date` and `feed_end_date`.
+    /// Missing dates don't limit the validity.
+    pub fn is_valid_on(&self, time: &GtfsTime) -> bool {
+        let date = time.service_date();
+        self.feed_start_date
+            .as_ref()
+            .map_or(true, |start| start.service_date() <= date)
+            && self
+                .feed_end_date
+                .as_ref()
+                .map_or(true, |end| date <= end.service_date())
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct Route {
     pub route_id: usize,
+    /// `route_id` in the feed `dataset_index`.
+    pub source_id: String,
+    /// `None` only when the feed has no agencies, or several and the route doesn't say which.
+    pub agency_id: Option<AgencyId>,
     pub route_short_name: String,
     pub route_long_name: String,
+    pub route_desc: String,
+    pub route_type: RouteType,
+    pub route_url: String,
+    /// Hex color of the line, without `#`, e.g. `FFFFFF` (the default).
+    pub route_color: String,
+    /// Hex color of the text drawn on `route_color`, without `#`, e.g. `000000` (the default).
+    pub route_text_color: String,
 
     pub trips: Vec<usize>,
     pub dataset_index: u64,
@@ -426,10 +870,125 @@ pub struct Route {
     pub stop_times: BTreeSet<usize>,
 }
 
+impl Route {
+    /// `route_short_name` in `language`, or the original one if it has no translation.
+    pub fn route_short_name_in<'a>(
+        &'a self,
+        translations: &'a Translations,
+        language: &str,
+    ) -> &'a str {
+        translations.translate(
+            TranslationTable::Routes,
+            "route_short_name",
+            language,
+            self.route_id,
+            &self.route_short_name,
+        )
+    }
+
+    /// `route_long_name` in `language`, or the original one if it has no translation.
+    pub fn route_long_name_in<'a>(
+        &'a self,
+        translations: &'a Translations,
+        language: &str,
+    ) -> &'a str {
+        translations.translate(
+            TranslationTable::Routes,
+            "route_long_name",
+            language,
+            self.route_id,
+            &self.route_long_name,
+        )
+    }
+}
+
+/// Values of `route_type` in `routes.txt`. Extended route types (e.g. `715`, demand and response
+/// bus) are kept as they are, see `RouteType::basic`.
+#[derive(Hash, Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum RouteType {
+    Tram,
+    Subway,
+    Rail,
+    Bus,
+    Ferry,
+    CableTram,
+    AerialLift,
+    Funicular,
+    Trolleybus,
+    Monorail,
+    Extended(u16),
+}
+
+impl Default for RouteType {
+    fn default() -> Self {
+        RouteType::Bus
+    }
+}
+
+impl RouteType {
+    /// Extended route types go from `100` to `1799`.
+    pub fn from_gtfs(value: &str) -> Option<RouteType> {
+        match value.trim().parse::<u16>().ok()? {
+            0 => Some(RouteType::Tram),
+            1 => Some(RouteType::Subway),
+            2 => Some(RouteType::Rail),
+            3 => Some(RouteType::Bus),
+            4 => Some(RouteType::Ferry),
+            5 => Some(RouteType::CableTram),
+            6 => Some(RouteType::AerialLift),
+            7 => Some(RouteType::Funicular),
+            11 => Some(RouteType::Trolleybus),
+            12 => Some(RouteType::Monorail),
+            extended @ 100..=1799 => Some(RouteType::Extended(extended)),
+            _ => None,
+        }
+    }
+
+    pub fn to_gtfs(&self) -> u16 {
+        match self {
+            RouteType::Tram => 0,
+            RouteType::Subway => 1,
+            RouteType::Rail => 2,
+            RouteType::Bus => 3,
+            RouteType::Ferry => 4,
+            RouteType::CableTram => 5,
+            RouteType::AerialLift => 6,
+            RouteType::Funicular => 7,
+            RouteType::Trolleybus => 11,
+            RouteType::Monorail => 12,
+            RouteType::Extended(extended) => *extended,
+        }
+    }
+
+    /// The basic type closest to an extended one, e.g. to choose an icon.
+    /// Extended types without one (air, taxi, miscellaneous) are returned as they are.
+    pub fn basic(&self) -> RouteType {
+        let extended = match self {
+            RouteType::Extended(extended) => *extended,
+            basic => return *basic,
+        };
+        match extended {
+            100..=199 => RouteType::Rail,
+            200..=299 | 700..=799 => RouteType::Bus,
+            400..=402 | 404..=499 => RouteType::Subway,
+            403 => RouteType::Monorail,
+            800..=899 => RouteType::Trolleybus,
+            900..=999 => RouteType::Tram,
+            1000..=1099 | 1200..=1299 => RouteType::Ferry,
+            1300..=1399 => RouteType::AerialLift,
+            1400..=1499 => RouteType::Funicular,
+            _ => *self,
+        }
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct Trip {
     pub route_id: usize,
     pub trip_id: usize,
+    /// `trip_id` in the feed `dataset_index`.
+    pub source_id: String,
+    pub dataset_index: u64,
     pub shape_id: usize,
     /// this points to a vec<StopTime>
     pub stop_times_id: usize,
@@ -442,34 +1001,305 @@ pub struct Trip {
     pub direction_id: String,
     pub block_id: String,
     pub wheelchair_accessible: String,
+
+    /// Headway windows from `frequencies.txt`, for trips without exact times.
+    /// When not empty, the trip runs every `headway_secs` in each window, and `start_time` is the
+    /// start of the first run. The windows are moved like the runs, from the departures from the
+    /// first stop to the trip starts.
+    pub frequencies: Vec<Frequency>,
+}
+
+impl Trip {
+    /// `trip_headsign` in `language`, or the original one if it has no translation.
+    pub fn trip_headsign_in<'a>(
+        &'a self,
+        translations: &'a Translations,
+        language: &str,
+    ) -> &'a str {
+        translations.translate(
+            TranslationTable::Trips,
+            "trip_headsign",
+            language,
+            self.trip_id,
+            &self.trip_headsign,
+        )
+    }
+
+    /// Start (in seconds since midnight) of the first run of this trip starting at or after
+    /// `min_start`, if any.
+    pub fn first_run_starting_from(&self, min_start: i64) -> Option<i64> {
+        if self.frequencies.is_empty() {
+            return if self.start_time >= min_start {
+                Some(self.start_time)
+            } else {
+                None
+            };
+        }
+        self.frequencies.iter().find_map(|f| {
+            let run_start = if min_start <= f.start_time {
+                f.start_time
+            } else {
+                let headways = (min_start - f.start_time + f.headway_secs - 1) / f.headway_secs;
+                f.start_time + headways * f.headway_secs
+            };
+            if run_start < f.end_time {
+                Some(run_start)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Start (in seconds since midnight) of the last run of this trip.
+    pub fn last_run_start(&self) -> i64 {
+        match self.frequencies.last() {
+            None => self.start_time,
+            Some(f) => {
+                f.start_time + (f.end_time - 1 - f.start_time) / f.headway_secs * f.headway_secs
+            }
+        }
+    }
+
+    pub fn is_frequency_based(&self) -> bool {
+        !self.frequencies.is_empty()
+    }
+}
+
+/// A window of `frequencies.txt`: a run starts every `headway_secs` in `[start_time, end_time)`.
+/// Times are in seconds since midnight.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Frequency {
+    pub start_time: i64,
+    pub end_time: i64,
+    pub headway_secs: i64,
+}
+
+/// A row of `transfers.txt`: how passengers can change vehicle from `from_stop_id` to
+/// `to_stop_id`. The optional routes and trips restrict the rule to those vehicles.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Transfer {
+    pub from_stop_id: StopId,
+    pub to_stop_id: StopId,
+    pub from_route_id: Option<RouteId>,
+    pub to_route_id: Option<RouteId>,
+    pub from_trip_id: Option<TripId>,
+    pub to_trip_id: Option<TripId>,
+    pub transfer_type: TransferType,
+    /// Seconds needed to change vehicle, for `TransferType::MinimumTime`.
+    pub min_transfer_time: Option<i64>,
+}
+
+impl Transfer {
+    /// Whether this rule applies when changing from (`from_route_id`, `from_trip_id`) to
+    /// (`to_route_id`, `to_trip_id`). The stops are not checked.
+    pub fn applies_to(
+        &self,
+        from_route_id: RouteId,
+        from_trip_id: TripId,
+        to_route_id: RouteId,
+        to_trip_id: TripId,
+    ) -> bool {
+        let matches = |rule: Option<usize>, value: usize| rule.map_or(true, |r| r == value);
+        matches(self.from_route_id, from_route_id)
+            && matches(self.from_trip_id, from_trip_id)
+            && matches(self.to_route_id, to_route_id)
+            && matches(self.to_trip_id, to_trip_id)
+    }
+
+    /// When several rules apply, the one with the highest specificity wins, as by specification:
+    /// trip to trip, then trip to route, then a single trip, then route to route, then a single
+    /// route, then the stops only.
+    pub fn specificity(&self) -> u8 {
+        let trips = self.from_trip_id.iter().chain(&self.to_trip_id).count() as u8;
+        let routes = self.from_route_id.iter().chain(&self.to_route_id).count() as u8;
+        trips * 3 + routes
+    }
+
+    /// True if the rule is between the two stops, whatever the vehicles.
+    pub fn is_between_stops_only(&self) -> bool {
+        self.specificity() == 0
+    }
+}
+
+/// Values of `transfer_type` in `transfers.txt`.
+#[derive(Hash, Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum TransferType {
+    /// A recommended transfer point between routes.
+    Recommended,
+    /// The departing vehicle waits for the arriving one.
+    Timed,
+    /// The change needs at least `min_transfer_time` seconds.
+    MinimumTime,
+    NotPossible,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize, Clone, Eq, Ord, PartialEq, PartialOrd)]
+impl Default for TransferType {
+    fn default() -> Self {
+        TransferType::Recommended
+    }
+}
+
+impl TransferType {
+    /// Empty values are `Recommended`, as by specification.
+    pub fn from_gtfs(value: &str) -> Option<TransferType> {
+        match value.trim() {
+            "" | "0" => Some(TransferType::Recommended),
+            "1" => Some(TransferType::Timed),
+            "2" => Some(TransferType::MinimumTime),
+            "3" => Some(TransferType::NotPossible),
+            _ => None,
+        }
+    }
+
+    pub fn to_gtfs(&self) -> u8 {
+        match self {
+            TransferType::Recommended => 0,
+            TransferType::Timed => 1,
+            TransferType::MinimumTime => 2,
+            TransferType::NotPossible => 3,
+        }
+    }
+}
+
+/// A fare of `fare_attributes.txt`, with the rows of `fare_rules.txt` saying where it applies.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FareAttribute {
+    pub fare_id: usize,
+    pub price: f64,
+    /// ISO 4217 code of the currency, e.g. "EUR".
+    pub currency_type: String,
+    pub payment_method: PaymentMethod,
+    /// How many times the ticket allows to change vehicle. `None` if unlimited.
+    pub transfers: Option<u32>,
+    /// Seconds the ticket is valid for, from the first departure. `None` if unlimited.
+    pub transfer_duration: Option<i64>,
+    /// When empty, the fare applies to every ride.
+    pub rules: Vec<FareRule>,
+}
+
+/// A row of `fare_rules.txt`. Empty fields match anything.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FareRule {
+    pub route_id: Option<RouteId>,
+    /// `zone_id` of the stop where the passenger gets on.
+    pub origin_id: Option<String>,
+    /// `zone_id` of the stop where the passenger gets off.
+    pub destination_id: Option<String>,
+    /// `zone_id` of a stop passed by the ride.
+    pub contains_id: Option<String>,
+}
+
+/// Values of `payment_method` in `fare_attributes.txt`.
+#[derive(Hash, Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum PaymentMethod {
+    OnBoard,
+    BeforeBoarding,
+}
+
+impl Default for PaymentMethod {
+    fn default() -> Self {
+        PaymentMethod::OnBoard
+    }
+}
+
+impl PaymentMethod {
+    pub fn from_gtfs(value: &str) -> Option<PaymentMethod> {
+        match value.trim() {
+            "0" => Some(PaymentMethod::OnBoard),
+            "1" => Some(PaymentMethod::BeforeBoarding),
+            _ => None,
+        }
+    }
+
+    pub fn to_gtfs(&self) -> u8 {
+        match self {
+            PaymentMethod::OnBoard => 0,
+            PaymentMethod::BeforeBoarding => 1,
+        }
+    }
+}
+
+/// An instant, read in `timezone` as GTFS does: a service date, and the seconds since its
+/// "noon minus 12h" (midnight, except on the days the clocks change).
+/// Times are compared as instants, whatever their timezone.
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GtfsTime {
     timestamp: i64,
+    timezone: Tz,
+}
+
+impl Default for GtfsTime {
+    fn default() -> Self {
+        GtfsTime::new_from_timestamp(0)
+    }
+}
+
+impl PartialEq for GtfsTime {
+    fn eq(&self, other: &Self) -> bool {
+        self.timestamp == other.timestamp
+    }
+}
+
+impl Eq for GtfsTime {}
+
+impl PartialOrd for GtfsTime {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for GtfsTime {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.timestamp.cmp(&other.timestamp)
+    }
 }
 
 impl GtfsTime {
+    /// Today, `time` seconds after midnight, in UTC.
     pub fn new_from_midnight(time: i64) -> GtfsTime {
         let ts = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
             .as_secs() as i64;
-        let sec_since_midnight = Utc.timestamp(ts as i64, 0).num_seconds_from_midnight() as i64;
-        let last_midnight = ts - sec_since_midnight;
+        GtfsTime::new_from_timestamp(ts).new_replacing_time(time)
+    }
 
+    /// `seconds_from_midnight` after the start of the service day `date` in `timezone`, as the
+    /// times of `stop_times.txt`.
+    pub fn from_service_date(
+        date: NaiveDate,
+        seconds_from_midnight: i64,
+        timezone: Tz,
+    ) -> GtfsTime {
         GtfsTime {
-            timestamp: last_midnight + time,
+            timestamp: service_day_start(date, timezone) + seconds_from_midnight,
+            timezone,
         }
     }
 
+    /// Same service day, `seconds_from_midnight` after its start.
     pub fn new_replacing_time(&self, seconds_from_midnight: i64) -> GtfsTime {
+        GtfsTime::from_service_date(self.service_date(), seconds_from_midnight, self.timezone)
+    }
+
+    /// A time in UTC.
+    pub fn new_from_timestamp(timestamp: i64) -> GtfsTime {
         GtfsTime {
-            timestamp: self.timestamp - (self.since_midnight() as i64) + seconds_from_midnight,
+            timestamp,
+            timezone: Tz::UTC,
         }
     }
-    pub fn new_from_timestamp(timestamp: i64) -> GtfsTime {
-        GtfsTime { timestamp }
+
+    /// The same instant, read in `timezone`.
+    pub fn in_timezone(&self, timezone: Tz) -> GtfsTime {
+        GtfsTime {
+            timestamp: self.timestamp,
+            timezone,
+        }
+    }
+
+    pub fn timezone(&self) -> Tz {
+        self.timezone
     }
 
     pub fn new_infinite() -> GtfsTime {
@@ -477,32 +1307,51 @@ impl GtfsTime {
     }
 
     pub fn from_date(yyyymmdd: &str) -> GtfsTime {
-        let date = NaiveDate::parse_from_str(yyyymmdd, "%Y%m%d")
-            .expect(&format!("Invalid date: {}", yyyymmdd));
-        let date = Utc
-            .from_utc_date(&date)
-            .and_time(NaiveTime::from_hms(0, 0, 0))
-            .unwrap();
-        GtfsTime::new_from_timestamp(date.timestamp())
+        GtfsTime::try_from_date(yyyymmdd).expect(&format!("Invalid date: {}", yyyymmdd))
+    }
+
+    /// Same as `from_date`, but returns `None` when the date is not in the `YYYYMMDD` format.
+    /// The date is read in UTC, and its service date is the same whatever the timezone.
+    pub fn try_from_date(yyyymmdd: &str) -> Option<GtfsTime> {
+        let date = NaiveDate::parse_from_str(yyyymmdd.trim(), "%Y%m%d").ok()?;
+        Some(GtfsTime::from_service_date(date, 0, Tz::UTC))
     }
 
+    /// Moves this time to the service date of `other`, keeping the seconds since midnight.
     pub fn set_day_from(&mut self, other: &GtfsTime) {
-        let since_midnight = self.since_midnight();
-        self.timestamp = (other.timestamp as u64 - other.since_midnight() + since_midnight) as i64;
+        *self = GtfsTime::from_service_date(
+            other.service_date(),
+            self.since_midnight() as i64,
+            self.timezone,
+        );
     }
 
     pub fn add_seconds(&mut self, sec: u64) -> &GtfsTime {
         self.timestamp += sec as i64;
         self
     }
-    fn date_time(&self) -> DateTime<Utc> {
-        Utc.timestamp(self.timestamp, 0)
+    pub fn sub_seconds(&mut self, sec: u64) -> &GtfsTime {
+        self.timestamp -= sec as i64;
+        self
+    }
+    fn date_time(&self) -> DateTime<Tz> {
+        self.timezone.timestamp(self.timestamp, 0)
+    }
+    /// The date in `timezone`, or the day before when the time comes before the start of that
+    /// service day (in the hour before the clocks go back).
+    pub fn service_date(&self) -> NaiveDate {
+        let date = self.date_time().naive_local().date();
+        if self.timestamp < service_day_start(date, self.timezone) {
+            date.pred()
+        } else {
+            date
+        }
     }
     pub fn is_same_day(&self, other: &GtfsTime) -> bool {
-        self.date_time().num_days_from_ce() == other.date_time().num_days_from_ce()
+        self.service_date() == other.service_date()
     }
     pub fn day_of_week(&self) -> u32 {
-        self.date_time().weekday().num_days_from_monday()
+        self.service_date().weekday().num_days_from_monday()
     }
     pub fn h(&self) -> u32 {
         self.date_time().hour()
@@ -513,8 +1362,9 @@ impl GtfsTime {
     pub fn s(&self) -> u32 {
         self.date_time().second()
     }
+    /// Seconds since the start of the service day.
     pub fn since_midnight(&self) -> u64 {
-        self.date_time().num_seconds_from_midnight() as u64
+        (self.timestamp - service_day_start(self.service_date(), self.timezone)) as u64
     }
 
     pub fn distance(&self, other: &GtfsTime) -> u64 {
@@ -527,27 +1377,184 @@ impl GtfsTime {
 
 impl fmt::Display for GtfsTime {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        let t = Utc.timestamp(self.timestamp as i64, 0);
-        write!(f, "{}", t.to_rfc2822())
+        write!(f, "{}", self.date_time().to_rfc2822())
     }
 }
 
+/// The starts of the service days before, of, and after the one of `time`, each with how many
+/// seconds it comes after the start of the service day of `time`.
+/// GTFS times go past `24:00:00` for the trips running after midnight, so the trips of three
+/// service days can be running at `time`.
+fn service_days_around(time: &GtfsTime) -> Vec<(GtfsTime, i64)> {
+    let date = time.service_date();
+    let day_start = time.new_replacing_time(0);
+    vec![date.pred(), date, date.succ()]
+        .into_iter()
+        .map(|date| {
+            let start = GtfsTime::from_service_date(date, 0, time.timezone);
+            let shift = start.timestamp - day_start.timestamp;
+            (start, shift)
+        })
+        .collect()
+}
+
+/// The timestamp of "noon minus 12h" of `date` in `timezone`, the start of its service day.
+fn service_day_start(date: NaiveDate, timezone: Tz) -> i64 {
+    let noon = date.and_hms(12, 0, 0);
+    let noon = timezone.from_local_datetime(&noon).earliest().map_or_else(
+        || Utc.from_utc_datetime(&noon).timestamp(),
+        |t| t.timestamp(),
+    );
+    noon - 12 * 60 * 60
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct Shape {
     pub(crate) shape_id: usize,
+    /// `shape_id` in the feed `dataset_index`. Empty for synthetic shapes.
+    pub source_id: String,
+    pub dataset_index: u64,
     pub points: Vec<LatLng>,
+    /// `shape_dist_traveled` of each point. Empty if the feed doesn't give it for all of them.
+    pub dist_traveled: Vec<f64>,
+    /// Not in `shapes.txt`: the straight lines between the stops of trips without a shape.
+    pub synthetic: bool,
+}
+
+impl Shape {
+    /// Index of the point where the vehicle is at `stop_time`, not before `start_from`.
+    /// It is found with `shape_dist_traveled` if both the shape and `stop_time` have it,
+    /// otherwise it is the nearest point to `stop_pos`, that can be wrong when the shape passes
+    /// twice near the stop.
+    pub fn stop_point_index(
+        &self,
+        stop_time: &StopTime,
+        stop_pos: &LatLng,
+        start_from: usize,
+    ) -> usize {
+        if self.points.is_empty() {
+            return 0;
+        }
+        let distances = &self.dist_traveled;
+        match stop_time.shape_dist_traveled {
+            Some(distance) if distances.len() == self.points.len() => {
+                let next = (start_from..distances.len())
+                    .find(|&i| distances[i] >= distance)
+                    .unwrap_or(distances.len() - 1);
+                if next > start_from && distance - distances[next - 1] < distances[next] - distance
+                {
+                    next - 1
+                } else {
+                    next
+                }
+            }
+            _ => self.nearest_point_index(stop_pos, start_from),
+        }
+    }
+
+    fn nearest_point_index(&self, target: &LatLng, start_from: usize) -> usize {
+        let mut best_dist = u64::MAX;
+        let mut best_inx = start_from;
+        for (i, p) in self.points.iter().enumerate().skip(start_from) {
+            let d = p.distance_meters(target);
+            if d < best_dist {
+                best_dist = d;
+                best_inx = i;
+            }
+        }
+        best_inx
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Stop {
     pub stop_id: usize,
+    /// `stop_id` in the feed `dataset_index`.
+    pub source_id: String,
+    pub dataset_index: u64,
+    pub stop_code: String,
     pub stop_name: String,
+    /// Generic nodes and boarding areas without a position have the one of their parent.
     pub stop_pos: LatLng,
-    pub(crate) stop_timezone: String,
+    pub location_type: LocationType,
+    /// The station of platforms, entrances and generic nodes, or the platform of boarding areas.
+    pub parent_station: Option<StopId>,
+    /// The stops having this one as `parent_station`.
+    pub children: BTreeSet<StopId>,
+    pub level_id: Option<usize>,
+    /// Indexes in `GtfsData::pathways` of the pathways from or to this stop.
+    pub pathways: BTreeSet<usize>,
+    pub wheelchair_boarding: String,
+    /// `None` when the stop is in the timezone of its agency. See `GtfsData::get_stop_timezone`.
+    pub stop_timezone: Option<Tz>,
+    /// Fare zone of the stop, used by `fare_rules.txt`. Empty if the stop has none.
+    pub zone_id: String,
 
     pub routes: BTreeSet<usize>,
 }
 
+impl Stop {
+    /// `stop_name` in `language`, or the original one if it has no translation.
+    pub fn stop_name_in<'a>(&'a self, translations: &'a Translations, language: &str) -> &'a str {
+        translations.translate(
+            TranslationTable::Stops,
+            "stop_name",
+            language,
+            self.stop_id,
+            &self.stop_name,
+        )
+    }
+}
+
+/// Values of `location_type` in `stops.txt`.
+#[derive(Hash, Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum LocationType {
+    /// Where vehicles stop. The only type in `stop_times.txt`.
+    StopOrPlatform,
+    /// A group of platforms, e.g. all the piers of "Piazzale Roma".
+    Station,
+    Entrance,
+    /// A node of `pathways.txt` inside a station.
+    GenericNode,
+    /// A part of a platform.
+    BoardingArea,
+}
+
+impl Default for LocationType {
+    fn default() -> Self {
+        LocationType::StopOrPlatform
+    }
+}
+
+impl LocationType {
+    /// Empty values are `StopOrPlatform`, as by specification.
+    pub fn from_gtfs(value: &str) -> Option<LocationType> {
+        match value.trim() {
+            "" | "0" => Some(LocationType::StopOrPlatform),
+            "1" => Some(LocationType::Station),
+            "2" => Some(LocationType::Entrance),
+            "3" => Some(LocationType::GenericNode),
+            "4" => Some(LocationType::BoardingArea),
+            _ => None,
+        }
+    }
+
+    pub fn to_gtfs(&self) -> u8 {
+        match self {
+            LocationType::StopOrPlatform => 0,
+            LocationType::Station => 1,
+            LocationType::Entrance => 2,
+            LocationType::GenericNode => 3,
+            LocationType::BoardingArea => 4,
+        }
+    }
+
+    /// Generic nodes and boarding areas can omit their position.
+    pub fn requires_position(&self) -> bool {
+        !matches!(self, LocationType::GenericNode | LocationType::BoardingArea)
+    }
+}
+
 pub fn to_coordinates(lat: &str, lng: &str) -> LatLng {
     LatLng {
         lat: lat.parse::<f64>().unwrap_or(0.0),
@@ -558,6 +1565,9 @@ pub fn to_coordinates(lat: &str, lng: &str) -> LatLng {
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct Service {
     pub service_id: usize,
+    /// `service_id` in the feed `dataset_index`.
+    pub source_id: String,
+    pub dataset_index: u64,
     pub days: Vec<bool>,
     pub start_date: GtfsTime,
     pub end_date: GtfsTime,
@@ -577,6 +1587,11 @@ pub struct LatLng {
 }
 
 impl LatLng {
+    /// `(0, 0)`, the position of the stops without one.
+    pub fn is_unknown(&self) -> bool {
+        self.lat == 0.0 && self.lng == 0.0
+    }
+
     pub fn as_point(&self) -> Point<f64> {
         Coordinate {
             y: self.lat,
@@ -609,6 +1624,8 @@ pub struct StopDistance {
     pub distance_meters: usize,
 }
 
+pub type AgencyId = usize;
+pub type ServiceId = usize;
 pub type RouteId = usize;
 pub type TripId = usize;
 pub type StopId = usize;
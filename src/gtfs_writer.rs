@@ -0,0 +1,712 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Cursor, Seek, Write};
+use std::path::Path;
+
+use serde::Serialize;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+use crate::gtfs_data::{GtfsData, GtfsTime, LatLng};
+use crate::raw_models::{
+    RawAgency, RawArea, RawFareAttribute, RawFareLegRule, RawFareMedia, RawFareProduct,
+    RawFareRule, RawFareTransferRule, RawFeedInfo, RawFrequency, RawLevel, RawNetwork, RawPathway,
+    RawRoute, RawRouteNetwork, RawService, RawServiceException, RawShape, RawStop, RawStopArea,
+    RawStopTime, RawTransfer, RawTranslation, RawTrip,
+};
+use crate::raw_parser::seconds_to_str_time;
+use crate::translations::TranslationTable;
+
+/// Writes a `GtfsData` as the files of a GTFS feed, e.g. after merging or editing feeds.
+///
+/// The ids of the feeds are kept when they are unique. Ids repeated in several feeds get the
+/// `dataset_index` as suffix (`A1_2`), and the ones still repeated (as the runs of a trip with
+/// exact times in `frequencies.txt`) also their index. Levels, pathways, fares, and the fare
+/// media, products, areas and networks of GTFS-Fares v2 don't keep their ids.
+pub struct GtfsWriter<'a> {
+    dataset: &'a GtfsData,
+    ids: OutputIds,
+}
+
+/// The id written for each entity, by index.
+struct OutputIds {
+    agencies: Vec<String>,
+    routes: Vec<String>,
+    trips: Vec<String>,
+    shapes: Vec<String>,
+    stops: Vec<String>,
+    services: Vec<String>,
+}
+
+impl<'a> GtfsWriter<'a> {
+    pub fn new(dataset: &'a GtfsData) -> GtfsWriter<'a> {
+        let ids = OutputIds {
+            agencies: unique_ids(
+                "agency",
+                dataset
+                    .agencies
+                    .iter()
+                    .map(|a| (a.source_id.as_str(), a.dataset_index)),
+            ),
+            routes: unique_ids(
+                "route",
+                dataset
+                    .routes
+                    .iter()
+                    .map(|r| (r.source_id.as_str(), r.dataset_index)),
+            ),
+            trips: unique_ids(
+                "trip",
+                dataset
+                    .trips
+                    .iter()
+                    .map(|t| (t.source_id.as_str(), t.dataset_index)),
+            ),
+            shapes: unique_ids(
+                "shape",
+                dataset
+                    .shapes
+                    .iter()
+                    .map(|s| (s.source_id.as_str(), s.dataset_index)),
+            ),
+            stops: unique_ids(
+                "stop",
+                dataset
+                    .stops
+                    .iter()
+                    .map(|s| (s.source_id.as_str(), s.dataset_index)),
+            ),
+            services: unique_ids(
+                "service",
+                dataset
+                    .services
+                    .iter()
+                    .map(|s| (s.source_id.as_str(), s.dataset_index)),
+            ),
+        };
+        GtfsWriter { dataset, ids }
+    }
+
+    /// Writes the `.txt` files in `folder`, creating it if needed.
+    pub fn write_to_folder(&self, folder: &Path) -> io::Result<()> {
+        fs::create_dir_all(folder)?;
+        for (name, content) in self.files()? {
+            fs::write(folder.join(name), content)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_to_zip(&self, path: &Path) -> io::Result<()> {
+        self.write_zip(File::create(path)?)
+    }
+
+    /// The content of the zip file, e.g. to give it to `FeedSource::from_zip_bytes`.
+    pub fn to_zip_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut content = vec![];
+        self.write_zip(Cursor::new(&mut content))?;
+        Ok(content)
+    }
+
+    fn write_zip<W: Write + Seek>(&self, output: W) -> io::Result<()> {
+        let mut writer = ZipWriter::new(output);
+        for (name, content) in self.files()? {
+            writer.start_file(name, FileOptions::default())?;
+            writer.write_all(&content)?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Name and content of each file. Files without rows are left out.
+    fn files(&self) -> io::Result<Vec<(&'static str, Vec<u8>)>> {
+        let files = vec![
+            ("agency.txt", to_csv(self.agencies())?),
+            ("stops.txt", to_csv(self.stops())?),
+            ("routes.txt", to_csv(self.routes())?),
+            ("trips.txt", to_csv(self.trips())?),
+            ("stop_times.txt", to_csv(self.stop_times())?),
+            ("calendar.txt", to_csv(self.calendar())?),
+            ("calendar_dates.txt", to_csv(self.calendar_dates())?),
+            ("shapes.txt", to_csv(self.shapes())?),
+            ("frequencies.txt", to_csv(self.frequencies())?),
+            ("transfers.txt", to_csv(self.transfers())?),
+            ("levels.txt", to_csv(self.levels())?),
+            ("pathways.txt", to_csv(self.pathways())?),
+            ("fare_attributes.txt", to_csv(self.fare_attributes())?),
+            ("fare_rules.txt", to_csv(self.fare_rules())?),
+            ("fare_media.txt", to_csv(self.fare_media())?),
+            ("fare_products.txt", to_csv(self.fare_products())?),
+            ("fare_leg_rules.txt", to_csv(self.fare_leg_rules())?),
+            (
+                "fare_transfer_rules.txt",
+                to_csv(self.fare_transfer_rules())?,
+            ),
+            ("areas.txt", to_csv(self.areas())?),
+            ("stop_areas.txt", to_csv(self.stop_areas())?),
+            ("networks.txt", to_csv(self.networks())?),
+            ("route_networks.txt", to_csv(self.route_networks())?),
+            ("translations.txt", to_csv(self.translations())?),
+            ("feed_info.txt", to_csv(self.feed_info())?),
+        ];
+        Ok(files
+            .into_iter()
+            .filter(|(_, content)| !content.is_empty())
+            .collect())
+    }
+
+    fn agencies(&self) -> Vec<RawAgency> {
+        self.dataset
+            .agencies
+            .iter()
+            .map(|a| RawAgency {
+                agency_id: self.ids.agencies[a.agency_id].clone(),
+                agency_name: a.agency_name.clone(),
+                agency_url: a.agency_url.clone(),
+                agency_timezone: a.agency_timezone.name().to_string(),
+                agency_lang: a.agency_lang.clone(),
+                agency_phone: a.agency_phone.clone(),
+                agency_fare_url: a.agency_fare_url.clone(),
+            })
+            .collect()
+    }
+
+    fn stops(&self) -> Vec<RawStop> {
+        self.dataset
+            .stops
+            .iter()
+            .map(|s| {
+                let (stop_lat, stop_lon) = coordinates(&s.stop_pos);
+                RawStop {
+                    stop_id: self.ids.stops[s.stop_id].clone(),
+                    stop_code: s.stop_code.clone(),
+                    stop_name: s.stop_name.clone(),
+                    stop_lat,
+                    stop_lon,
+                    zone_id: s.zone_id.clone(),
+                    location_type: s.location_type.to_gtfs().to_string(),
+                    parent_station: optional_id(&self.ids.stops, s.parent_station),
+                    stop_timezone: s
+                        .stop_timezone
+                        .map(|tz| tz.name().to_string())
+                        .unwrap_or_default(),
+                    wheelchair_boarding: s.wheelchair_boarding.clone(),
+                    level_id: s.level_id.map(level_id).unwrap_or_default(),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    fn routes(&self) -> Vec<RawRoute> {
+        self.dataset
+            .routes
+            .iter()
+            .map(|r| RawRoute {
+                route_id: self.ids.routes[r.route_id].clone(),
+                agency_id: optional_id(&self.ids.agencies, r.agency_id),
+                route_short_name: r.route_short_name.clone(),
+                route_long_name: r.route_long_name.clone(),
+                route_desc: r.route_desc.clone(),
+                route_type: r.route_type.to_gtfs().to_string(),
+                route_url: r.route_url.clone(),
+                route_color: r.route_color.clone(),
+                route_text_color: r.route_text_color.clone(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn trips(&self) -> Vec<RawTrip> {
+        self.dataset
+            .trips
+            .iter()
+            .map(|t| RawTrip {
+                route_id: self.ids.routes[t.route_id].clone(),
+                service_id: optional_id(&self.ids.services, t.service_id),
+                trip_id: self.ids.trips[t.trip_id].clone(),
+                trip_headsign: t.trip_headsign.clone(),
+                trip_short_name: t.trip_short_name.clone(),
+                direction_id: t.direction_id.clone(),
+                block_id: t.block_id.clone(),
+                shape_id: optional_id(&self.ids.shapes, self.written_shape(t.shape_id)),
+                wheelchair_accessible: t.wheelchair_accessible.clone(),
+            })
+            .collect()
+    }
+
+    /// Synthetic shapes are not written: they are created again when parsing.
+    fn written_shape(&self, shape_id: usize) -> Option<usize> {
+        self.dataset
+            .shapes
+            .get(shape_id)
+            .filter(|shape| !shape.synthetic)
+            .map(|_| shape_id)
+    }
+
+    /// The times of each trip are the ones of its `StopTimes` after `Trip::start_time`.
+    fn stop_times(&self) -> Vec<RawStopTime> {
+        let mut rows = vec![];
+        for trip in &self.dataset.trips {
+            let stop_times = self.dataset.get_stop_times(trip.stop_times_id);
+            for st in &stop_times.stop_times {
+                rows.push(RawStopTime {
+                    trip_id: self.ids.trips[trip.trip_id].clone(),
+                    arrival_time: seconds_to_str_time(st.arrival_offset_with_trip(trip.start_time)),
+                    departure_time: seconds_to_str_time(
+                        st.departure_offset_with_trip(trip.start_time),
+                    ),
+                    stop_id: self.ids.stops[st.stop_id].clone(),
+                    stop_sequence: st.stop_sequence.to_string(),
+                    pickup_type: st.pickup_type.to_gtfs().to_string(),
+                    drop_off_type: st.drop_off_type.to_gtfs().to_string(),
+                    shape_dist_traveled: st
+                        .shape_dist_traveled
+                        .map(|d| d.to_string())
+                        .unwrap_or_default(),
+                    ..Default::default()
+                });
+            }
+        }
+        rows
+    }
+
+    /// Services running on no day of the week are only in `calendar_dates.txt`, unless they have
+    /// no exceptions either.
+    fn calendar(&self) -> Vec<RawService> {
+        let day = |days: &Vec<bool>, inx: usize| {
+            let running = days.get(inx).copied().unwrap_or(false);
+            if running { "1" } else { "0" }.to_string()
+        };
+        self.dataset
+            .services
+            .iter()
+            .filter(|s| s.days.iter().any(|&d| d) || s.exceptions.is_empty())
+            .map(|s| RawService {
+                service_id: self.ids.services[s.service_id].clone(),
+                monday: day(&s.days, 0),
+                tuesday: day(&s.days, 1),
+                wednesday: day(&s.days, 2),
+                thursday: day(&s.days, 3),
+                friday: day(&s.days, 4),
+                saturday: day(&s.days, 5),
+                sunday: day(&s.days, 6),
+                start_date: date(&s.start_date),
+                end_date: date(&s.end_date),
+            })
+            .collect()
+    }
+
+    fn calendar_dates(&self) -> Vec<RawServiceException> {
+        let mut rows = vec![];
+        for service in &self.dataset.services {
+            for exception in &service.exceptions {
+                rows.push(RawServiceException {
+                    service_id: self.ids.services[service.service_id].clone(),
+                    date: date(&exception.date),
+                    exception_type: if exception.running { "1" } else { "2" }.to_string(),
+                });
+            }
+        }
+        rows
+    }
+
+    fn shapes(&self) -> Vec<RawShape> {
+        let mut rows = vec![];
+        for shape in self.dataset.shapes.iter().filter(|s| !s.synthetic) {
+            for (inx, point) in shape.points.iter().enumerate() {
+                let (shape_pt_lat, shape_pt_lon) = coordinates(point);
+                rows.push(RawShape {
+                    shape_id: self.ids.shapes[shape.shape_id].clone(),
+                    shape_pt_lat,
+                    shape_pt_lon,
+                    shape_pt_sequence: inx.to_string(),
+                    shape_dist_traveled: shape
+                        .dist_traveled
+                        .get(inx)
+                        .map(|d| d.to_string())
+                        .unwrap_or_default(),
+                });
+            }
+        }
+        rows
+    }
+
+    /// Only the headway windows: trips with exact times are written as one trip per run.
+    fn frequencies(&self) -> Vec<RawFrequency> {
+        let mut rows = vec![];
+        for trip in &self.dataset.trips {
+            // The windows are of the trip starts, `frequencies.txt` has the departures from the
+            // first stop.
+            let first_departure = self
+                .dataset
+                .get_stop_times(trip.stop_times_id)
+                .stop_times
+                .first()
+                .map_or(0, |st| st.departure_time);
+            for frequency in &trip.frequencies {
+                rows.push(RawFrequency {
+                    trip_id: self.ids.trips[trip.trip_id].clone(),
+                    start_time: seconds_to_str_time(frequency.start_time + first_departure),
+                    end_time: seconds_to_str_time(frequency.end_time + first_departure),
+                    headway_secs: frequency.headway_secs.to_string(),
+                    exact_times: "0".to_string(),
+                });
+            }
+        }
+        rows
+    }
+
+    fn transfers(&self) -> Vec<RawTransfer> {
+        self.dataset
+            .transfers
+            .iter()
+            .map(|t| RawTransfer {
+                from_stop_id: self.ids.stops[t.from_stop_id].clone(),
+                to_stop_id: self.ids.stops[t.to_stop_id].clone(),
+                from_route_id: optional_id(&self.ids.routes, t.from_route_id),
+                to_route_id: optional_id(&self.ids.routes, t.to_route_id),
+                from_trip_id: optional_id(&self.ids.trips, t.from_trip_id),
+                to_trip_id: optional_id(&self.ids.trips, t.to_trip_id),
+                transfer_type: t.transfer_type.to_gtfs().to_string(),
+                min_transfer_time: t
+                    .min_transfer_time
+                    .map(|m| m.to_string())
+                    .unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    fn levels(&self) -> Vec<RawLevel> {
+        self.dataset
+            .levels
+            .iter()
+            .map(|l| RawLevel {
+                level_id: level_id(l.level_id),
+                level_index: l.level_index.to_string(),
+                level_name: l.level_name.clone(),
+            })
+            .collect()
+    }
+
+    fn pathways(&self) -> Vec<RawPathway> {
+        self.dataset
+            .pathways
+            .iter()
+            .map(|p| RawPathway {
+                pathway_id: format!("pathway_{}", p.pathway_id),
+                from_stop_id: self.ids.stops[p.from_stop_id].clone(),
+                to_stop_id: self.ids.stops[p.to_stop_id].clone(),
+                pathway_mode: p.pathway_mode.to_gtfs().to_string(),
+                is_bidirectional: if p.is_bidirectional { "1" } else { "0" }.to_string(),
+                length: p.length.map(|l| l.to_string()).unwrap_or_default(),
+                traversal_time: p.traversal_time.map(|t| t.to_string()).unwrap_or_default(),
+                stair_count: p.stair_count.map(|c| c.to_string()).unwrap_or_default(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn fare_attributes(&self) -> Vec<RawFareAttribute> {
+        self.dataset
+            .fares
+            .iter()
+            .map(|f| RawFareAttribute {
+                fare_id: fare_id(f.fare_id),
+                price: f.price.to_string(),
+                currency_type: f.currency_type.clone(),
+                payment_method: f.payment_method.to_gtfs().to_string(),
+                transfers: optional_value(f.transfers),
+                transfer_duration: optional_value(f.transfer_duration),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn fare_rules(&self) -> Vec<RawFareRule> {
+        let mut rows = vec![];
+        for fare in &self.dataset.fares {
+            for rule in &fare.rules {
+                rows.push(RawFareRule {
+                    fare_id: fare_id(fare.fare_id),
+                    route_id: optional_id(&self.ids.routes, rule.route_id),
+                    origin_id: rule.origin_id.clone().unwrap_or_default(),
+                    destination_id: rule.destination_id.clone().unwrap_or_default(),
+                    contains_id: rule.contains_id.clone().unwrap_or_default(),
+                });
+            }
+        }
+        rows
+    }
+
+    fn fare_media(&self) -> Vec<RawFareMedia> {
+        self.dataset
+            .fares_v2
+            .fare_media
+            .iter()
+            .map(|m| RawFareMedia {
+                fare_media_id: fare_media_id(m.fare_media_id),
+                fare_media_name: m.fare_media_name.clone(),
+                fare_media_type: m.fare_media_type.to_gtfs().to_string(),
+            })
+            .collect()
+    }
+
+    /// A row for each price of the products.
+    fn fare_products(&self) -> Vec<RawFareProduct> {
+        let mut rows = vec![];
+        for product in &self.dataset.fares_v2.fare_products {
+            for price in &product.prices {
+                rows.push(RawFareProduct {
+                    fare_product_id: fare_product_id(product.fare_product_id),
+                    fare_product_name: product.fare_product_name.clone(),
+                    rider_category_id: price.rider_category_id.clone(),
+                    fare_media_id: price.fare_media_id.map(fare_media_id).unwrap_or_default(),
+                    amount: price.amount.to_string(),
+                    currency: price.currency.clone(),
+                });
+            }
+        }
+        rows
+    }
+
+    fn fare_leg_rules(&self) -> Vec<RawFareLegRule> {
+        self.dataset
+            .fares_v2
+            .fare_leg_rules
+            .iter()
+            .map(|r| RawFareLegRule {
+                leg_group_id: r.leg_group_id.clone().unwrap_or_default(),
+                network_id: r.network_id.map(network_id).unwrap_or_default(),
+                from_area_id: r.from_area_id.map(area_id).unwrap_or_default(),
+                to_area_id: r.to_area_id.map(area_id).unwrap_or_default(),
+                fare_product_id: fare_product_id(r.fare_product_id),
+                rule_priority: optional_value(r.rule_priority),
+            })
+            .collect()
+    }
+
+    fn fare_transfer_rules(&self) -> Vec<RawFareTransferRule> {
+        self.dataset
+            .fares_v2
+            .fare_transfer_rules
+            .iter()
+            .map(|r| RawFareTransferRule {
+                from_leg_group_id: r.from_leg_group_id.clone().unwrap_or_default(),
+                to_leg_group_id: r.to_leg_group_id.clone().unwrap_or_default(),
+                transfer_count: optional_value(r.transfer_count),
+                duration_limit: optional_value(r.duration_limit),
+                duration_limit_type: match r.duration_limit {
+                    Some(_) => r.duration_limit_type.to_gtfs().to_string(),
+                    None => String::new(),
+                },
+                fare_transfer_type: r.fare_transfer_type.to_gtfs().to_string(),
+                fare_product_id: r.fare_product_id.map(fare_product_id).unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    fn areas(&self) -> Vec<RawArea> {
+        self.dataset
+            .fares_v2
+            .areas
+            .iter()
+            .map(|a| RawArea {
+                area_id: area_id(a.area_id),
+                area_name: a.area_name.clone(),
+            })
+            .collect()
+    }
+
+    fn stop_areas(&self) -> Vec<RawStopArea> {
+        let mut rows = vec![];
+        for area in &self.dataset.fares_v2.areas {
+            for &stop_id in &area.stop_ids {
+                rows.push(RawStopArea {
+                    area_id: area_id(area.area_id),
+                    stop_id: self.ids.stops[stop_id].clone(),
+                });
+            }
+        }
+        rows
+    }
+
+    fn networks(&self) -> Vec<RawNetwork> {
+        self.dataset
+            .fares_v2
+            .networks
+            .iter()
+            .map(|n| RawNetwork {
+                network_id: network_id(n.network_id),
+                network_name: n.network_name.clone(),
+            })
+            .collect()
+    }
+
+    /// Also the networks given in `routes.txt` are written here.
+    fn route_networks(&self) -> Vec<RawRouteNetwork> {
+        let mut rows = vec![];
+        for network in &self.dataset.fares_v2.networks {
+            for &route_id in &network.route_ids {
+                rows.push(RawRouteNetwork {
+                    network_id: network_id(network.network_id),
+                    route_id: self.ids.routes[route_id].clone(),
+                });
+            }
+        }
+        rows
+    }
+
+    /// The translations of `feed_info.txt` are written only for the feed written.
+    fn translations(&self) -> Vec<RawTranslation> {
+        let written_feed = self.written_feed();
+        self.dataset
+            .translations
+            .iter()
+            .filter_map(|(key, translation)| {
+                let record_id = match (key.table, key.record_id) {
+                    (_, None) => String::new(),
+                    (TranslationTable::FeedInfo, feed) if feed == written_feed => String::new(),
+                    (TranslationTable::FeedInfo, _) => return None,
+                    (TranslationTable::Agency, Some(id)) => self.ids.agencies[id].clone(),
+                    (TranslationTable::Stops, Some(id)) => self.ids.stops[id].clone(),
+                    (TranslationTable::Routes, Some(id)) => self.ids.routes[id].clone(),
+                    (TranslationTable::Trips, Some(id)) => self.ids.trips[id].clone(),
+                    (TranslationTable::Levels, Some(id)) => level_id(id),
+                    // Not kept by the parser.
+                    (TranslationTable::StopTimes, Some(_)) => return None,
+                };
+                Some(RawTranslation {
+                    table_name: key.table.to_gtfs().to_string(),
+                    field_name: key.field_name.clone(),
+                    language: key.language.clone(),
+                    translation: translation.clone(),
+                    record_id,
+                    record_sub_id: String::new(),
+                    field_value: key.field_value.clone().unwrap_or_default(),
+                })
+            })
+            .collect()
+    }
+
+    /// The index of the first feed with a `feed_info.txt`.
+    fn written_feed(&self) -> Option<usize> {
+        self.dataset
+            .feeds
+            .iter()
+            .position(|f| !f.feed_publisher_name.is_empty())
+    }
+
+    /// `feed_info.txt` has a single row: the first feed having one is written.
+    fn feed_info(&self) -> Vec<RawFeedInfo> {
+        self.written_feed()
+            .map(|inx| &self.dataset.feeds[inx])
+            .map(|f| RawFeedInfo {
+                feed_publisher_name: f.feed_publisher_name.clone(),
+                feed_publisher_url: f.feed_publisher_url.clone(),
+                feed_lang: f.feed_lang.clone(),
+                default_lang: f.default_lang.clone(),
+                feed_start_date: f.feed_start_date.as_ref().map(date).unwrap_or_default(),
+                feed_end_date: f.feed_end_date.as_ref().map(date).unwrap_or_default(),
+                feed_version: f.feed_version.clone(),
+                feed_contact_email: f.feed_contact_email.clone(),
+                feed_contact_url: f.feed_contact_url.clone(),
+            })
+            .into_iter()
+            .collect()
+    }
+}
+
+/// The source ids, with the `dataset_index` when repeated, and then also the index.
+/// Empty ids become `{prefix}_{index}`. An id made this way that is already taken (e.g. by a
+/// source id `A1_2`) gets one more `_{n}`.
+fn unique_ids<'a>(prefix: &str, source_ids: impl Iterator<Item = (&'a str, u64)>) -> Vec<String> {
+    let source_ids = source_ids.collect::<Vec<_>>();
+    let mut id_count: HashMap<&str, usize> = HashMap::new();
+    let mut id_in_feed_count: HashMap<(&str, u64), usize> = HashMap::new();
+    for &(id, dataset_index) in &source_ids {
+        *id_count.entry(id).or_default() += 1;
+        *id_in_feed_count.entry((id, dataset_index)).or_default() += 1;
+    }
+    let is_kept = |id: &str| !id.is_empty() && id_count[id] == 1;
+    let mut taken: HashSet<String> = source_ids
+        .iter()
+        .map(|&(id, _)| id)
+        .filter(|&id| is_kept(id))
+        .map(|id| id.to_string())
+        .collect();
+    source_ids
+        .iter()
+        .enumerate()
+        .map(|(inx, &(id, dataset_index))| {
+            if is_kept(id) {
+                return id.to_string();
+            }
+            let candidate = if id.is_empty() {
+                format!("{}_{}", prefix, inx)
+            } else if id_in_feed_count[&(id, dataset_index)] == 1 {
+                format!("{}_{}", id, dataset_index)
+            } else {
+                format!("{}_{}_{}", id, dataset_index, inx)
+            };
+            let unique = std::iter::once(candidate.clone())
+                .chain((1..).map(|n| format!("{}_{}", candidate, n)))
+                .find(|id| !taken.contains(id))
+                .unwrap();
+            taken.insert(unique.clone());
+            unique
+        })
+        .collect()
+}
+
+fn optional_id(ids: &[String], inx: Option<usize>) -> String {
+    inx.map(|inx| ids[inx].clone()).unwrap_or_default()
+}
+
+fn optional_value<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn level_id(level_id: usize) -> String {
+    format!("level_{}", level_id)
+}
+
+fn fare_id(fare_id: usize) -> String {
+    format!("fare_{}", fare_id)
+}
+
+fn fare_media_id(fare_media_id: usize) -> String {
+    format!("fare_media_{}", fare_media_id)
+}
+
+fn fare_product_id(fare_product_id: usize) -> String {
+    format!("fare_product_{}", fare_product_id)
+}
+
+fn area_id(area_id: usize) -> String {
+    format!("area_{}", area_id)
+}
+
+fn network_id(network_id: usize) -> String {
+    format!("network_{}", network_id)
+}
+
+/// Empty for unknown positions.
+fn coordinates(pos: &LatLng) -> (String, String) {
+    if pos.is_unknown() {
+        return Default::default();
+    }
+    (pos.lat.to_string(), pos.lng.to_string())
+}
+
+fn date(time: &GtfsTime) -> String {
+    time.service_date().format("%Y%m%d").to_string()
+}
+
+/// Empty when there are no rows, as the header is written with the first one.
+fn to_csv<T: Serialize>(rows: Vec<T>) -> io::Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.into_inner().map_err(|e| e.into_error())
+}
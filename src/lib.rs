@@ -1,9 +1,20 @@
+pub mod fares;
+pub mod fares_v2;
+pub mod feed_source;
 pub mod gtfs_data;
+pub mod gtfs_writer;
 pub mod navigator;
 pub mod navigator_models;
+pub mod parse_error;
+pub mod pathways;
 pub mod raw_models;
 pub mod raw_parser;
 pub mod realtime_position;
+pub mod service_calendar;
+pub mod stop_merger;
+pub mod subset;
 pub mod test_utils;
 pub mod timetable;
+pub mod translations;
+pub mod validation;
 mod wasm_aware_rayon_iterators;
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::cmp::{max, min};
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::iter::FromIterator;
@@ -9,12 +10,14 @@ use log::{debug, error, info, trace};
 #[cfg(not(target_arch = "wasm32"))]
 use rayon::iter::ParallelIterator;
 
+use crate::fares::calculate_fare;
 use crate::gtfs_data::{
-    GtfsData, GtfsTime, LatLng, RouteId, Stop, StopDistance, StopId, StopIndex, StopTimesId, Trip,
-    TripId,
+    GtfsData, GtfsTime, LatLng, RouteId, Stop, StopDistance, StopId, StopIndex, StopTimesId,
+    Transfer, TransferType, Trip, TripId,
 };
 use crate::navigator_models::SolutionComponent::Bus;
 use crate::navigator_models::{NavigationParams, Solution, SolutionComponent, TimeUpdate};
+use crate::pathways::platform_walk_times;
 use crate::wasm_aware_rayon_iterators::{
     IntoParallelIteratorIfPossible, ParallelIteratorIfPossible,
 };
@@ -36,6 +39,8 @@ pub struct RaptorNavigator<'a> {
 
     start_stop: Stop,
     end_stop: Stop,
+    /// The platforms of the station of `start_stop`: the navigation starts from all of them.
+    start_stops: Vec<StopId>,
 
     /// v[stop_id] -> is within X m to destination? (check X in code)
     stops_near_destination_map: HashSet<StopId>,
@@ -76,6 +81,9 @@ pub struct RaptorNavigator<'a> {
     /// (In this way, we can find several different solutions).
     banned_trip_ids: HashSet<TripId>,
 
+    /// Rules of `transfers.txt`, by (from_stop_id, to_stop_id).
+    transfers: HashMap<(StopId, StopId), Vec<&'a Transfer>>,
+
     dataset: &'a GtfsData,
 }
 
@@ -86,10 +94,39 @@ type Round = u8;
 /// This is used to walk between stops when we change bus
 const NEAR_STOP_NUMBER: usize = 30;
 
+/// A run of a trip, as found by `GtfsData::trip_after_time`.
+#[derive(Debug, Clone)]
+struct Run {
+    trip_id: TripId,
+    /// Seconds after `service_day`. It differs from the trip start for frequency based trips,
+    /// and it is after `24:00:00` for a run of the day before `service_day`.
+    start_time: i64,
+    /// Start of the service day of the time the run was searched from, in the timezone of the
+    /// route. It can be a day after the one of the navigation start.
+    service_day: GtfsTime,
+}
+
+impl Run {
+    /// The time `seconds_since_start` after the start of the run.
+    fn time(&self, seconds_since_start: i64) -> GtfsTime {
+        self.service_day
+            .new_replacing_time(self.start_time + seconds_since_start)
+    }
+}
+
+/// The same run, even when found from different service days.
+impl PartialEq for Run {
+    fn eq(&self, other: &Self) -> bool {
+        self.trip_id == other.trip_id && self.time(0) == other.time(0)
+    }
+}
+
 /// All the `Option` fields are `None` for a walk path
 #[derive(Debug, Default)]
 pub struct BacktrackingInfo {
     trip_id: Option<TripId>,
+    /// The run of `trip_id` used.
+    run: Option<Run>,
     route_id: Option<RouteId>,
     /// we departed by this stop id to reach this new stop
     from_stop_id: StopId,
@@ -99,6 +136,14 @@ pub struct BacktrackingInfo {
     distance: Option<u64>, // meters
 }
 
+/// Where and when we got off a vehicle, before changing to another one.
+struct TransferStart {
+    stop_id: StopId,
+    route_id: RouteId,
+    trip_id: TripId,
+    time: GtfsTime,
+}
+
 impl BacktrackingInfo {
     fn is_walking_path(&self) -> bool {
         self.trip_id.is_none()
@@ -107,6 +152,7 @@ impl BacktrackingInfo {
     fn new_walking_info(from_stop_id: StopId, distance: u64) -> BacktrackingInfo {
         BacktrackingInfo {
             trip_id: None,
+            run: None,
             route_id: None,
             from_stop_id,
             from_stop_inx: None,
@@ -122,6 +168,7 @@ impl<'a> RaptorNavigator<'a> {
             dataset,
             start_stop: Default::default(),
             end_stop: Default::default(),
+            start_stops: vec![],
             stops_near_destination_list: Default::default(),
             stops_near_destination_map: Default::default(),
             navigation_params: Default::default(),
@@ -136,6 +183,11 @@ impl<'a> RaptorNavigator<'a> {
             on_solution_found,
             banned_trip_ids: Default::default(),
             active_trips: Default::default(),
+            transfers: dataset
+                .transfers
+                .iter()
+                .map(|t| ((t.from_stop_id, t.to_stop_id), t))
+                .into_group_map(),
         }
     }
 
@@ -154,6 +206,7 @@ impl<'a> RaptorNavigator<'a> {
             .collect::<Vec<&Stop>>();
         self.start_stop = start_end_stops[0].clone();
         self.end_stop = start_end_stops[1].clone();
+        self.start_stops = self.dataset.get_station_platforms(self.start_stop.stop_id);
 
         self.compute_trips_active_today(&params.start_time);
         self.compute_stops_near_destination(self.end_stop.stop_pos.clone());
@@ -175,7 +228,10 @@ impl<'a> RaptorNavigator<'a> {
     }
 
     fn compute_stops_near_destination(&mut self, pos: LatLng) {
-        self.stops_near_destination_list = self.dataset.get_stops_in_range(pos, 100.0);
+        let mut stops = self.dataset.get_stops_in_range(pos, 100.0);
+        // Any platform of the destination station will do.
+        stops.extend(self.dataset.get_station_platforms(self.end_stop.stop_id));
+        self.stops_near_destination_list = stops.into_iter().unique().collect();
         self.stops_near_destination_map =
             self.stops_near_destination_list.iter().copied().collect();
     }
@@ -221,12 +277,10 @@ impl<'a> RaptorNavigator<'a> {
 
     /// Does `navigation_params.max_changes` passes
     fn navigate(&mut self) {
-        self.update_best(
-            self.start_stop.stop_id,
-            0,
-            self.navigation_params.start_time.clone(),
-        );
-        self.marked_stops.push(self.start_stop.stop_id);
+        for stop_id in self.start_stops.clone() {
+            self.update_best(stop_id, 0, self.navigation_params.start_time.clone());
+            self.marked_stops.push(stop_id);
+        }
         self.add_walking_path(0);
 
         for hop_att in 1..=self.navigation_params.max_changes + 1 {
@@ -364,7 +418,8 @@ impl<'a> RaptorNavigator<'a> {
         // maximizes the waiting between precedent and next bus change max(arrival_in_stop - prec_best_time)
         let mut time_delta_change = 0;
         //debug!("Considering route  {} {}", &_route.route_short_name, &_route.route_long_name);
-        let mut trip: Option<usize> = None;
+        // The run of the trip we are on.
+        let mut trip: Option<Run> = None;
         let mut _curr_loop_stop_inx = start_stop_inx;
 
         let mut updates = vec![];
@@ -382,14 +437,18 @@ impl<'a> RaptorNavigator<'a> {
 
             let mut curr_time = GtfsTime::new_infinite();
 
-            if let Some(trip_id) = trip {
+            if let Some(run) = &trip {
                 // We already have a trip set.
-                let curr_trip = self.dataset.get_trip(trip_id);
                 let prec_best = self.best_stop_time(curr_stop_id);
 
-                curr_time = self.new_time(curr_stop_time.offset_with_trip(curr_trip.start_time));
+                // We get off at arrival.
+                curr_time = run.time(curr_stop_time.arrival_time);
 
-                if self.only_best && self.best_destination_time < curr_time {
+                if curr_stop_inx == start_stop_inx {
+                    // We just got on here. With a dwell, the arrival is before our boarding time.
+                } else if !curr_stop_time.drop_off_type.is_available() {
+                    // We can't get off here.
+                } else if self.only_best && self.best_destination_time < curr_time {
                     // this solution can't improve the best one.
                 } else if curr_time < prec_best {
                     debug_assert_ne!(start_stop_id, curr_stop_id);
@@ -397,7 +456,8 @@ impl<'a> RaptorNavigator<'a> {
                         to_stop_id: curr_stop.stop_id,
                         destination_time: curr_time.clone(),
                         backtrack_info: BacktrackingInfo {
-                            trip_id: Some(trip_id),
+                            trip_id: Some(run.trip_id),
+                            run: Some(run.clone()),
                             route_id: Some(route_id),
                             from_stop_id: start_stop_id,
                             from_stop_inx: Some(start_stop_inx),
@@ -421,20 +481,20 @@ impl<'a> RaptorNavigator<'a> {
                     continue;
                 }
                 // Let's get the next trip passing here, respecting all conditions.
-                let new_trip: Option<(&Trip, StopIndex)> = self.dataset.trip_after_time(
-                    self.active_trips.get(&route_id).unwrap(),
+                let new_trip: Option<(&Trip, StopIndex, Run)> = self.next_trip(
+                    route_id,
+                    stop_times_id,
                     curr_stop_id,
-                    &prec_time,
                     curr_stop_inx,
-                    stop_times_id,
-                    &self.banned_trip_ids,
+                    &prec_time,
+                    hop_att,
                 );
 
                 if new_trip.is_none() {
                     continue;
                 }
 
-                let (new_trip, new_trip_stop_inx) = new_trip.unwrap();
+                let (new_trip, new_trip_stop_inx, new_run) = new_trip.unwrap();
                 debug_assert!(
                     !self.banned_trip_ids.contains(&new_trip.trip_id),
                     "Returned a trip in the banned list!"
@@ -444,13 +504,11 @@ impl<'a> RaptorNavigator<'a> {
                 // this means that it was for us impossible to get into a bus in curr_stop_inx,
                 // this because self.dataset.trip_after_time scans in increasing stop index.
                 let trip_stop_inx = max(curr_stop_inx, new_trip_stop_inx);
-                let arriving_time_new_trip =
-                    self.new_time(new_trip.start_time + stop_times[trip_stop_inx].time);
-                let arriving_time_old_trip = match trip {
+                // We get on at departure, so we can still catch a trip that is dwelling here.
+                let arriving_time_new_trip = new_run.time(stop_times[trip_stop_inx].departure_time);
+                let arriving_time_old_trip = match &trip {
                     None => GtfsTime::new_infinite(),
-                    Some(trip) => self.new_time(
-                        self.dataset.get_trip(trip).start_time + stop_times[curr_stop_inx].time,
-                    ),
+                    Some(run) => run.time(stop_times[curr_stop_inx].departure_time),
                 };
 
                 // pruning if we are only looking for the best
@@ -467,7 +525,7 @@ impl<'a> RaptorNavigator<'a> {
                     );
 
                     // let's get into this new trip
-                    trip = Some(new_trip.trip_id);
+                    trip = Some(new_run);
                     _curr_loop_stop_inx = trip_stop_inx;
 
                     start_stop_inx = trip_stop_inx;
@@ -475,7 +533,7 @@ impl<'a> RaptorNavigator<'a> {
                     debug_assert_eq!(stop_times[curr_stop_inx].stop_id, start_stop_id);
 
                     time_delta_change = arriving_time_new_trip.distance(&prec_time);
-                } else if new_trip.trip_id == trip.unwrap() {
+                } else if trip == Some(new_run) {
                     // This is the same trip, but maybe it is better to take it at this stop?
                     // It all depends on the delta between the precedent arrival at this stop,
                     // and when this trip passes there
@@ -491,10 +549,147 @@ impl<'a> RaptorNavigator<'a> {
         updates
     }
 
-    fn new_time(&self, seconds_since_midnight: i64) -> GtfsTime {
-        self.navigation_params
-            .start_time
-            .new_replacing_time(seconds_since_midnight)
+    /// Same as `GtfsData::trip_after_time`, but the trip must also respect the rules of
+    /// `transfers.txt` for changing from the vehicle we used to reach `stop_id` in the previous hop.
+    fn next_trip(
+        &self,
+        route_id: RouteId,
+        stop_times_id: StopTimesId,
+        stop_id: StopId,
+        start_stop_inx: StopIndex,
+        prec_time: &GtfsTime,
+        hop_att: Round,
+    ) -> Option<(&'a Trip, StopIndex, Run)> {
+        let trips = self.active_trips.get(&route_id)?;
+        let stop_times = &self.dataset.get_stop_times(stop_times_id).stop_times;
+        let transfer_start = self.transfer_start(stop_id, hop_att - 1);
+
+        let mut min_time = prec_time.clone();
+        // Trips we can't change to are banned only for this search.
+        let mut banned = Cow::Borrowed(&self.banned_trip_ids);
+        loop {
+            let (trip, stop_inx, run_start) = self.dataset.trip_after_time(
+                trips,
+                stop_id,
+                &min_time,
+                start_stop_inx,
+                stop_times_id,
+                &banned,
+            )?;
+            let run = Run {
+                trip_id: trip.trip_id,
+                start_time: run_start,
+                service_day: self.service_day(route_id, &min_time),
+            };
+            let from = match &transfer_start {
+                Some(from) => from,
+                None => return Some((trip, stop_inx, run)),
+            };
+            let rule = match self.transfer_rule(from, stop_id, route_id, trip.trip_id) {
+                Some(rule) => rule,
+                None => return Some((trip, stop_inx, run)),
+            };
+            // A rule without `to_trip_id` holds for the later trips too, unless some of them
+            // have a more specific one.
+            let holds_for_later_trips =
+                rule.to_trip_id.is_none() && !self.has_trip_rules(from, stop_id, route_id);
+            match (rule.transfer_type, rule.min_transfer_time) {
+                (TransferType::NotPossible, _) => {
+                    if holds_for_later_trips {
+                        // No trip of this route can be reached from here.
+                        return None;
+                    }
+                    banned.to_mut().insert(trip.trip_id);
+                }
+                (TransferType::MinimumTime, Some(min_transfer_time)) => {
+                    let earliest = from.time.timestamp() + min_transfer_time;
+                    let departure = run.time(stop_times[stop_inx].departure_time);
+                    if departure.timestamp() >= earliest {
+                        return Some((trip, stop_inx, run));
+                    }
+                    if holds_for_later_trips {
+                        // `trip_after_time` looks for departures strictly after `min_time`.
+                        min_time = GtfsTime::new_from_timestamp(earliest - 1);
+                    } else {
+                        banned.to_mut().insert(trip.trip_id);
+                    }
+                }
+                _ => return Some((trip, stop_inx, run)),
+            }
+        }
+    }
+
+    /// The vehicle we got off to reach `stop_id` at `hop`, directly or walking from another
+    /// stop. `None` if we are not changing vehicle (e.g. at the start of the navigation).
+    fn transfer_start(&self, stop_id: StopId, hop: Round) -> Option<TransferStart> {
+        let mut info = self.p.get(&(stop_id, hop))?;
+        let mut from_stop_id = stop_id;
+        if info.is_walking_path() {
+            from_stop_id = info.from_stop_id;
+            info = self.p.get(&(from_stop_id, hop))?;
+        }
+        Some(TransferStart {
+            stop_id: from_stop_id,
+            route_id: info.route_id?,
+            trip_id: info.trip_id?,
+            time: self.stop_time(from_stop_id, hop),
+        })
+    }
+
+    /// The most specific rule of `transfers.txt` for changing from `from` to `to_trip_id`.
+    fn transfer_rule(
+        &self,
+        from: &TransferStart,
+        to_stop_id: StopId,
+        to_route_id: RouteId,
+        to_trip_id: TripId,
+    ) -> Option<&'a Transfer> {
+        self.transfers
+            .get(&(from.stop_id, to_stop_id))?
+            .iter()
+            .filter(|t| t.applies_to(from.route_id, from.trip_id, to_route_id, to_trip_id))
+            .max_by_key(|t| t.specificity())
+            .copied()
+    }
+
+    /// Whether some rule of `transfers.txt` for changing from `from` is about specific trips of
+    /// `to_route_id`.
+    fn has_trip_rules(
+        &self,
+        from: &TransferStart,
+        to_stop_id: StopId,
+        to_route_id: RouteId,
+    ) -> bool {
+        self.transfers
+            .get(&(from.stop_id, to_stop_id))
+            .map_or(false, |rules| {
+                rules.iter().any(|t| {
+                    t.to_trip_id.map_or(false, |to_trip_id| {
+                        self.dataset.get_trip(to_trip_id).route_id == to_route_id
+                            && t.applies_to(from.route_id, from.trip_id, to_route_id, to_trip_id)
+                    })
+                })
+            })
+    }
+
+    /// The rule of `transfers.txt` between two stops that holds for any vehicle.
+    fn stops_transfer_rule(
+        &self,
+        from_stop_id: StopId,
+        to_stop_id: StopId,
+    ) -> Option<&'a Transfer> {
+        self.transfers
+            .get(&(from_stop_id, to_stop_id))?
+            .iter()
+            .find(|t| t.is_between_stops_only())
+            .copied()
+    }
+
+    /// The start of the service day of `time` for the trips of `route_id`: the runs found by
+    /// `GtfsData::trip_after_time` from `time` start relative to it.
+    fn service_day(&self, route_id: RouteId, time: &GtfsTime) -> GtfsTime {
+        time.in_timezone(self.dataset.get_route_timezone(route_id))
+            .new_replacing_time(0)
     }
 
     fn update_best(&mut self, stop_id: usize, hop_att: Round, new_time: GtfsTime) {
@@ -591,12 +786,49 @@ impl<'a> RaptorNavigator<'a> {
                 &precalculated_near_stops.near_stops
             };
 
+        // Inside stations, the pathways give the time to reach the other platforms.
+        let avoid_stairs = self.navigation_params.avoid_stairs;
+        let pathway_times = platform_walk_times(ds, from_stop_id, avoid_stairs);
+        let platforms_by_pathway = pathway_times
+            .keys()
+            .filter(|&&to| near_stops_with_distance.iter().all(|sd| sd.stop_id != to))
+            .map(|&to| StopDistance {
+                stop_id: to,
+                distance_meters: from_stop
+                    .stop_pos
+                    .distance_meters(&ds.get_stop(to).stop_pos)
+                    as usize,
+            })
+            .sorted_by_key(|sd| sd.stop_id)
+            .collect_vec();
+        let from_station = ds.get_station(from_stop_id).map(|s| s.stop_id);
+
         near_stops_with_distance
             .iter()
+            .chain(&platforms_by_pathway)
             .filter(|sd| sd.distance_meters < 10000) //nobody wants to walk for 10 km
             .filter_map(|sd| {
                 let to_stop_id = sd.stop_id;
-                let cost = RaptorNavigator::seconds_by_walk(sd.distance_meters);
+                let mut cost = match pathway_times.get(&to_stop_id) {
+                    Some(&seconds) => seconds as u64,
+                    // Only the pathways are known to be without stairs.
+                    None if avoid_stairs
+                        && !from_stop.pathways.is_empty()
+                        && from_station.is_some()
+                        && ds.get_station(to_stop_id).map(|s| s.stop_id) == from_station =>
+                    {
+                        return None
+                    }
+                    None => RaptorNavigator::seconds_by_walk(sd.distance_meters),
+                };
+                match self.stops_transfer_rule(from_stop_id, to_stop_id) {
+                    Some(rule) if rule.transfer_type == TransferType::NotPossible => return None,
+                    Some(rule) if rule.transfer_type == TransferType::MinimumTime => {
+                        let min_transfer_time = rule.min_transfer_time.unwrap_or(0) as u64;
+                        cost = max(cost, min_transfer_time);
+                    }
+                    _ => {}
+                }
                 let destination_time = from_stop_best_time.clone().add_seconds(cost).clone();
                 let prec_best_destination_time = tbest
                     .get(&(to_stop_id))
@@ -638,12 +870,11 @@ impl<'a> RaptorNavigator<'a> {
 
         let mut att_stop = stop_id;
         let mut att_kth = hop_att;
-        let start_stop = self.start_stop.stop_id;
 
         let mut upper_time = self.t.get(&(stop_id, hop_att)).unwrap().clone();
 
         // we reconstruct the solution from the last component to the first
-        while att_stop != start_stop {
+        while !self.start_stops.contains(&att_stop) {
             let _entry = (att_stop, att_kth);
             debug_assert!(self.p.contains_key(&_entry));
             let backtrack_info = self.p.get(&(att_stop, att_kth)).unwrap_or_else(|| {
@@ -667,20 +898,23 @@ impl<'a> RaptorNavigator<'a> {
             }
 
             let prec_trip_id = backtrack_info.trip_id.unwrap();
-            let prec_trip = self.dataset.get_trip(prec_trip_id);
+            // The run we took: for frequency based trips, the start time changes.
+            let run = backtrack_info.run.as_ref().unwrap();
+            let mut prec_trip = self.dataset.get_trip(prec_trip_id).clone();
+            prec_trip.start_time = run.start_time;
             let prec_route_id = backtrack_info.route_id.unwrap();
             let prec_route = self.dataset.get_route(prec_route_id);
             let path = self.dataset.get_stop_times(prec_trip.stop_times_id);
 
             // Additional check
             let start_inx = backtrack_info.from_stop_inx.unwrap();
-            let time_at_start_inx =
-                self.new_time(path.stop_times[start_inx].time + prec_trip.start_time);
+            let time_at_start_inx = run.time(path.stop_times[start_inx].departure_time);
 
             solution.add_bus_path(
                 att_stop,
                 prec_route,
                 prec_trip,
+                run.service_day.clone(),
                 path,
                 backtrack_info.from_stop_inx.unwrap(),
                 backtrack_info.to_stop_index.unwrap(),
@@ -698,8 +932,9 @@ impl<'a> RaptorNavigator<'a> {
             att_stop = prec_stop;
             att_kth -= 1;
         }
-        solution.set_last_component_start(self.start_stop.stop_id);
+        solution.set_last_component_start(att_stop);
         solution.complete(&self.dataset);
+        solution.fare = calculate_fare(self.dataset, &solution);
 
         solution
     }
@@ -2,6 +2,7 @@ use std::fmt;
 
 use serde::{Deserialize, Serialize};
 
+use crate::fares::SolutionFare;
 use crate::gtfs_data::{GtfsData, GtfsTime, LatLng, Route, StopId, StopTimes, Trip};
 use crate::navigator::{BacktrackingInfo, RaptorNavigator};
 use crate::navigator_models::SolutionComponent::{Bus, Walk};
@@ -13,12 +14,16 @@ pub struct NavigationParams {
     pub max_changes: u8,
     pub start_time: GtfsTime,
     pub num_solutions_to_find: u8,
+    /// Inside stations with `pathways.txt`, change platform without using stairs.
+    pub avoid_stairs: bool,
 }
 
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct Solution {
     pub navigation_start_time: GtfsTime,
     pub components: Vec<SolutionComponent>,
+    /// `None` if the dataset has no fares, or they don't cover some bus component.
+    pub fare: Option<SolutionFare>,
 }
 
 impl fmt::Display for Solution {
@@ -68,18 +73,23 @@ impl Solution {
         &mut self,
         stop_id: usize,
         route: &Route,
-        trip: &Trip,
+        trip: Trip,
+        service_day: GtfsTime,
         path: &StopTimes,
         from_inx: usize,
         to_inx: usize,
     ) {
+        let call_ahead = path.stop_times[from_inx].pickup_type.needs_call_ahead()
+            || path.stop_times[to_inx].drop_off_type.needs_call_ahead();
         let component = BusSolutionComponent {
             route: route.clone(),
-            trip: trip.clone(),
+            trip,
             path: path.clone(),
             shape: Default::default(),
             from_inx,
             to_inx,
+            call_ahead,
+            service_day,
         };
         self.set_last_component_start(stop_id);
         self.components.push(SolutionComponent::Bus(component));
@@ -93,20 +103,15 @@ impl Solution {
     fn compute_bus_shapes(&mut self, dataset: &GtfsData) {
         for component in &mut self.components {
             if let Bus(b) = component {
-                let from_stop = dataset.get_stop(b.path.stop_times[b.from_inx].stop_id);
-                let to_stop = dataset.get_stop(b.path.stop_times[b.to_inx].stop_id);
-                b.shape = dataset.get_shape_between(
-                    b.trip.shape_id,
-                    &from_stop.stop_pos,
-                    &to_stop.stop_pos,
-                )
+                b.shape =
+                    dataset.get_shape_between_stops(b.trip.shape_id, &b.path, b.from_inx, b.to_inx)
             }
         }
     }
 
     pub fn start_time(&self) -> GtfsTime {
         if self.components.is_empty() {
-            return GtfsTime::new_from_midnight(self.navigation_start_time.since_midnight() as i64);
+            return self.navigation_start_time.clone();
         }
 
         let mut walk_time = 0;
@@ -116,18 +121,18 @@ impl Solution {
                     walk_time += RaptorNavigator::seconds_by_walk(w.distance);
                 }
                 SolutionComponent::Bus(bus) => {
-                    return GtfsTime::new_from_midnight(
-                        (bus.departure_time().since_midnight() - walk_time) as i64,
-                    );
+                    let mut start_time = bus.departure_time();
+                    start_time.sub_seconds(walk_time);
+                    return start_time;
                 }
             }
         }
         // Only 1 walk path.
-        GtfsTime::new_from_midnight(self.navigation_start_time.since_midnight() as i64)
+        self.navigation_start_time.clone()
     }
     pub fn end_time(&self) -> GtfsTime {
         if self.components.is_empty() {
-            return GtfsTime::new_from_midnight(self.navigation_start_time.since_midnight() as i64);
+            return self.navigation_start_time.clone();
         }
 
         let mut walk_time = 0;
@@ -137,21 +142,46 @@ impl Solution {
                     walk_time += RaptorNavigator::seconds_by_walk(w.distance);
                 }
                 SolutionComponent::Bus(bus) => {
-                    return GtfsTime::new_from_midnight(
-                        (bus.arrival_time().since_midnight() + walk_time) as i64,
-                    );
+                    let mut end_time = bus.arrival_time();
+                    end_time.add_seconds(walk_time);
+                    return end_time;
                 }
             }
         }
 
         // Only walk paths.
-        GtfsTime::new_from_midnight(
-            (self.navigation_start_time.since_midnight() + walk_time) as i64,
-        )
+        let mut end_time = self.navigation_start_time.clone();
+        end_time.add_seconds(walk_time);
+        end_time
     }
     pub fn duration_seconds(&self) -> usize {
         self.start_time().distance(&self.end_time()) as usize
     }
+
+    /// Replaces the route names and the headsigns of the bus components with the ones in
+    /// `language`, when `translations.txt` has them.
+    pub fn translate(&mut self, dataset: &GtfsData, language: &str) {
+        let translations = &dataset.translations;
+        for component in &mut self.components {
+            if let Bus(b) = component {
+                let route = dataset.get_route(b.route.route_id);
+                b.route.route_short_name = route.route_short_name_in(translations, language).into();
+                b.route.route_long_name = route.route_long_name_in(translations, language).into();
+                b.trip.trip_headsign = dataset
+                    .get_trip(b.trip.trip_id)
+                    .trip_headsign_in(translations, language)
+                    .into();
+            }
+        }
+    }
+
+    /// True if the passenger has to phone the agency or talk with the driver for any bus leg.
+    pub fn needs_call_ahead(&self) -> bool {
+        self.components.iter().any(|c| match c {
+            Bus(b) => b.call_ahead,
+            Walk(_) => false,
+        })
+    }
 }
 
 //noinspection RsExternalLinter
@@ -189,6 +219,7 @@ impl fmt::Display for SolutionComponent {
 
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct BusSolutionComponent {
+    /// With the type and the colors to draw the leg.
     pub route: Route,
     pub trip: Trip,
     pub path: StopTimes,
@@ -196,15 +227,26 @@ pub struct BusSolutionComponent {
     pub from_inx: usize,
     pub to_inx: usize,
     pub shape: Vec<LatLng>,
+    /// Getting on or off requires to contact the agency or the driver in advance.
+    pub call_ahead: bool,
+    /// The start of a service day, in the timezone of the agency of the trip. `trip.start_time` is
+    /// relative to it: a run of the day before at `25:00:00` starts at `01:00:00`, and a run of
+    /// the day after at `00:30:00` starts at `24:30:00`. Legs after midnight can have the service
+    /// day after the one of the navigation start.
+    pub service_day: GtfsTime,
 }
 
 impl BusSolutionComponent {
     pub fn departure_time(&self) -> GtfsTime {
-        GtfsTime::new_from_midnight(self.trip.start_time + self.path.stop_times[self.from_inx].time)
+        self.service_day.new_replacing_time(
+            self.path.stop_times[self.from_inx].departure_offset_with_trip(self.trip.start_time),
+        )
     }
 
     pub fn arrival_time(&self) -> GtfsTime {
-        GtfsTime::new_from_midnight(self.trip.start_time + self.path.stop_times[self.to_inx].time)
+        self.service_day.new_replacing_time(
+            self.path.stop_times[self.to_inx].arrival_offset_with_trip(self.trip.start_time),
+        )
     }
 }
 
@@ -0,0 +1,71 @@
+use std::error::Error;
+use std::fmt;
+
+/// Error returned by `RawParser::try_parse`.
+/// It points to the feed, the file and (when available) the line and the field that caused it.
+/// In lenient mode the same errors are collected in `RawParser::warnings`, and the rows skipped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// The feed the file comes from (a folder, a zip path, or an in memory zip).
+    pub feed: String,
+    pub file: String,
+    /// 1-based line number inside `file`, header included.
+    pub line: Option<u64>,
+    pub field: Option<String>,
+    pub kind: ParseErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    MissingFile,
+    EmptyFile,
+    /// The row (or the file) can't be read as csv.
+    Malformed(String),
+    /// The value of `field` can't be converted (e.g. a time that is not `HH:MM:SS`).
+    InvalidValue(String),
+    /// The value of `field` is an id that is not defined in the feed.
+    UnknownReference(String),
+}
+
+impl ParseError {
+    pub fn new(feed: &str, file: &str, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            feed: feed.to_string(),
+            file: file.to_string(),
+            line: None,
+            field: None,
+            kind,
+        }
+    }
+
+    pub fn at_line(mut self, line: u64) -> ParseError {
+        self.line = Some(line);
+        self
+    }
+
+    pub fn with_field(mut self, field: &str) -> ParseError {
+        self.field = Some(field.to_string());
+        self
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.feed, self.file)?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        if let Some(field) = &self.field {
+            write!(f, " field `{}`", field)?;
+        }
+        match &self.kind {
+            ParseErrorKind::MissingFile => write!(f, ": file not found"),
+            ParseErrorKind::EmptyFile => write!(f, ": file is empty"),
+            ParseErrorKind::Malformed(e) => write!(f, ": malformed content ({})", e),
+            ParseErrorKind::InvalidValue(v) => write!(f, ": invalid value `{}`", v),
+            ParseErrorKind::UnknownReference(v) => write!(f, ": unknown id `{}`", v),
+        }
+    }
+}
+
+impl Error for ParseError {}
@@ -0,0 +1,159 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::gtfs_data::{GtfsData, LocationType, StopId};
+
+/// A row of `levels.txt`: a floor of a station.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct Level {
+    pub level_id: usize,
+    /// Position of the level: 0 is the ground, negative values are underground.
+    pub level_index: f64,
+    pub level_name: String,
+}
+
+/// A row of `pathways.txt`: a way between two stops of the same station.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct Pathway {
+    pub pathway_id: usize,
+    pub from_stop_id: StopId,
+    pub to_stop_id: StopId,
+    pub pathway_mode: PathwayMode,
+    /// When false, it can be used only from `from_stop_id` to `to_stop_id`.
+    pub is_bidirectional: bool,
+    /// Meters.
+    pub length: Option<f64>,
+    /// Seconds to walk it.
+    pub traversal_time: Option<i64>,
+    /// Positive when going up from `from_stop_id` to `to_stop_id`.
+    pub stair_count: Option<i32>,
+}
+
+/// Values of `pathway_mode` in `pathways.txt`.
+#[derive(Hash, Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum PathwayMode {
+    Walkway,
+    Stairs,
+    MovingSidewalk,
+    Escalator,
+    Elevator,
+    /// Where the ticket is checked or bought.
+    FareGate,
+    ExitGate,
+}
+
+impl Default for PathwayMode {
+    fn default() -> Self {
+        PathwayMode::Walkway
+    }
+}
+
+impl PathwayMode {
+    pub fn from_gtfs(value: &str) -> Option<PathwayMode> {
+        match value.trim() {
+            "1" => Some(PathwayMode::Walkway),
+            "2" => Some(PathwayMode::Stairs),
+            "3" => Some(PathwayMode::MovingSidewalk),
+            "4" => Some(PathwayMode::Escalator),
+            "5" => Some(PathwayMode::Elevator),
+            "6" => Some(PathwayMode::FareGate),
+            "7" => Some(PathwayMode::ExitGate),
+            _ => None,
+        }
+    }
+
+    pub fn to_gtfs(&self) -> u8 {
+        match self {
+            PathwayMode::Walkway => 1,
+            PathwayMode::Stairs => 2,
+            PathwayMode::MovingSidewalk => 3,
+            PathwayMode::Escalator => 4,
+            PathwayMode::Elevator => 5,
+            PathwayMode::FareGate => 6,
+            PathwayMode::ExitGate => 7,
+        }
+    }
+}
+
+/// Seconds to walk a pathway with neither `traversal_time` nor `length`.
+const DEFAULT_TRAVERSAL_TIME: i64 = 60;
+
+impl Pathway {
+    pub fn is_elevator(&self) -> bool {
+        self.pathway_mode == PathwayMode::Elevator
+    }
+
+    pub fn is_escalator(&self) -> bool {
+        self.pathway_mode == PathwayMode::Escalator
+    }
+
+    pub fn has_stairs(&self) -> bool {
+        self.pathway_mode == PathwayMode::Stairs || self.stair_count.map_or(false, |c| c != 0)
+    }
+
+    /// `traversal_time`, or the time to walk `length` at 1 m/s.
+    pub fn traversal_seconds(&self) -> i64 {
+        self.traversal_time
+            .or_else(|| self.length.map(|length| length.ceil() as i64))
+            .unwrap_or(DEFAULT_TRAVERSAL_TIME)
+    }
+
+    /// The stop reached from `stop_id` through this pathway, if it can be used in that direction.
+    pub fn other_end(&self, stop_id: StopId) -> Option<StopId> {
+        if self.from_stop_id == stop_id {
+            Some(self.to_stop_id)
+        } else if self.to_stop_id == stop_id && self.is_bidirectional {
+            Some(self.from_stop_id)
+        } else {
+            None
+        }
+    }
+}
+
+/// Seconds to walk from `from_stop_id` to the platforms reachable through `pathways.txt`,
+/// following the fastest pathways. When `avoid_stairs` is true, pathways with stairs are not used.
+/// Empty if `from_stop_id` has no pathways.
+pub fn platform_walk_times(
+    dataset: &GtfsData,
+    from_stop_id: StopId,
+    avoid_stairs: bool,
+) -> HashMap<StopId, i64> {
+    if dataset.get_stop(from_stop_id).pathways.is_empty() {
+        return HashMap::new();
+    }
+    // Dijkstra on the graph of the pathways of the station.
+    let mut times: HashMap<StopId, i64> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    times.insert(from_stop_id, 0);
+    queue.push(Reverse((0, from_stop_id)));
+    while let Some(Reverse((time, stop_id))) = queue.pop() {
+        if times.get(&stop_id).map_or(false, |&best| best < time) {
+            continue;
+        }
+        for &pathway_id in &dataset.get_stop(stop_id).pathways {
+            let pathway = &dataset.pathways[pathway_id];
+            if avoid_stairs && pathway.has_stairs() {
+                continue;
+            }
+            let next_stop_id = match pathway.other_end(stop_id) {
+                Some(next_stop_id) => next_stop_id,
+                None => continue,
+            };
+            let next_time = time + pathway.traversal_seconds();
+            if times
+                .get(&next_stop_id)
+                .map_or(true, |&best| next_time < best)
+            {
+                times.insert(next_stop_id, next_time);
+                queue.push(Reverse((next_time, next_stop_id)));
+            }
+        }
+    }
+    times.remove(&from_stop_id);
+    times.retain(|&stop_id, _| {
+        dataset.get_stop(stop_id).location_type == LocationType::StopOrPlatform
+    });
+    times
+}
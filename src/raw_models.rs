@@ -1,10 +1,12 @@
 use std::fs::File;
-use std::io::Error;
+use std::io::{Error, Read};
 use std::path::Path;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Default)]
+use crate::feed_source::FeedSource;
+
+#[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(default)]
 pub struct RawAgency {
     pub agency_id: String,
@@ -16,6 +18,20 @@ pub struct RawAgency {
     pub agency_fare_url: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RawFeedInfo {
+    pub feed_publisher_name: String,
+    pub feed_publisher_url: String,
+    pub feed_lang: String,
+    pub default_lang: String,
+    pub feed_start_date: String,
+    pub feed_end_date: String,
+    pub feed_version: String,
+    pub feed_contact_email: String,
+    pub feed_contact_url: String,
+}
+
 #[derive(Debug, Deserialize, Default)]
 #[serde(default)]
 pub struct RawCalendar {
@@ -39,7 +55,7 @@ pub struct RawCalendarDates {
     pub exception_type: String,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(default)]
 pub struct RawRoute {
     pub route_id: String,
@@ -51,9 +67,10 @@ pub struct RawRoute {
     pub route_url: String,
     pub route_color: String,
     pub route_text_color: String,
+    pub network_id: String,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(default)]
 pub struct RawShape {
     pub shape_id: String,
@@ -63,7 +80,7 @@ pub struct RawShape {
     pub shape_dist_traveled: String,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(default)]
 pub struct RawStopTime {
     pub trip_id: String,
@@ -77,7 +94,7 @@ pub struct RawStopTime {
     pub shape_dist_traveled: String,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(default)]
 pub struct RawStop {
     pub stop_id: String,
@@ -92,9 +109,10 @@ pub struct RawStop {
     pub parent_station: String,
     pub stop_timezone: String,
     pub wheelchair_boarding: String,
+    pub level_id: String,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(default)]
 pub struct RawTrip {
     pub route_id: String,
@@ -108,7 +126,7 @@ pub struct RawTrip {
     pub wheelchair_accessible: String,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(default)]
 pub struct RawService {
     pub service_id: String,
@@ -123,7 +141,7 @@ pub struct RawService {
     pub end_date: String,
 }
 
-#[derive(Debug, Deserialize, Default, Clone)]
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
 #[serde(default)]
 pub struct RawServiceException {
     pub service_id: String,
@@ -131,10 +149,174 @@ pub struct RawServiceException {
     pub exception_type: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RawFrequency {
+    pub trip_id: String,
+    pub start_time: String,
+    pub end_time: String,
+    pub headway_secs: String,
+    pub exact_times: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RawTransfer {
+    pub from_stop_id: String,
+    pub to_stop_id: String,
+    pub from_route_id: String,
+    pub to_route_id: String,
+    pub from_trip_id: String,
+    pub to_trip_id: String,
+    pub transfer_type: String,
+    pub min_transfer_time: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RawFareAttribute {
+    pub fare_id: String,
+    pub price: String,
+    pub currency_type: String,
+    pub payment_method: String,
+    pub transfers: String,
+    pub agency_id: String,
+    pub transfer_duration: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RawFareRule {
+    pub fare_id: String,
+    pub route_id: String,
+    pub origin_id: String,
+    pub destination_id: String,
+    pub contains_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RawFareMedia {
+    pub fare_media_id: String,
+    pub fare_media_name: String,
+    pub fare_media_type: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RawFareProduct {
+    pub fare_product_id: String,
+    pub fare_product_name: String,
+    pub rider_category_id: String,
+    pub fare_media_id: String,
+    pub amount: String,
+    pub currency: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RawFareLegRule {
+    pub leg_group_id: String,
+    pub network_id: String,
+    pub from_area_id: String,
+    pub to_area_id: String,
+    pub fare_product_id: String,
+    pub rule_priority: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RawFareTransferRule {
+    pub from_leg_group_id: String,
+    pub to_leg_group_id: String,
+    pub transfer_count: String,
+    pub duration_limit: String,
+    pub duration_limit_type: String,
+    pub fare_transfer_type: String,
+    pub fare_product_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RawArea {
+    pub area_id: String,
+    pub area_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RawStopArea {
+    pub area_id: String,
+    pub stop_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RawNetwork {
+    pub network_id: String,
+    pub network_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RawRouteNetwork {
+    pub network_id: String,
+    pub route_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RawLevel {
+    pub level_id: String,
+    pub level_index: String,
+    pub level_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RawPathway {
+    pub pathway_id: String,
+    pub from_stop_id: String,
+    pub to_stop_id: String,
+    pub pathway_mode: String,
+    pub is_bidirectional: String,
+    pub length: String,
+    pub traversal_time: String,
+    pub stair_count: String,
+    pub max_slope: String,
+    pub min_width: String,
+    pub signposted_as: String,
+    pub reversed_signposted_as: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RawTranslation {
+    pub table_name: String,
+    pub field_name: String,
+    pub language: String,
+    pub translation: String,
+    pub record_id: String,
+    pub record_sub_id: String,
+    pub field_value: String,
+}
+
 pub fn parse_gtfs<T: for<'de> serde::Deserialize<'de>>(path: &Path) -> Result<Vec<T>, Error> {
     let file = File::open(&path)?;
-    Ok(csv::Reader::from_reader(file)
+    Ok(parse_gtfs_from_reader(file))
+}
+
+/// Same as `parse_gtfs`, but reads `file_name` from any kind of `FeedSource` (folder or zip).
+pub fn parse_gtfs_from_source<T: for<'de> serde::Deserialize<'de>>(
+    source: &FeedSource,
+    file_name: &str,
+) -> Result<Vec<T>, Error> {
+    Ok(parse_gtfs_from_reader(source.open(file_name)?))
+}
+
+pub fn parse_gtfs_from_reader<T: for<'de> serde::Deserialize<'de>, R: Read>(reader: R) -> Vec<T> {
+    csv::Reader::from_reader(reader)
         .deserialize()
         .filter_map(Result::ok)
-        .collect::<Vec<T>>())
+        .collect::<Vec<T>>()
 }
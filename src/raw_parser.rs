@@ -1,27 +1,45 @@
-use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::File;
-use std::io::Read;
+use std::io::{ErrorKind, Read};
 use std::path::Path;
+use std::str::FromStr;
 use std::{env, fs};
 
+use chrono_tz::Tz;
 use geo::algorithm::geodesic_distance::GeodesicDistance;
 use itertools::Itertools;
-use log::error;
+use log::warn;
 #[cfg(not(target_arch = "wasm32"))]
 use rayon::iter::ParallelIterator;
+use serde::de::DeserializeOwned;
 
+use crate::fares_v2::{
+    Area, DurationLimitType, FareLegRule, FareMedia, FareMediaType, FareProduct, FareProductPrice,
+    FareTransferRule, FareTransferType, Network,
+};
+use crate::feed_source::FeedSource;
 use crate::gtfs_data::{
-    to_coordinates, GtfsData, GtfsTime, LatLng, Route, Service, ServiceException, Shape, Stop,
-    StopDistance, StopTime, StopTimes, StopWalkTime, Trip,
+    to_coordinates, Agency, FareAttribute, FareRule, FeedInfo, Frequency, GtfsData, GtfsTime,
+    LatLng, LocationType, PaymentMethod, PickupDropOffType, Route, RouteType, Service,
+    ServiceException, Shape, Stop, StopDistance, StopId, StopTime, StopTimes, StopWalkTime,
+    Transfer, TransferType, Trip, TripId,
 };
+use crate::parse_error::{ParseError, ParseErrorKind};
+use crate::pathways::{Level, Pathway, PathwayMode};
 use crate::raw_models::{
-    parse_gtfs, RawRoute, RawService, RawServiceException, RawShape, RawStop, RawStopTime, RawTrip,
+    RawAgency, RawArea, RawFareAttribute, RawFareLegRule, RawFareMedia, RawFareProduct,
+    RawFareRule, RawFareTransferRule, RawFeedInfo, RawFrequency, RawLevel, RawNetwork, RawPathway,
+    RawRoute, RawRouteNetwork, RawService, RawServiceException, RawShape, RawStop, RawStopArea,
+    RawStopTime, RawTransfer, RawTranslation, RawTrip,
 };
+use crate::service_calendar::ServiceCalendar;
+use crate::translations::{TranslationKey, TranslationTable, Translations};
 use crate::wasm_aware_rayon_iterators::{
     IntoParallelIteratorIfPossible, ParallelIteratorIfPossible,
 };
 
 /// `RawParser` converts several different gtfs datasets (set of .txt files), into a `GtfsData` structure.
+/// Each dataset can be a folder with the extracted files or a `.zip` archive (see `FeedSource`).
 ///
 /// # Example
 /// ```no_run
@@ -33,21 +51,63 @@ use crate::wasm_aware_rayon_iterators::{
 ///     parser.parse();
 ///     let dataset : GtfsData = parser.dataset;
 /// ```
+///
+/// Archives can also be parsed directly from memory:
+/// ```no_run
+///     use fastgtfs::feed_source::FeedSource;
+///     use fastgtfs::raw_parser::RawParser;
+///
+///     let zip_content: Vec<u8> = std::fs::read("actv_aut.zip").unwrap();
+///     let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+///     parser.parse();
+/// ```
+///
+/// `parse` panics on malformed input. `try_parse` returns a `ParseError` instead, and in
+/// lenient mode it skips the bad rows, collecting them in `warnings`:
+/// ```no_run
+///     use fastgtfs::raw_parser::RawParser;
+///
+///     let mut parser = RawParser::new(vec!["actv_aut".to_string()]);
+///     parser.lenient = true;
+///     let dataset = parser.try_parse().unwrap();
+///     for warning in &parser.warnings {
+///         println!("Skipped: {}", warning);
+///     }
+/// ```
 #[derive(Debug, Default)]
 pub struct RawParser {
-    paths: Vec<String>,
+    sources: Vec<FeedSource>,
     pub dataset: GtfsData,
     pub dataset_index: u64,
 
+    /// When true, rows with errors are skipped and reported in `warnings`, instead of failing.
+    pub lenient: bool,
+    pub warnings: Vec<ParseError>,
+
     /// The following are used to map from the dataset namespace to the final `GtfsData` name space,
     /// where all the entities are indexed by a numeric ID.
+    pub agency_name_to_inx: HashMap<String, usize>,
     pub routes_name_to_inx: HashMap<String, usize>,
     pub trip_name_to_inx: HashMap<String, usize>,
     pub shape_name_to_inx: HashMap<String, usize>,
     pub stop_name_to_inx: HashMap<String, usize>,
     pub service_name_to_inx: HashMap<String, usize>,
+    pub fare_name_to_inx: HashMap<String, usize>,
+    pub fare_media_name_to_inx: HashMap<String, usize>,
+    pub fare_product_name_to_inx: HashMap<String, usize>,
+    pub area_name_to_inx: HashMap<String, usize>,
+    pub network_name_to_inx: HashMap<String, usize>,
+    pub level_name_to_inx: HashMap<String, usize>,
 
     pub stop_times_inserted: HashMap<StopTimes, usize>,
+
+    /// Trips of the feed being parsed with an empty or unknown `shape_id`, with their `stop_times_id` once
+    /// `stop_times.txt` assigned them one.
+    trips_without_shape: BTreeMap<TripId, Option<usize>>,
+    /// From `stop_times_id` to the synthetic shape built from its stops.
+    synthetic_shapes: HashMap<usize, usize>,
+    /// Rows of the `translations.txt` of all the feeds, see `GtfsData::translations`.
+    translations: Vec<(TranslationKey, String)>,
 }
 
 #[derive(Debug, Default)]
@@ -55,6 +115,7 @@ struct ShapeInConstruction<'a> {
     id: String,
     raw_shapes: Vec<&'a RawShape>,
     points: Vec<LatLng>,
+    dist_traveled: Vec<f64>,
 }
 
 struct StopTimeInConstruction {
@@ -65,18 +126,176 @@ struct StopTimeInConstruction {
 
 /// Converts time from `05:00:00` to `5 * 60 * 60 + 00 * 60 + 00`
 pub fn str_time_to_seconds(s: &str) -> i64 {
-    let sp: Vec<i64> = s.split(':').map(|s| s.parse::<i64>().unwrap()).collect();
+    try_str_time_to_seconds(s).unwrap_or_else(|| panic!("Invalid time: {}", s))
+}
+
+/// Same as `str_time_to_seconds`, but returns `None` when `s` is not in the `H:MM:SS` format.
+pub fn try_str_time_to_seconds(s: &str) -> Option<i64> {
+    let sp = s
+        .trim()
+        .split(':')
+        .map(|s| s.parse::<i64>().ok())
+        .collect::<Option<Vec<i64>>>()?;
+    if sp.len() != 3 {
+        return None;
+    }
     let (h, m, s) = (sp[0], sp[1], sp[2]);
-    s + m * 60 + h * 60 * 60
+    Some(s + m * 60 + h * 60 * 60)
+}
+
+/// The inverse of `str_time_to_seconds`: `HH:MM:SS`, with hours after `23` for the next day.
+pub fn seconds_to_str_time(seconds: i64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// Seconds since midnight. When only one of the two is in the file, it's used for both.
+/// `None` when both are empty, as they can be for the stops that are not timepoints.
+fn arrival_and_departure(st: &RawStopTime) -> Option<(i64, i64)> {
+    let arrival = try_str_time_to_seconds(&st.arrival_time);
+    let departure = try_str_time_to_seconds(&st.departure_time);
+    let arrival = arrival.or(departure)?;
+    Some((arrival, departure.unwrap_or(arrival)))
+}
+
+/// The times of the stops that are not timepoints, interpolated between the timepoints before and
+/// after them, in proportion to the distance between the stops (or evenly, if there is none).
+/// The first and the last stop must have their times.
+fn interpolate_missing_times(
+    times: &[Option<(i64, i64)>],
+    positions: &[LatLng],
+) -> Vec<(i64, i64)> {
+    let mut result = times.iter().map(|t| t.unwrap_or_default()).collect_vec();
+    let timepoints = times.iter().positions(Option::is_some).collect_vec();
+    for (&from, &to) in timepoints.iter().tuple_windows() {
+        let distances = (from..to)
+            .map(|inx| positions[inx].distance_meters(&positions[inx + 1]) as f64)
+            .collect_vec();
+        let total_distance: f64 = distances.iter().sum();
+        let start = result[from].1;
+        let duration = (result[to].0 - start) as f64;
+        let mut distance = 0.0;
+        for inx in from + 1..to {
+            distance += distances[inx - from - 1];
+            let fraction = if total_distance > 0.0 {
+                distance / total_distance
+            } else {
+                (inx - from) as f64 / (to - from) as f64
+            };
+            let time = start + (duration * fraction).round() as i64;
+            result[inx] = (time, time);
+        }
+    }
+    result
 }
 
 struct StopTimesInConstruction {
     trip_id: String,
-    stop_times: Vec<RawStopTime>,
+    stop_times: Vec<Row<RawStopTime>>,
+}
+
+/// A csv row, with the line of the file where it was read.
+#[derive(Clone)]
+struct Row<T> {
+    line: u64,
+    value: T,
+}
+
+/// An error in a single field of a row. The parser adds the feed, the file and the line.
+struct FieldError {
+    field: &'static str,
+    kind: ParseErrorKind,
+}
+
+impl FieldError {
+    fn invalid(field: &'static str, value: &str) -> FieldError {
+        FieldError {
+            field,
+            kind: ParseErrorKind::InvalidValue(value.to_string()),
+        }
+    }
+
+    fn unknown(field: &'static str, value: &str) -> FieldError {
+        FieldError {
+            field,
+            kind: ParseErrorKind::UnknownReference(value.to_string()),
+        }
+    }
+
+    fn into_parse_error(self, feed: &FeedSource, file: &str, line: u64) -> ParseError {
+        ParseError::new(&feed.to_string(), file, self.kind)
+            .at_line(line)
+            .with_field(self.field)
+    }
+}
+
+/// The index of `value` in `map`, for a field referencing an entity of another file.
+fn lookup(
+    map: &HashMap<String, usize>,
+    field: &'static str,
+    value: &str,
+) -> Result<usize, FieldError> {
+    map.get(value)
+        .copied()
+        .ok_or_else(|| FieldError::unknown(field, value))
+}
+
+/// Same as `lookup`, but an empty `value` is `None`.
+fn lookup_optional(
+    map: &HashMap<String, usize>,
+    field: &'static str,
+    value: &str,
+) -> Result<Option<usize>, FieldError> {
+    if value.is_empty() {
+        Ok(None)
+    } else {
+        lookup(map, field, value).map(Some)
+    }
+}
+
+/// Colors are six hex digits, `default` when empty. They are returned uppercase.
+fn parse_color(field: &'static str, value: &str, default: &str) -> Result<String, FieldError> {
+    match value.trim() {
+        "" => Ok(default.to_string()),
+        color if color.len() == 6 && color.chars().all(|c| c.is_ascii_hexdigit()) => {
+            Ok(color.to_ascii_uppercase())
+        }
+        _ => Err(FieldError::invalid(field, value)),
+    }
+}
+
+fn parse_shape_dist_traveled(value: &str) -> Result<Option<f64>, FieldError> {
+    parse_optional("shape_dist_traveled", value, |&d: &f64| d >= 0.0)
+}
+
+/// Parses an optional field: empty values are `None`, the others must parse and be valid.
+fn parse_optional<T: FromStr>(
+    field: &'static str,
+    value: &str,
+    is_valid: impl Fn(&T) -> bool,
+) -> Result<Option<T>, FieldError> {
+    match value.trim() {
+        "" => Ok(None),
+        trimmed => trimmed
+            .parse::<T>()
+            .ok()
+            .filter(|v| is_valid(v))
+            .map(Some)
+            .ok_or_else(|| FieldError::invalid(field, value)),
+    }
 }
 
 const DEFAULT_OUT_PATH: &str = "gtfs_serialized";
 
+/// Version of the serialized data, written with it in `format_version`.
+/// Increase it whenever a serialized struct, or the way the feeds are parsed into it, changes:
+/// data in another format can't be read, and `ensure_data_serialized_created` generates it again.
+const SERIALIZED_FORMAT_VERSION: u32 = 3;
+
 mod gtfs_serializer {
     use std::fs::File;
     use std::io::Write;
@@ -86,6 +305,7 @@ mod gtfs_serializer {
     use serde::Serialize;
 
     use crate::gtfs_data::GtfsData;
+    use crate::raw_parser::SERIALIZED_FORMAT_VERSION;
 
     fn serialize_vector<T: 'static + serde::Serialize + Sync + Send>(
         out_path: String,
@@ -108,13 +328,23 @@ mod gtfs_serializer {
     pub fn generate_serialized_data(ds: GtfsData, folder: String) {
         let f = folder;
         vec![
+            serialize_vector(f.clone(), "format_version", vec![SERIALIZED_FORMAT_VERSION]),
+            serialize_vector(f.clone(), "feeds", ds.feeds),
+            serialize_vector(f.clone(), "agencies", ds.agencies),
             serialize_vector(f.clone(), "routes", ds.routes),
             serialize_vector(f.clone(), "trips", ds.trips),
             serialize_vector(f.clone(), "shapes", ds.shapes),
             serialize_vector(f.clone(), "stops", ds.stops),
             serialize_vector(f.clone(), "stop_times", ds.stop_times),
             serialize_vector(f.clone(), "services", ds.services),
-            serialize_vector(f, "walk_times", ds.walk_times),
+            serialize_vector(f.clone(), "service_calendar", vec![ds.service_calendar]),
+            serialize_vector(f.clone(), "walk_times", ds.walk_times),
+            serialize_vector(f.clone(), "transfers", ds.transfers),
+            serialize_vector(f.clone(), "pathways", ds.pathways),
+            serialize_vector(f.clone(), "levels", ds.levels),
+            serialize_vector(f.clone(), "fares", ds.fares),
+            serialize_vector(f.clone(), "fares_v2", vec![ds.fares_v2]),
+            serialize_vector(f, "translations", vec![ds.translations]),
         ]
         .into_iter()
         .for_each(|v| {
@@ -131,8 +361,8 @@ mod gtfs_deserializer {
     use serde::de::DeserializeOwned;
     use serde::Deserialize;
 
-    use crate::gtfs_data::GtfsData;
-    use crate::raw_parser::read_file;
+    use crate::gtfs_data::{FeedInfo, GtfsData};
+    use crate::raw_parser::{read_file, SERIALIZED_FORMAT_VERSION};
 
     fn deserialize_vector<T: 'static + DeserializeOwned + Sync + Send>(
         in_file: String,
@@ -145,25 +375,68 @@ mod gtfs_deserializer {
         })
     }
 
+    /// Whether the data in `folder` was serialized with the current format.
+    /// Data serialized before the format was versioned has no `format_version`.
+    pub fn is_current_format(folder: &str) -> bool {
+        let in_file = format!("{}/format_version", folder);
+        Path::new(&in_file).exists()
+            && deserialize_vector::<u32>(in_file).join().unwrap() == [SERIALIZED_FORMAT_VERSION]
+    }
+
+    /// Only the feeds of the data serialized in `folder`. Empty if it was serialized with
+    /// another format.
+    pub fn read_serialized_feeds(folder: String) -> Vec<FeedInfo> {
+        if !is_current_format(&folder) {
+            return vec![];
+        }
+        deserialize_vector(folder + "/feeds").join().unwrap()
+    }
+
     pub fn read_serialized_data(folder: String) -> GtfsData {
+        assert!(
+            is_current_format(&folder),
+            "The data in {} was serialized with another format, generate it again",
+            folder
+        );
+        let feeds_t = deserialize_vector(folder.clone() + "/feeds");
+        let agencies_t = deserialize_vector(folder.clone() + "/agencies");
         let routes_t = deserialize_vector(folder.clone() + "/routes");
         let trips_t = deserialize_vector(folder.clone() + "/trips");
         let shapes_t = deserialize_vector(folder.clone() + "/shapes");
         let stops_t = deserialize_vector(folder.clone() + "/stops");
         let stop_times_t = deserialize_vector(folder.clone() + "/stop_times");
         let services_t = deserialize_vector(folder.clone() + "/services");
-        let walk_times_t = deserialize_vector(folder + "/walk_times");
-
-        GtfsData {
+        let service_calendar_t = deserialize_vector(folder.clone() + "/service_calendar");
+        let walk_times_t = deserialize_vector(folder.clone() + "/walk_times");
+        let transfers_t = deserialize_vector(folder.clone() + "/transfers");
+        let pathways_t = deserialize_vector(folder.clone() + "/pathways");
+        let levels_t = deserialize_vector(folder.clone() + "/levels");
+        let fares_t = deserialize_vector(folder.clone() + "/fares");
+        let fares_v2_t = deserialize_vector(folder.clone() + "/fares_v2");
+        let translations_t = deserialize_vector(folder + "/translations");
+
+        let mut dataset = GtfsData {
             dataset_id: 0,
+            feeds: feeds_t.join().unwrap(),
+            agencies: agencies_t.join().unwrap(),
             routes: routes_t.join().unwrap(),
             trips: trips_t.join().unwrap(),
             shapes: shapes_t.join().unwrap(),
             stops: stops_t.join().unwrap(),
             services: services_t.join().unwrap(),
+            service_calendar: service_calendar_t.join().unwrap().pop().unwrap(),
             stop_times: stop_times_t.join().unwrap(),
             walk_times: walk_times_t.join().unwrap(),
-        }
+            transfers: transfers_t.join().unwrap(),
+            pathways: pathways_t.join().unwrap(),
+            levels: levels_t.join().unwrap(),
+            fares: fares_t.join().unwrap(),
+            fares_v2: fares_v2_t.join().unwrap().pop().unwrap(),
+            translations: translations_t.join().unwrap().pop().unwrap(),
+            source_ids: Default::default(),
+        };
+        dataset.index_source_ids();
+        dataset
     }
 }
 
@@ -179,6 +452,7 @@ mod bytes_gtfs_deserializer {
     use serde::Deserialize;
 
     use crate::gtfs_data::GtfsData;
+    use crate::raw_parser::SERIALIZED_FORMAT_VERSION;
 
     fn deserialize_bytes<T: 'static + DeserializeOwned + Sync + Send>(content: &Vec<u8>) -> Vec<T> {
         let r = flexbuffers::Reader::get_root(&content).unwrap();
@@ -189,16 +463,43 @@ mod bytes_gtfs_deserializer {
         trace!("unzipping {} MB", bytes.len() / (1024 * 1024));
         let unzipped = unzip_data(bytes.as_ref());
         trace!("All sucessfully unzipped!");
-        GtfsData {
+        let format_version: Vec<u32> = unzipped
+            .get("format_version")
+            .map(deserialize_bytes)
+            .unwrap_or_default();
+        assert_eq!(
+            format_version,
+            [SERIALIZED_FORMAT_VERSION],
+            "The data was serialized with another format, generate it again"
+        );
+        let mut dataset = GtfsData {
             dataset_id: 0,
+            feeds: deserialize_bytes(unzipped.get("feeds").unwrap()),
+            agencies: deserialize_bytes(unzipped.get("agencies").unwrap()),
             routes: deserialize_bytes(unzipped.get("routes").unwrap()),
             trips: deserialize_bytes(unzipped.get("trips").unwrap()),
             shapes: deserialize_bytes(unzipped.get("shapes").unwrap()),
             stops: deserialize_bytes(unzipped.get("stops").unwrap()),
             services: deserialize_bytes(unzipped.get("services").unwrap()),
+            service_calendar: deserialize_bytes(unzipped.get("service_calendar").unwrap())
+                .pop()
+                .unwrap(),
             stop_times: deserialize_bytes(unzipped.get("stop_times").unwrap()),
             walk_times: deserialize_bytes(unzipped.get("walk_times").unwrap()),
-        }
+            transfers: deserialize_bytes(unzipped.get("transfers").unwrap()),
+            pathways: deserialize_bytes(unzipped.get("pathways").unwrap()),
+            levels: deserialize_bytes(unzipped.get("levels").unwrap()),
+            fares: deserialize_bytes(unzipped.get("fares").unwrap()),
+            fares_v2: deserialize_bytes(unzipped.get("fares_v2").unwrap())
+                .pop()
+                .unwrap(),
+            translations: deserialize_bytes(unzipped.get("translations").unwrap())
+                .pop()
+                .unwrap(),
+            source_ids: Default::default(),
+        };
+        dataset.index_source_ids();
+        dataset
     }
 
     fn unzip_data(data: &[u8]) -> HashMap<String, Vec<u8>> {
@@ -265,36 +566,159 @@ pub fn read_file(path: &Path) -> Vec<u8> {
 }
 
 impl RawParser {
+    /// Each path can be either a folder or a `.zip` file.
     pub fn new(paths: Vec<String>) -> RawParser {
+        RawParser::new_from_sources(paths.iter().map(|p| FeedSource::from_path(p)).collect())
+    }
+
+    pub fn new_from_sources(sources: Vec<FeedSource>) -> RawParser {
         RawParser {
-            paths,
+            sources,
             ..Default::default()
         }
     }
 
+    /// Parses all the feeds into `self.dataset`. Panics at the first error, see `try_parse`.
     pub fn parse(&mut self) {
-        self.paths
-            .clone()
-            .iter()
-            .map(|p| Path::new(p))
-            .for_each(|p| {
-                self.dataset_index += 1;
-                self.parse_path(p);
-            });
+        if let Err(e) = self.parse_feeds() {
+            panic!("Error parsing GTFS data: {}", e);
+        }
+    }
+
+    /// Parses all the feeds, and returns the resulting dataset (`self.dataset` is left empty).
+    pub fn try_parse(&mut self) -> Result<GtfsData, ParseError> {
+        self.parse_feeds()?;
+        Ok(std::mem::take(&mut self.dataset))
+    }
+
+    fn parse_feeds(&mut self) -> Result<(), ParseError> {
+        for feed in self.sources.clone().iter() {
+            self.dataset_index += 1;
+            self.parse_feed(feed)?;
+        }
+        self.dataset.service_calendar = ServiceCalendar::new(&self.dataset.services);
+        self.dataset.translations = Translations::new(std::mem::take(&mut self.translations));
+        self.dataset.index_source_ids();
         // This parses an additional file created with `walk_distance_calculator`,
         // used to add walking paths in the navigation.
         self.try_parse_walk_paths();
+        Ok(())
     }
 
-    fn parse_path(&mut self, path: &Path) {
-        self.parse_stops(path);
-        self.parse_shape(path);
-        self.parse_routes(path);
-        self.parse_services(path);
-        self.parse_trips(path);
-        self.parse_stop_times(path);
+    fn parse_feed(&mut self, feed: &FeedSource) -> Result<(), ParseError> {
+        self.clear_feed_ids();
+        let feed_info = self.parse_feed_info(feed, self.dataset_index)?;
+        self.dataset.feeds.push(feed_info);
+        self.parse_levels(feed)?;
+        self.parse_stops(feed)?;
+        self.parse_pathways(feed)?;
+        self.parse_shape(feed)?;
+        self.parse_agencies(feed)?;
+        self.parse_routes(feed)?;
+        self.parse_services(feed)?;
+        self.parse_trips(feed)?;
+        self.parse_stop_times(feed)?;
+        self.add_synthetic_shapes();
+        self.parse_frequencies(feed)?;
+        self.parse_transfers(feed)?;
+        self.parse_fares(feed)?;
+        self.parse_fares_v2(feed)?;
+        self.parse_translations(feed)?;
         self.assign_stop_times_to_routes();
         self.assign_routes_to_stops();
+        Ok(())
+    }
+
+    /// The ids of a feed are resolved only within it: after parsing, the `*_name_to_inx` maps
+    /// have the ids of the last feed. See `GtfsData::lookup_by_source_id` for all of them.
+    fn clear_feed_ids(&mut self) {
+        self.agency_name_to_inx.clear();
+        self.routes_name_to_inx.clear();
+        self.trip_name_to_inx.clear();
+        self.shape_name_to_inx.clear();
+        self.stop_name_to_inx.clear();
+        self.service_name_to_inx.clear();
+        self.fare_name_to_inx.clear();
+        self.fare_media_name_to_inx.clear();
+        self.fare_product_name_to_inx.clear();
+        self.area_name_to_inx.clear();
+        self.network_name_to_inx.clear();
+        self.level_name_to_inx.clear();
+    }
+
+    /// In lenient mode `error` becomes a warning, otherwise it is returned.
+    fn report(&mut self, error: ParseError) -> Result<(), ParseError> {
+        if !self.lenient {
+            return Err(error);
+        }
+        warn!("Skipping row: {}", error);
+        self.warnings.push(error);
+        Ok(())
+    }
+
+    /// Returns `None` if the file is not in the feed.
+    /// Rows that can't be deserialized are reported as `Malformed`.
+    fn read_rows<T: DeserializeOwned>(
+        &mut self,
+        feed: &FeedSource,
+        file_name: &str,
+    ) -> Result<Option<Vec<Row<T>>>, ParseError> {
+        let malformed = |e: &dyn std::error::Error| {
+            ParseError::new(
+                &feed.to_string(),
+                file_name,
+                ParseErrorKind::Malformed(e.to_string()),
+            )
+        };
+        let reader = match feed.open(file_name) {
+            Ok(reader) => reader,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(malformed(&e)),
+        };
+        let mut reader = csv::Reader::from_reader(reader);
+        let headers = reader.headers().map_err(|e| malformed(&e))?.clone();
+
+        let mut rows = vec![];
+        for record in reader.records() {
+            let row = record.and_then(|record| {
+                let line = record.position().map(|p| p.line()).unwrap_or_default();
+                record
+                    .deserialize(Some(&headers))
+                    .map(|value| Row { line, value })
+            });
+            match row {
+                Ok(row) => rows.push(row),
+                Err(e) => {
+                    let mut error = malformed(&e);
+                    error.line = e.position().map(|p| p.line());
+                    self.report(error)?;
+                }
+            }
+        }
+        Ok(Some(rows))
+    }
+
+    /// Like `read_rows`, but the file must exist and must not be empty.
+    fn read_required_rows<T: DeserializeOwned>(
+        &mut self,
+        feed: &FeedSource,
+        file_name: &str,
+    ) -> Result<Vec<Row<T>>, ParseError> {
+        match self.read_rows(feed, file_name)? {
+            Some(rows) => {
+                if rows.is_empty() {
+                    let error =
+                        ParseError::new(&feed.to_string(), file_name, ParseErrorKind::EmptyFile);
+                    self.report(error)?;
+                }
+                Ok(rows)
+            }
+            None => Err(ParseError::new(
+                &feed.to_string(),
+                file_name,
+                ParseErrorKind::MissingFile,
+            )),
+        }
     }
 
     pub fn read_preprocessed_data_from_default() -> GtfsData {
@@ -315,22 +739,44 @@ impl RawParser {
         self.ensure_data_serialized_created_in_path(DEFAULT_OUT_PATH)
     }
 
+    /// The `feed_info.txt` of the feeds serialized in `folder`, without reading the rest.
+    pub fn read_preprocessed_feeds(folder: String) -> Vec<FeedInfo> {
+        gtfs_deserializer::read_serialized_feeds(folder)
+    }
+
+    /// The `feed_info.txt` of each source, without parsing the rest of the feeds.
+    pub fn read_feed_infos(&mut self) -> Result<Vec<FeedInfo>, ParseError> {
+        self.sources
+            .clone()
+            .iter()
+            .enumerate()
+            .map(|(inx, feed)| self.parse_feed_info(feed, inx as u64 + 1))
+            .collect()
+    }
+
+    /// Serializes the data in `path`, unless it is already there and was created from the same
+    /// version of the feeds (i.e. their `feed_info.txt` didn't change, or their content for feeds
+    /// without a `feed_version`).
     pub fn ensure_data_serialized_created_in_path(&mut self, path: &str) {
         println!("Ensuring data serialized");
-        // todo, add a dataset version check, to avoid errors when the old app tries to read new data.
         let routes_file = format!("{}/routes", path);
         if !Path::new(&routes_file).exists() {
             self.generate_serialized_data(path);
             return;
         }
 
-        let metadata = fs::metadata(routes_file).unwrap();
-        let last_modified = metadata.modified().unwrap().elapsed().unwrap().as_secs();
-
-        println!("Last modified: {}", last_modified);
-        if last_modified > 60 * 60 * 24 * 7 {
-            // rebuild the data every day
-            println!("Generating serializable data!");
+        if !gtfs_deserializer::is_current_format(path) {
+            println!("Serialized data has another format, generating serializable data!");
+            self.generate_serialized_data(path);
+            return;
+        }
+        let serialized_feeds = RawParser::read_preprocessed_feeds(path.to_string());
+        let up_to_date = match self.read_feed_infos() {
+            Ok(feeds) => feeds == serialized_feeds,
+            Err(_) => false,
+        };
+        if !up_to_date {
+            println!("Feeds changed, generating serializable data!");
             self.generate_serialized_data(path);
         }
     }
@@ -345,14 +791,15 @@ impl RawParser {
             env::current_dir().unwrap().to_str().unwrap()
         );
         fs::create_dir_all(DEFAULT_OUT_PATH).unwrap();
-        let path = self.paths.first().unwrap();
-        let destination_folder = &format!("{}/{}", path, out_folder);
-        if !Path::new(destination_folder).exists() {
-            println!("Creating output path!");
-            match fs::create_dir_all(destination_folder) {
-                Ok(_) => {}
-                Err(e) => {
-                    panic!("Can't create output folder {}, {}", destination_folder, e);
+        if let Some(FeedSource::Folder(path)) = self.sources.first() {
+            let destination_folder = &format!("{}/{}", path.display(), out_folder);
+            if !Path::new(destination_folder).exists() {
+                println!("Creating output path!");
+                match fs::create_dir_all(destination_folder) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        panic!("Can't create output folder {}, {}", destination_folder, e);
+                    }
                 }
             }
         }
@@ -391,50 +838,230 @@ impl RawParser {
         }
     }
 
-    fn parse_stops(&mut self, path: &Path) {
-        let stop_path = Path::new(&path).join(Path::new("stops.txt"));
-        let raw_stops: Vec<RawStop> = parse_gtfs(&stop_path)
-            .unwrap_or_else(|_| panic!("Stop file not found: {}", path.display()));
-        assert!(!raw_stops.is_empty());
-        raw_stops.into_iter().for_each(|s| self.add_stop(s));
+    fn parse_stops(&mut self, feed: &FeedSource) -> Result<(), ParseError> {
+        let raw_stops: Vec<Row<RawStop>> = self.read_required_rows(feed, "stops.txt")?;
+        // Parents can come after their children: they are linked once all the stops are added.
+        let mut parent_stations = vec![];
+        for row in raw_stops {
+            let parent_station = row.value.parent_station.trim().to_string();
+            match self.add_stop(row.value) {
+                Ok(stop_id) if !parent_station.is_empty() => {
+                    parent_stations.push((row.line, stop_id, parent_station))
+                }
+                Ok(_) => {}
+                Err(e) => self.report(e.into_parse_error(feed, "stops.txt", row.line))?,
+            }
+        }
+        for (line, stop_id, parent_station) in parent_stations {
+            if let Err(e) = self.link_parent_station(stop_id, &parent_station) {
+                self.report(e.into_parse_error(feed, "stops.txt", line))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn link_parent_station(
+        &mut self,
+        stop_id: StopId,
+        parent_station: &str,
+    ) -> Result<(), FieldError> {
+        let parent_id = lookup(&self.stop_name_to_inx, "parent_station", parent_station)?;
+        if self.dataset.stops[stop_id].location_type == LocationType::Station {
+            return Err(FieldError::invalid("parent_station", parent_station));
+        }
+        let parent = &mut self.dataset.stops[parent_id];
+        parent.children.insert(stop_id);
+        let parent_pos = parent.stop_pos.clone();
+
+        let stop = &mut self.dataset.stops[stop_id];
+        stop.parent_station = Some(parent_id);
+        if !stop.location_type.requires_position() && stop.stop_pos.is_unknown() {
+            stop.stop_pos = parent_pos;
+        }
+        Ok(())
     }
 
     pub fn create_stop(stop: RawStop, stop_id: usize) -> Stop {
-        Stop {
+        let stop_name = stop.stop_id.clone();
+        RawParser::try_create_stop(stop, stop_id)
+            .unwrap_or_else(|_| panic!("Invalid position for stop {}", stop_name))
+    }
+
+    /// `parent_station` and `level_id` are not read, see `add_stop`.
+    fn try_create_stop(stop: RawStop, stop_id: usize) -> Result<Stop, FieldError> {
+        let location_type = LocationType::from_gtfs(&stop.location_type)
+            .ok_or_else(|| FieldError::invalid("location_type", &stop.location_type))?;
+        let stop_pos = if location_type.requires_position()
+            || !(stop.stop_lat.trim().is_empty() && stop.stop_lon.trim().is_empty())
+        {
+            let lat = stop
+                .stop_lat
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| FieldError::invalid("stop_lat", &stop.stop_lat))?;
+            let lng = stop
+                .stop_lon
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| FieldError::invalid("stop_lon", &stop.stop_lon))?;
+            LatLng { lat, lng }
+        } else {
+            LatLng::default()
+        };
+        Ok(Stop {
             stop_id,
+            source_id: stop.stop_id,
+            dataset_index: 0,
+            stop_code: stop.stop_code,
             stop_name: stop.stop_name,
-            stop_pos: LatLng {
-                lat: stop.stop_lat.parse::<f64>().unwrap(),
-                lng: stop.stop_lon.parse::<f64>().unwrap(),
-            },
-            stop_timezone: "".to_string(),
+            stop_pos,
+            location_type,
+            parent_station: None,
+            children: Default::default(),
+            level_id: None,
+            pathways: Default::default(),
+            wheelchair_boarding: stop.wheelchair_boarding,
+            stop_timezone: parse_optional("stop_timezone", &stop.stop_timezone, |_: &Tz| true)?,
+            zone_id: stop.zone_id.trim().to_string(),
             routes: Default::default(),
-        }
+        })
     }
-    fn add_stop(&mut self, stop: RawStop) {
+
+    fn add_stop(&mut self, stop: RawStop) -> Result<StopId, FieldError> {
         let number_of_stops = self.dataset.stops.len();
-        self.stop_name_to_inx
-            .insert(stop.stop_id.clone(), number_of_stops);
-        self.dataset
-            .stops
-            .push(RawParser::create_stop(stop, number_of_stops))
-    }
-
-    fn parse_stop_times(&mut self, path: &Path) {
-        let stop_times_path = Path::new(&path).join(Path::new("stop_times.txt"));
-        let mut reader = csv::Reader::from_reader(File::open(&stop_times_path).unwrap());
-        let grouped_trips: Vec<StopTimesInConstruction> = reader
-            .deserialize()
-            .filter_map(Result::ok)
-            .group_by(|l: &RawStopTime| l.trip_id.clone())
-            .into_iter()
-            .map(|(trip_id, raw_stop_times)| StopTimesInConstruction {
-                trip_id,
-                stop_times: raw_stop_times.collect(),
-            })
-            .collect();
+        let raw_stop_id = stop.stop_id.clone();
+        let level_id = lookup_optional(&self.level_name_to_inx, "level_id", stop.level_id.trim())?;
+        let mut stop = RawParser::try_create_stop(stop, number_of_stops)?;
+        stop.level_id = level_id;
+        stop.dataset_index = self.dataset_index;
+        self.stop_name_to_inx.insert(raw_stop_id, number_of_stops);
+        self.dataset.stops.push(stop);
+        Ok(number_of_stops)
+    }
 
-        let stop_times = grouped_trips
+    /// `levels.txt` is optional.
+    fn parse_levels(&mut self, feed: &FeedSource) -> Result<(), ParseError> {
+        let raw_levels: Vec<Row<RawLevel>> = match self.read_rows(feed, "levels.txt")? {
+            Some(rows) => rows,
+            None => return Ok(()),
+        };
+        for row in raw_levels {
+            if let Err(e) = self.add_level(row.value) {
+                self.report(e.into_parse_error(feed, "levels.txt", row.line))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn add_level(&mut self, level: RawLevel) -> Result<(), FieldError> {
+        let level_index = level
+            .level_index
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| FieldError::invalid("level_index", &level.level_index))?;
+        let level_id = self.dataset.levels.len();
+        self.level_name_to_inx.insert(level.level_id, level_id);
+        self.dataset.levels.push(Level {
+            level_id,
+            level_index,
+            level_name: level.level_name,
+        });
+        Ok(())
+    }
+
+    /// `pathways.txt` is optional.
+    fn parse_pathways(&mut self, feed: &FeedSource) -> Result<(), ParseError> {
+        let raw_pathways: Vec<Row<RawPathway>> = match self.read_rows(feed, "pathways.txt")? {
+            Some(rows) => rows,
+            None => return Ok(()),
+        };
+        for row in raw_pathways {
+            match self.create_pathway(&row.value) {
+                Ok(pathway) => {
+                    let stops = &mut self.dataset.stops;
+                    stops[pathway.from_stop_id]
+                        .pathways
+                        .insert(pathway.pathway_id);
+                    stops[pathway.to_stop_id]
+                        .pathways
+                        .insert(pathway.pathway_id);
+                    self.dataset.pathways.push(pathway);
+                }
+                Err(e) => self.report(e.into_parse_error(feed, "pathways.txt", row.line))?,
+            }
+        }
+        Ok(())
+    }
+
+    fn create_pathway(&self, p: &RawPathway) -> Result<Pathway, FieldError> {
+        let pathway_mode = PathwayMode::from_gtfs(&p.pathway_mode)
+            .ok_or_else(|| FieldError::invalid("pathway_mode", &p.pathway_mode))?;
+        let is_bidirectional = match p.is_bidirectional.trim() {
+            "0" => false,
+            "1" => true,
+            _ => return Err(FieldError::invalid("is_bidirectional", &p.is_bidirectional)),
+        };
+        Ok(Pathway {
+            pathway_id: self.dataset.pathways.len(),
+            from_stop_id: lookup(&self.stop_name_to_inx, "from_stop_id", &p.from_stop_id)?,
+            to_stop_id: lookup(&self.stop_name_to_inx, "to_stop_id", &p.to_stop_id)?,
+            pathway_mode,
+            is_bidirectional,
+            length: parse_optional("length", &p.length, |&l: &f64| l >= 0.0)?,
+            traversal_time: parse_optional("traversal_time", &p.traversal_time, |&t: &i64| t > 0)?,
+            stair_count: parse_optional("stair_count", &p.stair_count, |_: &i32| true)?,
+        })
+    }
+
+    fn parse_stop_times(&mut self, feed: &FeedSource) -> Result<(), ParseError> {
+        let raw_stop_times: Vec<Row<RawStopTime>> =
+            self.read_required_rows(feed, "stop_times.txt")?;
+
+        let mut valid_stop_times = Vec::with_capacity(raw_stop_times.len());
+        for row in raw_stop_times {
+            match self.check_stop_time(&row.value) {
+                Ok(()) => valid_stop_times.push(row),
+                Err(e) => self.report(e.into_parse_error(feed, "stop_times.txt", row.line))?,
+            }
+        }
+
+        // Rows of the same trip are not necessarily contiguous, nor sorted by stop_sequence.
+        // Trips are kept in order of first appearance, and sorted in `create_stop_times`.
+        let mut grouped_trips: Vec<StopTimesInConstruction> = vec![];
+        let mut trip_to_group: HashMap<String, usize> = HashMap::new();
+        for row in valid_stop_times {
+            let group_inx = *trip_to_group
+                .entry(row.value.trip_id.clone())
+                .or_insert_with(|| {
+                    grouped_trips.push(StopTimesInConstruction {
+                        trip_id: row.value.trip_id.clone(),
+                        stop_times: vec![],
+                    });
+                    grouped_trips.len() - 1
+                });
+            grouped_trips[group_inx].stop_times.push(row);
+        }
+
+        // Times can be missing only between two timepoints, where they are interpolated.
+        let mut timed_trips = Vec::with_capacity(grouped_trips.len());
+        for trip in grouped_trips {
+            let stop_sequence =
+                |row: &&Row<RawStopTime>| row.value.stop_sequence.trim().parse::<u32>().unwrap();
+            let first = trip.stop_times.iter().min_by_key(stop_sequence).unwrap();
+            let last = trip.stop_times.iter().max_by_key(stop_sequence).unwrap();
+            match [first, last]
+                .iter()
+                .find(|row| arrival_and_departure(&row.value).is_none())
+            {
+                Some(row) => {
+                    let e = FieldError::invalid("arrival_time", &row.value.arrival_time);
+                    self.report(e.into_parse_error(feed, "stop_times.txt", row.line))?;
+                }
+                None => timed_trips.push(trip),
+            }
+        }
+
+        let stop_times = timed_trips
             .into_par_iter_if_possible()
             .map(|stop_times_in_construction| self.create_stop_times(stop_times_in_construction))
             .collect::<Vec<StopTimeInConstruction>>();
@@ -447,8 +1074,494 @@ impl RawParser {
                 let trip: &mut Trip = self.dataset.trips.get_mut(trip_id).unwrap();
                 trip.stop_times_id = stop_time_id;
                 trip.start_time = st.start_time;
+                if let Some(stop_times_id) = self.trips_without_shape.get_mut(&trip_id) {
+                    *stop_times_id = Some(stop_time_id);
+                }
+            });
+        Ok(())
+    }
+
+    /// Checks the references and the times of a row, so that `create_stop_times` can't fail.
+    fn check_stop_time(&self, st: &RawStopTime) -> Result<(), FieldError> {
+        if !self.trip_name_to_inx.contains_key(&st.trip_id) {
+            return Err(FieldError::unknown("trip_id", &st.trip_id));
+        }
+        if !self.stop_name_to_inx.contains_key(&st.stop_id) {
+            return Err(FieldError::unknown("stop_id", &st.stop_id));
+        }
+        // One of the two is enough, they are usually the same. Stops that are not timepoints
+        // can have none.
+        if try_str_time_to_seconds(&st.arrival_time).is_none() && !st.arrival_time.trim().is_empty()
+        {
+            return Err(FieldError::invalid("arrival_time", &st.arrival_time));
+        }
+        if try_str_time_to_seconds(&st.departure_time).is_none()
+            && !st.departure_time.trim().is_empty()
+        {
+            return Err(FieldError::invalid("departure_time", &st.departure_time));
+        }
+        if st.stop_sequence.trim().parse::<u32>().is_err() {
+            return Err(FieldError::invalid("stop_sequence", &st.stop_sequence));
+        }
+        if PickupDropOffType::from_gtfs(&st.pickup_type).is_none() {
+            return Err(FieldError::invalid("pickup_type", &st.pickup_type));
+        }
+        if PickupDropOffType::from_gtfs(&st.drop_off_type).is_none() {
+            return Err(FieldError::invalid("drop_off_type", &st.drop_off_type));
+        }
+        parse_shape_dist_traveled(&st.shape_dist_traveled)?;
+        Ok(())
+    }
+    /// `frequencies.txt` is optional. Trips with `exact_times=1` are expanded into one trip for
+    /// each departure, the others keep their headway windows in `Trip.frequencies`.
+    fn parse_frequencies(&mut self, feed: &FeedSource) -> Result<(), ParseError> {
+        let raw_frequencies: Vec<Row<RawFrequency>> =
+            match self.read_rows(feed, "frequencies.txt")? {
+                Some(rows) => rows,
+                None => return Ok(()),
+            };
+
+        let mut trips_order: Vec<usize> = vec![];
+        let mut windows_per_trip: HashMap<usize, Vec<(Frequency, bool)>> = HashMap::new();
+        for row in raw_frequencies {
+            match self.create_frequency(&row.value) {
+                Ok((trip_id, frequency, exact_times)) => windows_per_trip
+                    .entry(trip_id)
+                    .or_insert_with(|| {
+                        trips_order.push(trip_id);
+                        vec![]
+                    })
+                    .push((frequency, exact_times)),
+                Err(e) => self.report(e.into_parse_error(feed, "frequencies.txt", row.line))?,
+            }
+        }
+
+        for trip_id in trips_order {
+            let mut windows = windows_per_trip.remove(&trip_id).unwrap();
+            windows.sort_by_key(|(frequency, _)| frequency.start_time);
+            self.add_frequencies(trip_id, windows);
+        }
+        Ok(())
+    }
+
+    /// Returns the index of the trip, the window, and whether it has exact times.
+    fn create_frequency(&self, f: &RawFrequency) -> Result<(usize, Frequency, bool), FieldError> {
+        let trip_id = *self
+            .trip_name_to_inx
+            .get(&f.trip_id)
+            .ok_or_else(|| FieldError::unknown("trip_id", &f.trip_id))?;
+        let start_time = try_str_time_to_seconds(&f.start_time)
+            .ok_or_else(|| FieldError::invalid("start_time", &f.start_time))?;
+        let end_time = try_str_time_to_seconds(&f.end_time)
+            .filter(|&end_time| end_time > start_time)
+            .ok_or_else(|| FieldError::invalid("end_time", &f.end_time))?;
+        let headway_secs = f
+            .headway_secs
+            .trim()
+            .parse::<i64>()
+            .ok()
+            .filter(|&h| h > 0)
+            .ok_or_else(|| FieldError::invalid("headway_secs", &f.headway_secs))?;
+        let exact_times = match f.exact_times.trim() {
+            "" | "0" => false,
+            "1" => true,
+            _ => return Err(FieldError::invalid("exact_times", &f.exact_times)),
+        };
+        let frequency = Frequency {
+            start_time,
+            end_time,
+            headway_secs,
+        };
+        Ok((trip_id, frequency, exact_times))
+    }
+
+    /// `windows` must be sorted by start time.
+    fn add_frequencies(&mut self, trip_id: usize, windows: Vec<(Frequency, bool)>) {
+        let (exact, headway): (Vec<_>, Vec<_>) = windows
+            .into_iter()
+            .partition(|(_, exact_times)| *exact_times);
+        // The windows give the departures from the first stop, while the trips start at its
+        // arrival.
+        let first_departure = self
+            .dataset
+            .stop_times
+            .get(self.dataset.trips[trip_id].stop_times_id)
+            .and_then(|st| st.stop_times.first())
+            .map_or(0, |st| st.departure_time);
+        let headway_windows = headway
+            .into_iter()
+            .map(|(f, _)| Frequency {
+                start_time: f.start_time - first_departure,
+                end_time: f.end_time - first_departure,
+                ..f
+            })
+            .collect_vec();
+        let mut exact_starts = exact
+            .into_iter()
+            .flat_map(|(f, _)| (f.start_time..f.end_time).step_by(f.headway_secs as usize))
+            .map(|departure| departure - first_departure);
+
+        // The times in stop_times.txt are only used as offsets: the template trip becomes either
+        // the headway based trip, or the first exact departure.
+        let template = self.dataset.trips.get_mut(trip_id).unwrap();
+        if let Some(first_window) = headway_windows.first() {
+            template.start_time = first_window.start_time;
+            template.frequencies = headway_windows;
+        } else if let Some(first_start) = exact_starts.next() {
+            template.start_time = first_start;
+        }
+        let template = template.clone();
+
+        // The other runs get the id of the template with a counter: `T1_1` for the second run,
+        // `T1_2` for the third, skipping the ids of the trips in the feed.
+        let trip_names = &self.trip_name_to_inx;
+        let mut source_ids = (1..)
+            .map(|n| format!("{}_{}", template.source_id, n))
+            .filter(|source_id| !trip_names.contains_key(source_id));
+        for start_time in exact_starts {
+            let new_trip_id = self.dataset.trips.len();
+            self.dataset.trips.push(Trip {
+                trip_id: new_trip_id,
+                source_id: source_ids.next().unwrap(),
+                start_time,
+                frequencies: vec![],
+                ..template.clone()
+            });
+            let route = self.dataset.routes.get_mut(template.route_id).unwrap();
+            route.trips.push(new_trip_id);
+        }
+    }
+
+    /// `transfers.txt` is optional.
+    fn parse_transfers(&mut self, feed: &FeedSource) -> Result<(), ParseError> {
+        let raw_transfers: Vec<Row<RawTransfer>> = match self.read_rows(feed, "transfers.txt")? {
+            Some(rows) => rows,
+            None => return Ok(()),
+        };
+        for row in raw_transfers {
+            match self.create_transfer(&row.value) {
+                Ok(transfer) => self.dataset.transfers.push(transfer),
+                Err(e) => self.report(e.into_parse_error(feed, "transfers.txt", row.line))?,
+            }
+        }
+        Ok(())
+    }
+
+    fn create_transfer(&self, t: &RawTransfer) -> Result<Transfer, FieldError> {
+        let transfer_type = TransferType::from_gtfs(&t.transfer_type)
+            .ok_or_else(|| FieldError::invalid("transfer_type", &t.transfer_type))?;
+        // An empty route (or trip) means any.
+        let (routes, trips) = (&self.routes_name_to_inx, &self.trip_name_to_inx);
+        Ok(Transfer {
+            from_stop_id: lookup(&self.stop_name_to_inx, "from_stop_id", &t.from_stop_id)?,
+            to_stop_id: lookup(&self.stop_name_to_inx, "to_stop_id", &t.to_stop_id)?,
+            from_route_id: lookup_optional(routes, "from_route_id", &t.from_route_id)?,
+            to_route_id: lookup_optional(routes, "to_route_id", &t.to_route_id)?,
+            from_trip_id: lookup_optional(trips, "from_trip_id", &t.from_trip_id)?,
+            to_trip_id: lookup_optional(trips, "to_trip_id", &t.to_trip_id)?,
+            transfer_type,
+            min_transfer_time: parse_optional("min_transfer_time", &t.min_transfer_time, |&s| {
+                s >= 0
+            })?,
+        })
+    }
+
+    /// `fare_attributes.txt` and `fare_rules.txt` are optional.
+    fn parse_fares(&mut self, feed: &FeedSource) -> Result<(), ParseError> {
+        let raw_fares: Vec<Row<RawFareAttribute>> =
+            match self.read_rows(feed, "fare_attributes.txt")? {
+                Some(rows) => rows,
+                None => return Ok(()),
+            };
+        for row in raw_fares {
+            if let Err(e) = self.add_fare(row.value) {
+                self.report(e.into_parse_error(feed, "fare_attributes.txt", row.line))?;
+            }
+        }
+
+        let raw_rules: Vec<Row<RawFareRule>> =
+            self.read_rows(feed, "fare_rules.txt")?.unwrap_or_default();
+        for row in raw_rules {
+            match self.create_fare_rule(&row.value) {
+                Ok((fare_id, rule)) => self.dataset.fares[fare_id].rules.push(rule),
+                Err(e) => self.report(e.into_parse_error(feed, "fare_rules.txt", row.line))?,
+            }
+        }
+        Ok(())
+    }
+
+    fn add_fare(&mut self, fare: RawFareAttribute) -> Result<(), FieldError> {
+        let price = fare
+            .price
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|&price| price >= 0.0 && price.is_finite())
+            .ok_or_else(|| FieldError::invalid("price", &fare.price))?;
+        let payment_method = PaymentMethod::from_gtfs(&fare.payment_method)
+            .ok_or_else(|| FieldError::invalid("payment_method", &fare.payment_method))?;
+        // Empty means unlimited transfers.
+        let transfers = match fare.transfers.trim() {
+            "" => None,
+            "0" => Some(0),
+            "1" => Some(1),
+            "2" => Some(2),
+            _ => return Err(FieldError::invalid("transfers", &fare.transfers)),
+        };
+        let transfer_duration =
+            parse_optional("transfer_duration", &fare.transfer_duration, |&s: &i64| {
+                s >= 0
+            })?;
+
+        let fare_id = self.dataset.fares.len();
+        self.fare_name_to_inx.insert(fare.fare_id, fare_id);
+        self.dataset.fares.push(FareAttribute {
+            fare_id,
+            price,
+            currency_type: fare.currency_type.trim().to_string(),
+            payment_method,
+            transfers,
+            transfer_duration,
+            rules: vec![],
+        });
+        Ok(())
+    }
+
+    /// Returns the index of the fare, and the rule.
+    fn create_fare_rule(&self, rule: &RawFareRule) -> Result<(usize, FareRule), FieldError> {
+        let fare_id = lookup(&self.fare_name_to_inx, "fare_id", &rule.fare_id)?;
+        let route_id = lookup_optional(&self.routes_name_to_inx, "route_id", &rule.route_id)?;
+        let zone = |zone_id: &str| match zone_id.trim() {
+            "" => None,
+            zone_id => Some(zone_id.to_string()),
+        };
+        Ok((
+            fare_id,
+            FareRule {
+                route_id,
+                origin_id: zone(&rule.origin_id),
+                destination_id: zone(&rule.destination_id),
+                contains_id: zone(&rule.contains_id),
+            },
+        ))
+    }
+
+    /// The GTFS-Fares v2 files are all optional. See `FaresV2`.
+    fn parse_fares_v2(&mut self, feed: &FeedSource) -> Result<(), ParseError> {
+        for row in self
+            .read_rows::<RawNetwork>(feed, "networks.txt")?
+            .unwrap_or_default()
+        {
+            let network_id = self.get_or_add_network(&row.value.network_id);
+            self.dataset.fares_v2.networks[network_id].network_name = row.value.network_name;
+        }
+        let route_networks = self
+            .read_rows::<RawRouteNetwork>(feed, "route_networks.txt")?
+            .unwrap_or_default();
+        for row in route_networks {
+            let r = &row.value;
+            let route_network = lookup(&self.network_name_to_inx, "network_id", &r.network_id)
+                .and_then(|n| {
+                    Ok((
+                        n,
+                        lookup(&self.routes_name_to_inx, "route_id", &r.route_id)?,
+                    ))
+                });
+            match route_network {
+                Ok((network_id, route_id)) => {
+                    let network = &mut self.dataset.fares_v2.networks[network_id];
+                    network.route_ids.insert(route_id);
+                }
+                Err(e) => self.report(e.into_parse_error(feed, "route_networks.txt", row.line))?,
+            }
+        }
+
+        for row in self
+            .read_rows::<RawArea>(feed, "areas.txt")?
+            .unwrap_or_default()
+        {
+            let area_id = self.dataset.fares_v2.areas.len();
+            self.area_name_to_inx.insert(row.value.area_id, area_id);
+            self.dataset.fares_v2.areas.push(Area {
+                area_id,
+                area_name: row.value.area_name,
+                stop_ids: Default::default(),
             });
+        }
+        let stop_areas = self
+            .read_rows::<RawStopArea>(feed, "stop_areas.txt")?
+            .unwrap_or_default();
+        for row in stop_areas {
+            let r = &row.value;
+            let stop_area = lookup(&self.area_name_to_inx, "area_id", &r.area_id)
+                .and_then(|a| Ok((a, lookup(&self.stop_name_to_inx, "stop_id", &r.stop_id)?)));
+            match stop_area {
+                Ok((area_id, stop_id)) => {
+                    self.dataset.fares_v2.areas[area_id]
+                        .stop_ids
+                        .insert(stop_id);
+                }
+                Err(e) => self.report(e.into_parse_error(feed, "stop_areas.txt", row.line))?,
+            }
+        }
+
+        let fare_media = self
+            .read_rows::<RawFareMedia>(feed, "fare_media.txt")?
+            .unwrap_or_default();
+        for row in fare_media {
+            if let Err(e) = self.add_fare_media(row.value) {
+                self.report(e.into_parse_error(feed, "fare_media.txt", row.line))?;
+            }
+        }
+        let fare_products = self
+            .read_rows::<RawFareProduct>(feed, "fare_products.txt")?
+            .unwrap_or_default();
+        for row in fare_products {
+            if let Err(e) = self.add_fare_product(row.value) {
+                self.report(e.into_parse_error(feed, "fare_products.txt", row.line))?;
+            }
+        }
+
+        let leg_rules = self
+            .read_rows::<RawFareLegRule>(feed, "fare_leg_rules.txt")?
+            .unwrap_or_default();
+        for row in leg_rules {
+            match self.create_fare_leg_rule(&row.value) {
+                Ok(rule) => self.dataset.fares_v2.fare_leg_rules.push(rule),
+                Err(e) => self.report(e.into_parse_error(feed, "fare_leg_rules.txt", row.line))?,
+            }
+        }
+        let transfer_rules = self
+            .read_rows::<RawFareTransferRule>(feed, "fare_transfer_rules.txt")?
+            .unwrap_or_default();
+        for row in transfer_rules {
+            match self.create_fare_transfer_rule(&row.value) {
+                Ok(rule) => self.dataset.fares_v2.fare_transfer_rules.push(rule),
+                Err(e) => {
+                    self.report(e.into_parse_error(feed, "fare_transfer_rules.txt", row.line))?
+                }
+            }
+        }
+        Ok(())
     }
+
+    /// Networks can be defined both by `networks.txt` and by `routes.txt`.
+    fn get_or_add_network(&mut self, raw_network_id: &str) -> usize {
+        if let Some(&network_id) = self.network_name_to_inx.get(raw_network_id) {
+            return network_id;
+        }
+        let network_id = self.dataset.fares_v2.networks.len();
+        self.network_name_to_inx
+            .insert(raw_network_id.to_string(), network_id);
+        self.dataset.fares_v2.networks.push(Network {
+            network_id,
+            ..Default::default()
+        });
+        network_id
+    }
+
+    fn add_fare_media(&mut self, media: RawFareMedia) -> Result<(), FieldError> {
+        let fare_media_type = FareMediaType::from_gtfs(&media.fare_media_type)
+            .ok_or_else(|| FieldError::invalid("fare_media_type", &media.fare_media_type))?;
+        let fare_media_id = self.dataset.fares_v2.fare_media.len();
+        self.fare_media_name_to_inx
+            .insert(media.fare_media_id, fare_media_id);
+        self.dataset.fares_v2.fare_media.push(FareMedia {
+            fare_media_id,
+            fare_media_name: media.fare_media_name,
+            fare_media_type,
+        });
+        Ok(())
+    }
+
+    /// A product can have several rows, one for each rider category and fare media.
+    fn add_fare_product(&mut self, product: RawFareProduct) -> Result<(), FieldError> {
+        // Negative for discounts, but never NaN or infinite: amounts are compared.
+        let amount = product
+            .amount
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|amount| amount.is_finite())
+            .ok_or_else(|| FieldError::invalid("amount", &product.amount))?;
+        let fare_media_id = lookup_optional(
+            &self.fare_media_name_to_inx,
+            "fare_media_id",
+            &product.fare_media_id,
+        )?;
+        let price = FareProductPrice {
+            rider_category_id: product.rider_category_id.trim().to_string(),
+            fare_media_id,
+            amount,
+            currency: product.currency.trim().to_string(),
+        };
+
+        let fare_product_name = product.fare_product_name;
+        let products = &mut self.dataset.fares_v2.fare_products;
+        let fare_product_id = *self
+            .fare_product_name_to_inx
+            .entry(product.fare_product_id)
+            .or_insert_with(|| {
+                products.push(FareProduct {
+                    fare_product_id: products.len(),
+                    fare_product_name,
+                    prices: vec![],
+                });
+                products.len() - 1
+            });
+        products[fare_product_id].prices.push(price);
+        Ok(())
+    }
+
+    fn create_fare_leg_rule(&self, rule: &RawFareLegRule) -> Result<FareLegRule, FieldError> {
+        let areas = &self.area_name_to_inx;
+        Ok(FareLegRule {
+            leg_group_id: Some(rule.leg_group_id.trim().to_string()).filter(|g| !g.is_empty()),
+            network_id: lookup_optional(&self.network_name_to_inx, "network_id", &rule.network_id)?,
+            from_area_id: lookup_optional(areas, "from_area_id", &rule.from_area_id)?,
+            to_area_id: lookup_optional(areas, "to_area_id", &rule.to_area_id)?,
+            fare_product_id: lookup(
+                &self.fare_product_name_to_inx,
+                "fare_product_id",
+                &rule.fare_product_id,
+            )?,
+            rule_priority: parse_optional("rule_priority", &rule.rule_priority, |&p: &i32| p >= 0)?,
+        })
+    }
+
+    fn create_fare_transfer_rule(
+        &self,
+        rule: &RawFareTransferRule,
+    ) -> Result<FareTransferRule, FieldError> {
+        let group = |id: &str| Some(id.trim().to_string()).filter(|g| !g.is_empty());
+        let duration_limit =
+            parse_optional("duration_limit", &rule.duration_limit, |&d: &i64| d > 0)?;
+        // The type is required only with a limit.
+        let duration_limit_type = match (duration_limit, rule.duration_limit_type.trim()) {
+            (None, "") => DurationLimitType::default(),
+            (_, value) => DurationLimitType::from_gtfs(value).ok_or_else(|| {
+                FieldError::invalid("duration_limit_type", &rule.duration_limit_type)
+            })?,
+        };
+        Ok(FareTransferRule {
+            from_leg_group_id: group(&rule.from_leg_group_id),
+            to_leg_group_id: group(&rule.to_leg_group_id),
+            transfer_count: parse_optional("transfer_count", &rule.transfer_count, |&c: &i32| {
+                c == -1 || c >= 1
+            })?,
+            duration_limit,
+            duration_limit_type,
+            fare_transfer_type: FareTransferType::from_gtfs(&rule.fare_transfer_type).ok_or_else(
+                || FieldError::invalid("fare_transfer_type", &rule.fare_transfer_type),
+            )?,
+            fare_product_id: lookup_optional(
+                &self.fare_product_name_to_inx,
+                "fare_product_id",
+                &rule.fare_product_id,
+            )?,
+        })
+    }
+
     fn assign_stop_times_to_routes(&mut self) {
         let ds = &mut self.dataset;
 
@@ -475,19 +1588,55 @@ impl RawParser {
         &self,
         stop_times_in_construction: StopTimesInConstruction,
     ) -> StopTimeInConstruction {
-        let raw_stop_times = stop_times_in_construction.stop_times;
+        let mut raw_stop_times = stop_times_in_construction
+            .stop_times
+            .into_iter()
+            .map(|row| {
+                let stop_sequence = row.value.stop_sequence.trim().parse::<u32>().unwrap();
+                (stop_sequence, row)
+            })
+            .collect::<Vec<(u32, Row<RawStopTime>)>>();
+        raw_stop_times.sort_by_key(|(stop_sequence, _)| *stop_sequence);
         let trip_id = stop_times_in_construction.trip_id;
         assert!(!raw_stop_times.is_empty());
-        let start_time = str_time_to_seconds(&raw_stop_times[0].arrival_time);
+        let stop_ids = raw_stop_times
+            .iter()
+            .map(|(_, row)| *self.stop_name_to_inx.get(&row.value.stop_id).unwrap())
+            .collect_vec();
+        let times = raw_stop_times
+            .iter()
+            .map(|(_, row)| arrival_and_departure(&row.value))
+            .collect_vec();
+        let times = if times.iter().all(Option::is_some) {
+            times.into_iter().flatten().collect_vec()
+        } else {
+            let positions = stop_ids
+                .iter()
+                .map(|&stop_id| self.dataset.get_stop(stop_id).stop_pos.clone())
+                .collect_vec();
+            interpolate_missing_times(&times, &positions)
+        };
+        let start_time = times[0].0;
         let stop_times = raw_stop_times
-            .par_iter_if_possible()
-            .map(|st| {
-                let stop_id = *self.stop_name_to_inx.get(&st.stop_id).unwrap();
-                StopTime {
-                    stop_id,
-                    time: str_time_to_seconds(&st.arrival_time) - start_time,
-                }
-            })
+            .iter()
+            .zip(stop_ids)
+            .zip(times)
+            .map(
+                |(((stop_sequence, row), stop_id), (arrival_time, departure_time))| {
+                    let st = &row.value;
+                    StopTime {
+                        stop_id,
+                        arrival_time: arrival_time - start_time,
+                        departure_time: departure_time - start_time,
+                        stop_sequence: *stop_sequence,
+                        pickup_type: PickupDropOffType::from_gtfs(&st.pickup_type).unwrap(),
+                        drop_off_type: PickupDropOffType::from_gtfs(&st.drop_off_type).unwrap(),
+                        shape_dist_traveled: parse_shape_dist_traveled(&st.shape_dist_traveled)
+                            .ok()
+                            .flatten(),
+                    }
+                },
+            )
             .collect::<Vec<StopTime>>();
 
         StopTimeInConstruction {
@@ -518,9 +1667,12 @@ impl RawParser {
         new_id
     }
 
-    fn parse_shape(&mut self, path: &Path) {
-        let shape_path = Path::new(&path).join(Path::new("shapes.txt"));
-        let raw_shapes: Vec<RawShape> = parse_gtfs(&shape_path).expect("Raw shape parsing");
+    /// `shapes.txt` is optional: trips without a shape get one from `add_synthetic_shapes`.
+    fn parse_shape(&mut self, feed: &FeedSource) -> Result<(), ParseError> {
+        let raw_shapes: Vec<RawShape> = match self.read_rows(feed, "shapes.txt")? {
+            Some(rows) => rows.into_iter().map(|row| row.value).collect(),
+            None => return Ok(()),
+        };
 
         let grouped_shapes = raw_shapes.iter().group_by(|l| &l.shape_id[..]);
 
@@ -530,6 +1682,7 @@ impl RawParser {
                 id: shape_id.to_string(),
                 raw_shapes: vals.collect(),
                 points: vec![],
+                dist_traveled: vec![],
             })
             .collect();
 
@@ -541,115 +1694,423 @@ impl RawParser {
                 raw_shapes: vec![],
                 points: sh
                     .raw_shapes
-                    .into_iter()
-                    .map(|v: &RawShape| to_coordinates(&v.shape_pt_lat, &v.shape_pt_lon))
+                    .iter()
+                    .map(|v: &&RawShape| to_coordinates(&v.shape_pt_lat, &v.shape_pt_lon))
                     .collect::<Vec<LatLng>>(),
+                // Kept only if all the points have a valid one.
+                dist_traveled: sh
+                    .raw_shapes
+                    .iter()
+                    .map(|v| {
+                        parse_shape_dist_traveled(&v.shape_dist_traveled)
+                            .ok()
+                            .flatten()
+                    })
+                    .collect::<Option<Vec<f64>>>()
+                    .unwrap_or_default(),
             })
             .collect::<Vec<ShapeInConstruction>>();
 
-        with_points.into_iter().for_each(|s| self.add_shape(s))
+        with_points.into_iter().for_each(|s| self.add_shape(s));
+        Ok(())
     }
 
     fn add_shape(&mut self, shape: ShapeInConstruction) {
         let number_of_shapes = self.dataset.shapes.len();
-        self.shape_name_to_inx.insert(shape.id, number_of_shapes);
+        self.shape_name_to_inx
+            .insert(shape.id.clone(), number_of_shapes);
         self.dataset.shapes.push(Shape {
             shape_id: number_of_shapes,
+            source_id: shape.id,
+            dataset_index: self.dataset_index,
             points: shape.points,
+            dist_traveled: shape.dist_traveled,
+            synthetic: false,
         })
     }
 
-    fn parse_routes(&mut self, path: &Path) {
-        let routes_path = Path::new(&path).join(Path::new("routes.txt"));
-        let raw_routes: Vec<RawRoute> = parse_gtfs(&routes_path).expect("Raw routes parsing");
-        assert!(!raw_routes.is_empty());
-        for route in raw_routes {
-            self.add_route(route);
+    /// Gives to the trips without `shape_id` a shape through the positions of their stops.
+    /// Trips with the same `StopTimes` share it, trips without stop times get none.
+    fn add_synthetic_shapes(&mut self) {
+        let trips_without_shape = std::mem::take(&mut self.trips_without_shape);
+        for (trip_id, stop_times_id) in trips_without_shape {
+            let stop_times_id = match stop_times_id {
+                Some(stop_times_id) => stop_times_id,
+                None => continue,
+            };
+            let shape_id = match self.synthetic_shapes.get(&stop_times_id) {
+                Some(&shape_id) => shape_id,
+                None => {
+                    let ds = &self.dataset;
+                    let points = ds
+                        .get_stop_times(stop_times_id)
+                        .stop_times
+                        .iter()
+                        .map(|st| ds.get_stop(st.stop_id).stop_pos.clone())
+                        .collect();
+                    let shape_id = ds.shapes.len();
+                    self.dataset.shapes.push(Shape {
+                        shape_id,
+                        source_id: String::new(),
+                        dataset_index: self.dataset_index,
+                        points,
+                        dist_traveled: vec![],
+                        synthetic: true,
+                    });
+                    self.synthetic_shapes.insert(stop_times_id, shape_id);
+                    shape_id
+                }
+            };
+            self.dataset.trips[trip_id].shape_id = shape_id;
+        }
+    }
+
+    fn parse_routes(&mut self, feed: &FeedSource) -> Result<(), ParseError> {
+        let raw_routes: Vec<Row<RawRoute>> = self.read_required_rows(feed, "routes.txt")?;
+        for row in raw_routes {
+            if let Err(e) = self.add_route(row.value) {
+                self.report(e.into_parse_error(feed, "routes.txt", row.line))?;
+            }
         }
+        Ok(())
     }
 
-    fn add_route(&mut self, route: RawRoute) {
+    /// `translations.txt` is optional.
+    fn parse_translations(&mut self, feed: &FeedSource) -> Result<(), ParseError> {
+        let raw_translations: Vec<Row<RawTranslation>> =
+            match self.read_rows(feed, "translations.txt")? {
+                Some(rows) => rows,
+                None => return Ok(()),
+            };
+        for row in raw_translations {
+            match self.create_translation(&row.value) {
+                Ok(Some(key)) => self.translations.push((key, row.value.translation)),
+                Ok(None) => {}
+                Err(e) => self.report(e.into_parse_error(feed, "translations.txt", row.line))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// `None` for the tables that are not kept (`attributions` and `pathways`), and for the
+    /// translations of single stop times, that are only looked up by `field_value`.
+    fn create_translation(
+        &self,
+        translation: &RawTranslation,
+    ) -> Result<Option<TranslationKey>, FieldError> {
+        let table = match translation.table_name.trim() {
+            "attributions" | "pathways" => return Ok(None),
+            table_name => TranslationTable::from_gtfs(table_name)
+                .ok_or_else(|| FieldError::invalid("table_name", &translation.table_name))?,
+        };
+        let raw_record_id = translation.record_id.trim();
+        let record_id = match table {
+            // `feed_info.txt` has no ids: the translation is for the feed being parsed.
+            TranslationTable::FeedInfo => Some(self.dataset_index as usize - 1),
+            _ if raw_record_id.is_empty() => None,
+            TranslationTable::StopTimes => return Ok(None),
+            TranslationTable::Agency => Some(lookup(
+                &self.agency_name_to_inx,
+                "record_id",
+                raw_record_id,
+            )?),
+            TranslationTable::Stops => {
+                Some(lookup(&self.stop_name_to_inx, "record_id", raw_record_id)?)
+            }
+            TranslationTable::Routes => Some(lookup(
+                &self.routes_name_to_inx,
+                "record_id",
+                raw_record_id,
+            )?),
+            TranslationTable::Trips => {
+                Some(lookup(&self.trip_name_to_inx, "record_id", raw_record_id)?)
+            }
+            TranslationTable::Levels => {
+                Some(lookup(&self.level_name_to_inx, "record_id", raw_record_id)?)
+            }
+        };
+        let field_value = match translation.field_value.as_str() {
+            "" => None,
+            value => Some(value.to_string()),
+        };
+        // Either the record or the value to translate.
+        match (record_id, &field_value) {
+            (None, None) => return Err(FieldError::invalid("record_id", &translation.record_id)),
+            (Some(_), Some(_)) => {
+                return Err(FieldError::invalid("field_value", &translation.field_value))
+            }
+            _ => {}
+        }
+        Ok(Some(TranslationKey {
+            table,
+            field_name: translation.field_name.trim().to_string(),
+            language: translation.language.trim().to_string(),
+            record_id,
+            field_value,
+        }))
+    }
+
+    /// `feed_info.txt` is optional: without it, all the fields are empty.
+    /// Only the first row is used, as the file should have only one.
+    fn parse_feed_info(
+        &mut self,
+        feed: &FeedSource,
+        dataset_index: u64,
+    ) -> Result<FeedInfo, ParseError> {
+        let row = match self.read_rows::<RawFeedInfo>(feed, "feed_info.txt")? {
+            Some(rows) => rows.into_iter().next(),
+            None => None,
+        };
+        let feed_info = match row {
+            Some(row) => match RawParser::create_feed_info(row.value, dataset_index) {
+                Ok(feed_info) => Some(feed_info),
+                Err(e) => {
+                    self.report(e.into_parse_error(feed, "feed_info.txt", row.line))?;
+                    None
+                }
+            },
+            None => None,
+        };
+        let mut feed_info = feed_info.unwrap_or(FeedInfo {
+            dataset_index,
+            ..Default::default()
+        });
+        if feed_info.feed_version.is_empty() {
+            feed_info.content_hash = feed.content_hash().ok();
+        }
+        Ok(feed_info)
+    }
+
+    fn create_feed_info(
+        feed_info: RawFeedInfo,
+        dataset_index: u64,
+    ) -> Result<FeedInfo, FieldError> {
+        let date = |field, value: &str| match value.trim() {
+            "" => Ok(None),
+            trimmed => GtfsTime::try_from_date(trimmed)
+                .map(Some)
+                .ok_or_else(|| FieldError::invalid(field, value)),
+        };
+        let feed_start_date = date("feed_start_date", &feed_info.feed_start_date)?;
+        let feed_end_date = date("feed_end_date", &feed_info.feed_end_date)?;
+        if let (Some(start), Some(end)) = (&feed_start_date, &feed_end_date) {
+            if end < start {
+                return Err(FieldError::invalid(
+                    "feed_end_date",
+                    &feed_info.feed_end_date,
+                ));
+            }
+        }
+        Ok(FeedInfo {
+            dataset_index,
+            feed_publisher_name: feed_info.feed_publisher_name,
+            feed_publisher_url: feed_info.feed_publisher_url,
+            feed_lang: feed_info.feed_lang,
+            default_lang: feed_info.default_lang,
+            feed_start_date,
+            feed_end_date,
+            feed_version: feed_info.feed_version,
+            feed_contact_email: feed_info.feed_contact_email,
+            feed_contact_url: feed_info.feed_contact_url,
+            content_hash: None,
+        })
+    }
+
+    /// `agency.txt` is required by the specification, but feeds without it are still accepted.
+    fn parse_agencies(&mut self, feed: &FeedSource) -> Result<(), ParseError> {
+        let raw_agencies = self
+            .read_rows::<RawAgency>(feed, "agency.txt")?
+            .unwrap_or_default();
+        for row in raw_agencies {
+            if let Err(e) = self.add_agency(row.value) {
+                self.report(e.into_parse_error(feed, "agency.txt", row.line))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn add_agency(&mut self, agency: RawAgency) -> Result<(), FieldError> {
+        let agency_timezone = agency
+            .agency_timezone
+            .trim()
+            .parse::<Tz>()
+            .map_err(|_| FieldError::invalid("agency_timezone", &agency.agency_timezone))?;
+        let agency_id = self.dataset.agencies.len();
+        self.agency_name_to_inx
+            .insert(agency.agency_id.clone(), agency_id);
+        self.dataset.agencies.push(Agency {
+            agency_id,
+            source_id: agency.agency_id,
+            agency_name: agency.agency_name,
+            agency_url: agency.agency_url,
+            agency_timezone,
+            agency_lang: agency.agency_lang,
+            agency_phone: agency.agency_phone,
+            agency_fare_url: agency.agency_fare_url,
+            routes: Default::default(),
+            dataset_index: self.dataset_index,
+        });
+        Ok(())
+    }
+
+    /// `agency_id` can be omitted when the feed has only one agency.
+    fn route_agency(&self, raw_agency_id: &str) -> Result<Option<usize>, FieldError> {
+        if !raw_agency_id.is_empty() {
+            return lookup(&self.agency_name_to_inx, "agency_id", raw_agency_id).map(Some);
+        }
+        let feed_agencies = self
+            .dataset
+            .agencies
+            .iter()
+            .filter(|a| a.dataset_index == self.dataset_index)
+            .map(|a| a.agency_id)
+            .collect::<Vec<usize>>();
+        match feed_agencies.as_slice() {
+            [agency_id] => Ok(Some(*agency_id)),
+            _ => Ok(None),
+        }
+    }
+
+    fn add_route(&mut self, route: RawRoute) -> Result<(), FieldError> {
+        let agency_id = self.route_agency(&route.agency_id)?;
+        let route_type = RouteType::from_gtfs(&route.route_type)
+            .ok_or_else(|| FieldError::invalid("route_type", &route.route_type))?;
+        let route_color = parse_color("route_color", &route.route_color, "FFFFFF")?;
+        let route_text_color = parse_color("route_text_color", &route.route_text_color, "000000")?;
         let number_of_routes = self.dataset.routes.len();
         self.routes_name_to_inx
-            .insert(route.route_id, number_of_routes);
+            .insert(route.route_id.clone(), number_of_routes);
+        if !route.network_id.is_empty() {
+            let network_id = self.get_or_add_network(&route.network_id);
+            self.dataset.fares_v2.networks[network_id]
+                .route_ids
+                .insert(number_of_routes);
+        }
+        if let Some(agency_id) = agency_id {
+            self.dataset.agencies[agency_id]
+                .routes
+                .insert(number_of_routes);
+        }
         self.dataset.routes.push(Route {
             route_id: number_of_routes,
+            source_id: route.route_id,
+            agency_id,
             route_short_name: route.route_short_name,
             route_long_name: route.route_long_name,
+            route_desc: route.route_desc,
+            route_type,
+            route_url: route.route_url,
+            route_color,
+            route_text_color,
             trips: vec![],
             stop_times: Default::default(),
             dataset_index: self.dataset_index,
-        })
+        });
+        Ok(())
     }
 
-    fn parse_services(&mut self, path: &Path) {
-        let services_path = Path::new(&path).join(Path::new("calendar.txt"));
-        let services_exceptions_path = Path::new(&path).join(Path::new("calendar_dates.txt"));
-        let raw_services: Vec<RawService> = parse_gtfs(&services_path).unwrap_or_else(|_| {
-            println!("calendar.txt not found!");
-            vec![]
-        });
-        let raw_services_exceptions: Vec<RawServiceException> =
-            parse_gtfs(&services_exceptions_path).unwrap_or_else(|_| {
+    fn parse_services(&mut self, feed: &FeedSource) -> Result<(), ParseError> {
+        let raw_services: Vec<Row<RawService>> =
+            self.read_rows(feed, "calendar.txt")?.unwrap_or_else(|| {
+                println!("calendar.txt not found!");
+                vec![]
+            });
+        let raw_services_exceptions: Vec<Row<RawServiceException>> = self
+            .read_rows(feed, "calendar_dates.txt")?
+            .unwrap_or_else(|| {
                 println!("calendar_dates.txt not found!");
                 vec![]
             });
 
-        for service in &raw_services {
-            let this_exceptions = raw_services_exceptions
-                .iter()
-                .filter(|e| e.service_id == service.service_id)
-                .cloned()
-                .collect();
+        let mut exceptions_per_service: HashMap<String, Vec<ServiceException>> = HashMap::new();
+        // Services that are only in calendar_dates.txt, in order of appearance.
+        let mut exception_only_services: Vec<String> = vec![];
+        for row in raw_services_exceptions {
+            let e = row.value;
+            let date = match GtfsTime::try_from_date(&e.date) {
+                Some(date) => date,
+                None => {
+                    let error = FieldError::invalid("date", &e.date);
+                    self.report(error.into_parse_error(feed, "calendar_dates.txt", row.line))?;
+                    continue;
+                }
+            };
+            let running = match e.exception_type.as_str() {
+                "1" => true,
+                "2" => false,
+                _ => {
+                    let error = FieldError::invalid("exception_type", &e.exception_type);
+                    self.report(error.into_parse_error(feed, "calendar_dates.txt", row.line))?;
+                    continue;
+                }
+            };
+            let exceptions = exceptions_per_service
+                .entry(e.service_id.clone())
+                .or_insert_with(|| {
+                    exception_only_services.push(e.service_id.clone());
+                    vec![]
+                });
+            exceptions.push(ServiceException { date, running });
+        }
 
-            self.add_service(service, this_exceptions);
+        for row in &raw_services {
+            let service = &row.value;
+            let exceptions = exceptions_per_service
+                .remove(&service.service_id)
+                .unwrap_or_default();
+            if let Err(e) = self.add_service(service, exceptions) {
+                self.report(e.into_parse_error(feed, "calendar.txt", row.line))?;
+            }
         }
-        // Add remaining exceptions without a service in calendar.txt
 
-        let service_ids = raw_services
-            .iter()
-            .map(|s| s.service_id.clone())
-            .collect::<HashSet<String>>();
-        for exception in raw_services_exceptions {
-            if service_ids.contains(&exception.service_id) {
-                continue;
-            }
-            self.add_service(
-                &RawService {
-                    service_id: exception.service_id.clone(),
-                    monday: "0".to_string(),
-                    tuesday: "0".to_string(),
-                    wednesday: "0".to_string(),
-                    thursday: "0".to_string(),
-                    friday: "0".to_string(),
-                    saturday: "0".to_string(),
-                    sunday: "0".to_string(),
-                    start_date: "19700101".to_string(),
-                    end_date: "19700101".to_string(),
-                },
-                vec![exception],
-            );
+        // Add remaining exceptions without a service in calendar.txt
+        for service_id in exception_only_services {
+            let exceptions = match exceptions_per_service.remove(&service_id) {
+                Some(exceptions) => exceptions,
+                None => continue, // Already added from calendar.txt
+            };
+            let service = RawService {
+                service_id,
+                monday: "0".to_string(),
+                tuesday: "0".to_string(),
+                wednesday: "0".to_string(),
+                thursday: "0".to_string(),
+                friday: "0".to_string(),
+                saturday: "0".to_string(),
+                sunday: "0".to_string(),
+                start_date: "19700101".to_string(),
+                end_date: "19700101".to_string(),
+            };
+            self.add_service(&service, exceptions)
+                .unwrap_or_else(|_| unreachable!("Default service dates are valid"));
         }
+        Ok(())
     }
 
-    fn add_service(&mut self, service: &RawService, exceptions: Vec<RawServiceException>) {
+    fn add_service(
+        &mut self,
+        service: &RawService,
+        exceptions: Vec<ServiceException>,
+    ) -> Result<(), FieldError> {
+        let start_date = GtfsTime::try_from_date(&service.start_date)
+            .ok_or_else(|| FieldError::invalid("start_date", &service.start_date))?;
+        let end_date = GtfsTime::try_from_date(&service.end_date)
+            .ok_or_else(|| FieldError::invalid("end_date", &service.end_date))?;
+
         let number_of_services = self.dataset.services.len();
         self.service_name_to_inx
             .insert(service.service_id.clone(), number_of_services);
 
         self.dataset.services.push(Service {
             service_id: number_of_services,
+            source_id: service.service_id.clone(),
+            dataset_index: self.dataset_index,
             days: self.generate_service_days(&service),
-            start_date: GtfsTime::from_date(&service.start_date),
-            end_date: GtfsTime::from_date(&service.end_date),
-            exceptions: exceptions
-                .into_iter()
-                .map(|e| ServiceException {
-                    date: GtfsTime::from_date(&e.date),
-                    running: e.exception_type == "1",
-                })
-                .collect(),
-        })
+            start_date,
+            end_date,
+            exceptions,
+        });
+        Ok(())
     }
 
     fn generate_service_days(&self, service: &RawService) -> Vec<bool> {
@@ -665,51 +2126,64 @@ impl RawParser {
         days.into_iter().map(|d| d == "1").collect::<Vec<bool>>()
     }
 
-    fn parse_trips(&mut self, path: &Path) {
-        let trips_path = Path::new(&path).join(Path::new("trips.txt"));
-        let raw_trips: Vec<RawTrip> = parse_gtfs(&trips_path).expect("Raw trips parsing");
-        assert!(!raw_trips.is_empty());
-        for trip in raw_trips {
-            self.add_trip(trip);
+    fn parse_trips(&mut self, feed: &FeedSource) -> Result<(), ParseError> {
+        let raw_trips: Vec<Row<RawTrip>> = self.read_required_rows(feed, "trips.txt")?;
+        for row in raw_trips {
+            if let Err(e) = self.add_trip(row.value) {
+                self.report(e.into_parse_error(feed, "trips.txt", row.line))?;
+            }
         }
+        Ok(())
     }
 
-    fn add_trip(&mut self, trip: RawTrip) {
+    fn add_trip(&mut self, trip: RawTrip) -> Result<(), FieldError> {
+        let route_id = *self
+            .routes_name_to_inx
+            .get(&trip.route_id)
+            .ok_or_else(|| FieldError::unknown("route_id", &trip.route_id))?;
+        let service_id = lookup(&self.service_name_to_inx, "service_id", &trip.service_id)?;
         let number_of_trips = self.dataset.trips.len();
+        // Replaced in `add_synthetic_shapes` when the trip has no shape. Feeds without
+        // `shapes.txt` often still fill `shape_id`, so unknown ids are handled the same way.
+        let shape_id = match self.shape_name_to_inx.get(&trip.shape_id) {
+            Some(&shape_id) => shape_id,
+            None => {
+                self.trips_without_shape.insert(number_of_trips, None);
+                0
+            }
+        };
         self.trip_name_to_inx
             .insert(trip.trip_id.clone(), number_of_trips);
         let trip_id = number_of_trips;
-        let route_id = *self.routes_name_to_inx.get(&trip.route_id).unwrap();
-        let shape_id = *self.shape_name_to_inx.get(&trip.shape_id).unwrap();
-        let service_id_val = self.service_name_to_inx.get(&trip.service_id);
-        let service_id: Option<usize> = if let Some(s_id) = service_id_val {
-            Some(*s_id)
-        } else {
-            error!("Shape id not found: {}, trip: {:?}", trip.service_id, trip);
-            None
-        };
 
         self.dataset.trips.push(Trip {
             route_id,
             shape_id,
             trip_id,
+            source_id: trip.trip_id,
+            dataset_index: self.dataset_index,
             stop_times_id: 0,
             start_time: 0,
-            service_id,
+            service_id: Some(service_id),
             trip_headsign: trip.trip_headsign,
             trip_short_name: trip.trip_short_name,
             direction_id: trip.direction_id,
             block_id: trip.block_id,
             wheelchair_accessible: trip.wheelchair_accessible,
+            frequencies: vec![],
         });
 
         let route_associated: &mut Route = self.dataset.routes.get_mut(route_id as usize).unwrap();
         route_associated.trips.push(trip_id);
+        Ok(())
     }
 
     fn try_parse_walk_paths(&mut self) {
-        for path in self.paths.clone() {
-            let path = Path::new(&path);
+        for feed in self.sources.clone() {
+            let path = match feed.path() {
+                Some(path) => path,
+                None => continue,
+            };
             if path.parent().is_some() {
                 let walk_file_path = path.parent().unwrap().join("stop_distances_by_walk.txt");
                 if walk_file_path.exists() {
@@ -5,16 +5,21 @@ pub struct TripRealTimePositionData {
     trip_id: TripId,
     shape: Shape,
     time_at_shape_point_seconds: Vec<i64>,
+    /// Same as `time_at_shape_point_seconds`, except for the points where the vehicle dwells at a
+    /// stop: there, this is when it leaves.
+    departure_at_shape_point_seconds: Vec<i64>,
 }
 
 /// `TripRealTimePositionData` is used to show the trips in movement on the map,
 /// in real time.
 impl TripRealTimePositionData {
     pub fn new(ds: &GtfsData, trip_id: TripId) -> TripRealTimePositionData {
+        let (arrivals, departures) = calculate_time_at_each_path_point(ds, trip_id);
         TripRealTimePositionData {
             trip_id,
             shape: ds.get_shape(ds.get_trip(trip_id).shape_id).clone(),
-            time_at_shape_point_seconds: calculate_time_at_each_path_point(ds, trip_id),
+            time_at_shape_point_seconds: arrivals,
+            departure_at_shape_point_seconds: departures,
         }
     }
     pub fn get_position(&self, seconds_since_midnight: i64) -> LatLng {
@@ -33,21 +38,24 @@ impl TripRealTimePositionData {
             }
         };
 
-        if start_point == 0 {
+        if start_point == 0 && seconds_since_midnight <= self.time_at_shape_point_seconds[0] {
             return points[0].clone();
         }
         if start_point == points.len() - 1 {
             return points.last().unwrap().clone();
         }
+        // Still waiting at the stop
+        let departure = self.departure_at_shape_point_seconds[start_point];
+        if seconds_since_midnight <= departure {
+            return points[start_point].clone();
+        }
         assert!(start_point < points.len() - 1);
         let att = &points[start_point];
         let succ = &points[start_point + 1];
 
-        // seconds after the initial_point
-        let att_time = seconds_since_midnight - self.time_at_shape_point_seconds[start_point];
-        let delta_time_ = self.time_at_shape_point_seconds[start_point + 1]
-            - self.time_at_shape_point_seconds[start_point]
-            + 1;
+        // seconds after leaving the initial_point
+        let att_time = seconds_since_midnight - departure;
+        let delta_time_ = self.time_at_shape_point_seconds[start_point + 1] - departure + 1;
 
         let dx = succ.lat - att.lat;
         let dy = succ.lng - att.lng;
@@ -75,32 +83,35 @@ fn create_cumulative_distances(points: &[LatLng]) -> Vec<u64> {
     cum_dist
 }
 
-fn calculate_time_at_each_path_point(ds: &GtfsData, trip_id: TripId) -> Vec<i64> {
+/// Returns the time the vehicle reaches each point of the shape, and the time it leaves it.
+fn calculate_time_at_each_path_point(ds: &GtfsData, trip_id: TripId) -> (Vec<i64>, Vec<i64>) {
     let trip = ds.get_trip(trip_id);
     let shape = ds.get_shape(trip.shape_id).clone();
     let points = &shape.points;
     let cum_dist = create_cumulative_distances(&shape.points);
     let stop_times = ds.get_stop_times(trip.stop_times_id);
+    let stop_points = ds.get_stop_points_in_shape(trip.shape_id, stop_times);
 
     let mut time_at_shape_point = vec![0; points.len()];
-    time_at_shape_point[0] = stop_times.stop_times[0].time + trip.start_time;
+    let mut departure_at_shape_point = vec![0; points.len()];
+    let first_stop_time = &stop_times.stop_times[0];
+    time_at_shape_point[0] = first_stop_time.arrival_offset_with_trip(trip.start_time);
+    departure_at_shape_point[0] = first_stop_time.departure_offset_with_trip(trip.start_time);
 
     assert_eq!(cum_dist.len(), points.len());
     assert_eq!(time_at_shape_point.len(), points.len());
 
     let mut shape_inx = 1;
     let mut prec_stop_inx_in_shape = 0;
-    let mut prec_time = time_at_shape_point[0];
+    let mut prec_time = departure_at_shape_point[0];
 
     // let's iterate all the stops, skipping the first. Each time we set the times of all the
     // shape points until the one of the current stop.
     // prec_stop_inx_in_shape ----------------------> next_stop ----(next for iteration)---> ...
-    //     (prec_time)                               (next_time)
+    //     (prec_time, departure)                    (next_time, arrival)
     for (ith, next_stop_time) in stop_times.stop_times.iter().enumerate().skip(1) {
-        let (mut next_time, next_stop) = (
-            next_stop_time.time + trip.start_time,
-            ds.get_stop(next_stop_time.stop_id),
-        );
+        let mut next_time = next_stop_time.arrival_offset_with_trip(trip.start_time);
+        let next_departure = next_stop_time.departure_offset_with_trip(trip.start_time);
 
         // There is no time difference between this stop and the previous one
         // (time needed would be 0, speed infinite)
@@ -114,13 +125,13 @@ fn calculate_time_at_each_path_point(ds: &GtfsData, trip_id: TripId) -> Vec<i64>
             }
         }
 
-        // let's find the nearest shape point to the stop position
-        let stop_inx_in_shape =
-            nearest_point_index(points, &next_stop.stop_pos, prec_stop_inx_in_shape);
+        let stop_inx_in_shape = stop_points[ith].max(prec_stop_inx_in_shape);
         let stops_delta_dist =
             (cum_dist[stop_inx_in_shape] - cum_dist[prec_stop_inx_in_shape]) as f64;
-        // maybe 2 repeated stops
+        // maybe 2 repeated stops: the vehicle keeps waiting there.
         if stops_delta_dist == 0.0 {
+            departure_at_shape_point[prec_stop_inx_in_shape] = next_departure;
+            prec_time = next_departure;
             continue;
         }
         let stops_delta_time = (next_time - prec_time) as f64;
@@ -131,8 +142,8 @@ fn calculate_time_at_each_path_point(ds: &GtfsData, trip_id: TripId) -> Vec<i64>
         /* Now, let's find a time for each shape point between these two stops */
         while shape_inx <= stop_inx_in_shape {
             let att_dist = (cum_dist[shape_inx] - cum_dist[prec_stop_inx_in_shape]) as f64;
-            time_at_shape_point[shape_inx] = time_at_shape_point[prec_stop_inx_in_shape]
-                + (att_dist / speed_between_stops) as i64;
+            time_at_shape_point[shape_inx] = prec_time + (att_dist / speed_between_stops) as i64;
+            departure_at_shape_point[shape_inx] = time_at_shape_point[shape_inx];
             shape_inx += 1;
         }
         assert_eq!(shape_inx, stop_inx_in_shape + 1);
@@ -140,21 +151,9 @@ fn calculate_time_at_each_path_point(ds: &GtfsData, trip_id: TripId) -> Vec<i64>
         // due to float rounding, the destination might be missing by a few seconds. In this way,
         // we do not propagate the error.
         time_at_shape_point[stop_inx_in_shape] = next_time;
+        departure_at_shape_point[stop_inx_in_shape] = next_departure.max(next_time);
         prec_stop_inx_in_shape = stop_inx_in_shape;
-        prec_time = next_time;
-    }
-    time_at_shape_point
-}
-
-fn nearest_point_index(points: &[LatLng], target: &LatLng, start_from: usize) -> usize {
-    let mut best_dist = u64::MAX;
-    let mut best_inx = start_from;
-    for (i, p) in points.iter().skip(start_from).enumerate() {
-        let d = p.distance_meters(target);
-        if d < best_dist {
-            best_dist = d;
-            best_inx = i;
-        }
+        prec_time = departure_at_shape_point[stop_inx_in_shape];
     }
-    best_inx + start_from
+    (time_at_shape_point, departure_at_shape_point)
 }
@@ -0,0 +1,190 @@
+use std::collections::BTreeSet;
+
+use chrono::{Datelike, Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::gtfs_data::{Service, ServiceId};
+
+const WORD_BITS: usize = 64;
+
+/// Days expanded after the last `start_date` or exception of the services. Farther `end_date`s,
+/// as the `20991231` of the services running "until further notice", are not expanded.
+const HORIZON_DAYS: i64 = 2 * 366;
+
+/// The dates on which each service runs, expanded at load time from `calendar.txt` (days of the
+/// week between `start_date` and `end_date`) and `calendar_dates.txt` (exceptions).
+/// For each date there is a bitset of the services running on it, so both `is_active` and
+/// `services_on` don't depend on the number of exceptions.
+/// The dates after `HORIZON_DAYS` have no exceptions: they are computed from the days of the week
+/// and the `end_date` of each service.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ServiceCalendar {
+    first_date: NaiveDate,
+    days: usize,
+    words_per_day: usize,
+    bits: Vec<u64>,
+    /// The days of the week and the `end_date` of each service, for the dates not expanded.
+    weeks: Vec<(Vec<bool>, NaiveDate)>,
+}
+
+impl Default for ServiceCalendar {
+    fn default() -> Self {
+        ServiceCalendar {
+            first_date: NaiveDate::from_ymd(1970, 1, 1),
+            days: 0,
+            words_per_day: 0,
+            bits: vec![],
+            weeks: vec![],
+        }
+    }
+}
+
+impl ServiceCalendar {
+    /// `services[i]` must have `service_id == i`, as in `GtfsData::services`.
+    pub fn new(services: &[Service]) -> ServiceCalendar {
+        let horizon = services
+            .iter()
+            .flat_map(|s| {
+                let start_date = Some(s.start_date.service_date()).filter(|_| runs_weekly(s));
+                start_date
+                    .into_iter()
+                    .chain(s.exceptions.iter().map(|e| e.date.service_date()))
+            })
+            .max()
+            .map(|date| date + Duration::days(HORIZON_DAYS));
+        let horizon = match horizon {
+            Some(horizon) => horizon,
+            None => return ServiceCalendar::default(),
+        };
+        let running_dates = services
+            .iter()
+            .map(|s| running_dates(s, horizon))
+            .collect::<Vec<_>>();
+        let first_date = running_dates.iter().filter_map(|d| d.iter().next()).min();
+        let last_date = running_dates
+            .iter()
+            .filter_map(|d| d.iter().next_back())
+            .max();
+        let (first_date, last_date) = match (first_date, last_date) {
+            (Some(&first_date), Some(&last_date)) => (first_date, last_date),
+            _ => return ServiceCalendar::default(),
+        };
+
+        // Up to the last exception, even if it removes a date, so that the dates after are
+        // computed only from the days of the week.
+        let last_exception = services
+            .iter()
+            .flat_map(|s| s.exceptions.iter().map(|e| e.date.service_date()))
+            .max();
+        let last_date = last_exception.map_or(last_date, |date| date.max(last_date));
+
+        let words_per_day = services.len() / WORD_BITS + 1;
+        let days = (last_date - first_date).num_days() as usize + 1;
+        let weeks = services
+            .iter()
+            .map(|s| (s.days.clone(), s.end_date.service_date()))
+            .collect();
+        let mut calendar = ServiceCalendar {
+            first_date,
+            days,
+            words_per_day,
+            bits: vec![0; days * words_per_day],
+            weeks,
+        };
+        for (service_id, dates) in running_dates.iter().enumerate() {
+            for &date in dates {
+                let day = calendar.day_index(date).unwrap();
+                calendar.bits[day * words_per_day + service_id / WORD_BITS] |=
+                    1 << (service_id % WORD_BITS);
+            }
+        }
+        calendar
+    }
+
+    /// Whether `service_id` runs on the service date `date`.
+    pub fn is_active(&self, service_id: ServiceId, date: NaiveDate) -> bool {
+        if service_id >= self.words_per_day * WORD_BITS {
+            return false;
+        }
+        match self.day_index(date) {
+            Some(day) => {
+                let word = self.bits[day * self.words_per_day + service_id / WORD_BITS];
+                word & (1 << (service_id % WORD_BITS)) != 0
+            }
+            None => self.runs_after_expanded_dates(service_id, date),
+        }
+    }
+
+    /// The services running on the service date `date`, sorted by id.
+    pub fn services_on(&self, date: NaiveDate) -> Vec<ServiceId> {
+        let day = match self.day_index(date) {
+            Some(day) => day,
+            None => {
+                return (0..self.weeks.len())
+                    .filter(|&service_id| self.runs_after_expanded_dates(service_id, date))
+                    .collect()
+            }
+        };
+        let words = &self.bits[day * self.words_per_day..(day + 1) * self.words_per_day];
+        words
+            .iter()
+            .enumerate()
+            .flat_map(|(inx, &word)| {
+                (0..WORD_BITS)
+                    .filter(move |bit| word & (1 << bit) != 0)
+                    .map(move |bit| inx * WORD_BITS + bit)
+            })
+            .collect()
+    }
+
+    /// For the dates after the expanded ones, only the days of the week and the `end_date` count.
+    fn runs_after_expanded_dates(&self, service_id: ServiceId, date: NaiveDate) -> bool {
+        let after_expanded =
+            self.days > 0 && date >= self.first_date + Duration::days(self.days as i64);
+        match self.weeks.get(service_id) {
+            Some((days, end_date)) if after_expanded && date <= *end_date => days
+                .get(date.weekday().num_days_from_monday() as usize)
+                .copied()
+                .unwrap_or(false),
+            _ => false,
+        }
+    }
+
+    fn day_index(&self, date: NaiveDate) -> Option<usize> {
+        let day = (date - self.first_date).num_days();
+        if day < 0 || day >= self.days as i64 {
+            None
+        } else {
+            Some(day as usize)
+        }
+    }
+}
+
+fn runs_weekly(service: &Service) -> bool {
+    service.days.iter().any(|&d| d)
+}
+
+/// The days of the week of `service` in `[start_date, end_date]` (until `horizon`), plus the
+/// dates added by its exceptions, minus the removed ones.
+fn running_dates(service: &Service, horizon: NaiveDate) -> BTreeSet<NaiveDate> {
+    let mut dates = BTreeSet::new();
+    if runs_weekly(service) {
+        let end_date = service.end_date.service_date().min(horizon);
+        let mut date = service.start_date.service_date();
+        while date <= end_date {
+            if service.days[date.weekday().num_days_from_monday() as usize] {
+                dates.insert(date);
+            }
+            date = date.succ();
+        }
+    }
+    for exception in &service.exceptions {
+        let date = exception.date.service_date();
+        if exception.running {
+            dates.insert(date);
+        } else {
+            dates.remove(&date);
+        }
+    }
+    dates
+}
@@ -0,0 +1,299 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use itertools::Itertools;
+use log::info;
+
+use crate::gtfs_data::{GtfsData, LocationType, Stop, StopDistance, StopId, StopWalkTime};
+
+/// Meters in a degree of latitude, to discard far stops before computing the distance.
+const METERS_PER_LAT_DEGREE: f64 = 111_000.0;
+
+#[derive(Debug, Clone)]
+pub struct StopMergeParams {
+    /// Stops farther than this are never merged.
+    pub max_distance_meters: u64,
+    /// From 0 (any name) to 1 (the same name, ignoring case, spaces and punctuation).
+    pub min_name_similarity: f64,
+    /// When false, the stops are only put in a shared station, and can be reached walking one
+    /// from the other. When true, the trips stopping at the others stop at the first one instead.
+    pub merge: bool,
+}
+
+impl Default for StopMergeParams {
+    fn default() -> Self {
+        StopMergeParams {
+            max_distance_meters: 50,
+            min_name_similarity: 0.8,
+            merge: false,
+        }
+    }
+}
+
+/// A group of stops of different feeds that are the same physical stop.
+#[derive(Debug, Clone)]
+pub struct StopMerge {
+    /// The station the stops are now in. Created by `merge_duplicate_stops` if none of them had
+    /// one.
+    pub station_id: StopId,
+    /// Sorted. When merged, the first one is the one kept.
+    pub stop_ids: Vec<StopId>,
+    pub merged: bool,
+}
+
+impl fmt::Display for StopMerge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = if self.merged { "merged" } else { "linked" };
+        write!(
+            f,
+            "stops {:?} {} in station {}",
+            self.stop_ids, action, self.station_id
+        )
+    }
+}
+
+/// Finds the stops of different feeds near each other and with a similar name, and puts each
+/// group in a shared station. With `params.merge`, the references to the stops of a group
+/// (stop times, routes, transfers, walk times, pathways and fare areas) are moved to the first.
+/// Returns every group found.
+pub fn merge_duplicate_stops(dataset: &mut GtfsData, params: &StopMergeParams) -> Vec<StopMerge> {
+    let groups = find_duplicate_stops(dataset, params);
+    let mut merges = vec![];
+    for stop_ids in groups {
+        let station_id = match shared_station(dataset, &stop_ids) {
+            Some(station_id) => station_id,
+            None => continue,
+        };
+        for &stop_id in &stop_ids {
+            dataset.stops[stop_id].parent_station = Some(station_id);
+            dataset.stops[station_id].children.insert(stop_id);
+        }
+        if params.merge {
+            move_references(dataset, stop_ids[0], &stop_ids[1..]);
+        } else {
+            add_walk_paths(dataset, &stop_ids);
+        }
+        let merge = StopMerge {
+            station_id,
+            stop_ids,
+            merged: params.merge,
+        };
+        info!("Duplicate {}", merge);
+        merges.push(merge);
+    }
+    merges
+}
+
+/// Groups of stops (sorted) of at least two feeds, near and with similar names. A group has at
+/// most one stop of each feed: two stops of a feed near the same stop of another stay apart.
+fn find_duplicate_stops(dataset: &GtfsData, params: &StopMergeParams) -> Vec<Vec<StopId>> {
+    let candidates = dataset
+        .stops
+        .iter()
+        .filter(|s| s.location_type == LocationType::StopOrPlatform && !s.stop_pos.is_unknown())
+        .sorted_by(|a, b| a.stop_pos.lat.partial_cmp(&b.stop_pos.lat).unwrap())
+        .collect_vec();
+    let max_lat_difference = params.max_distance_meters as f64 / METERS_PER_LAT_DEGREE;
+
+    let mut parents = (0..dataset.stops.len()).collect_vec();
+    // The feeds of the stops in each group, by root.
+    let mut group_feeds = dataset
+        .stops
+        .iter()
+        .map(|s| vec![s.dataset_index])
+        .collect_vec();
+    for (inx, a) in candidates.iter().enumerate() {
+        for b in candidates[inx + 1..]
+            .iter()
+            .take_while(|b| b.stop_pos.lat - a.stop_pos.lat <= max_lat_difference)
+        {
+            if a.dataset_index != b.dataset_index && are_duplicates(a, b, params) {
+                let (root_a, root_b) = (
+                    find_root(&mut parents, a.stop_id),
+                    find_root(&mut parents, b.stop_id),
+                );
+                let shared_feed = group_feeds[root_a]
+                    .iter()
+                    .any(|feed| group_feeds[root_b].contains(feed));
+                if shared_feed {
+                    continue;
+                }
+                let (root, child) = (root_a.min(root_b), root_a.max(root_b));
+                parents[child] = root;
+                let child_feeds = std::mem::take(&mut group_feeds[child]);
+                group_feeds[root].extend(child_feeds);
+            }
+        }
+    }
+
+    let mut groups: BTreeMap<StopId, Vec<StopId>> = BTreeMap::new();
+    for stop in &candidates {
+        let root = find_root(&mut parents, stop.stop_id);
+        groups.entry(root).or_default().push(stop.stop_id);
+    }
+    groups
+        .values()
+        .map(|group| group.iter().copied().sorted().collect_vec())
+        .filter(|group| group.len() > 1)
+        .collect()
+}
+
+fn are_duplicates(a: &Stop, b: &Stop, params: &StopMergeParams) -> bool {
+    a.stop_pos.distance_meters(&b.stop_pos) <= params.max_distance_meters
+        && name_similarity(&a.stop_name, &b.stop_name) >= params.min_name_similarity
+}
+
+fn find_root(parents: &mut [StopId], stop_id: StopId) -> StopId {
+    let mut root = stop_id;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    parents[stop_id] = root;
+    root
+}
+
+/// 1 minus the edit distance between the names, lowercase and without spaces and punctuation,
+/// relative to the longest one.
+pub fn name_similarity(a: &str, b: &str) -> f64 {
+    let normalize = |name: &str| {
+        name.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect_vec()
+    };
+    let (a, b) = (normalize(a), normalize(b));
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    // Levenshtein distance, keeping only the previous row.
+    let mut previous = (0..=b.len()).collect_vec();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == cb { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    1.0 - previous[b.len()] as f64 / longest as f64
+}
+
+/// The station of the first stop of `group` having one, or a new one at the first stop.
+/// `None` if the stops are already in different stations.
+fn shared_station(dataset: &mut GtfsData, group: &[StopId]) -> Option<StopId> {
+    let stations = group
+        .iter()
+        .filter_map(|&stop_id| dataset.get_stop(stop_id).parent_station)
+        .unique()
+        .collect_vec();
+    match stations.as_slice() {
+        [] => {}
+        [station_id] => return Some(*station_id),
+        _ => return None,
+    }
+    let first = dataset.get_stop(group[0]);
+    let station_id = dataset.stops.len();
+    let station = Stop {
+        stop_id: station_id,
+        stop_name: first.stop_name.clone(),
+        stop_pos: first.stop_pos.clone(),
+        location_type: LocationType::Station,
+        stop_timezone: first.stop_timezone,
+        ..Default::default()
+    };
+    // `walk_times` is indexed by stop, when there is one for each.
+    if dataset.walk_times.len() == dataset.stops.len() {
+        dataset.walk_times.push(StopWalkTime {
+            stop_id: station_id,
+            near_stops: vec![],
+        });
+    }
+    dataset.stops.push(station);
+    Some(station_id)
+}
+
+/// Lets to walk between each pair of `stop_ids`, if they didn't already.
+fn add_walk_paths(dataset: &mut GtfsData, stop_ids: &[StopId]) {
+    if dataset.walk_times.len() != dataset.stops.len() {
+        return;
+    }
+    for (&from, &to) in stop_ids.iter().tuple_combinations() {
+        let distance_meters = dataset
+            .get_stop(from)
+            .stop_pos
+            .distance_meters(&dataset.get_stop(to).stop_pos) as usize;
+        for &(a, b) in &[(from, to), (to, from)] {
+            let near_stops = &mut dataset.walk_times[a].near_stops;
+            if near_stops.iter().all(|sd| sd.stop_id != b) {
+                near_stops.push(StopDistance {
+                    stop_id: b,
+                    distance_meters,
+                });
+            }
+        }
+    }
+}
+
+/// Makes everything referring to one of `others` refer to `kept` instead.
+fn move_references(dataset: &mut GtfsData, kept: StopId, others: &[StopId]) {
+    let replacement = others
+        .iter()
+        .map(|&other| (other, kept))
+        .collect::<HashMap<StopId, StopId>>();
+    let replace = |stop_id: &mut StopId| {
+        if let Some(&new_stop_id) = replacement.get(stop_id) {
+            *stop_id = new_stop_id;
+        }
+    };
+
+    for stop_times in &mut dataset.stop_times {
+        stop_times
+            .stop_times
+            .iter_mut()
+            .for_each(|st| replace(&mut st.stop_id));
+    }
+    for transfer in &mut dataset.transfers {
+        replace(&mut transfer.from_stop_id);
+        replace(&mut transfer.to_stop_id);
+    }
+    for pathway in &mut dataset.pathways {
+        replace(&mut pathway.from_stop_id);
+        replace(&mut pathway.to_stop_id);
+    }
+    for area in &mut dataset.fares_v2.areas {
+        area.stop_ids = area
+            .stop_ids
+            .iter()
+            .map(|&stop_id| *replacement.get(&stop_id).unwrap_or(&stop_id))
+            .collect();
+    }
+    for &other in others {
+        let routes = std::mem::take(&mut dataset.stops[other].routes);
+        let pathways = std::mem::take(&mut dataset.stops[other].pathways);
+        dataset.stops[kept].routes.extend(routes);
+        dataset.stops[kept].pathways.extend(pathways);
+    }
+
+    if dataset.walk_times.len() != dataset.stops.len() {
+        return;
+    }
+    for &other in others {
+        let near_stops = std::mem::take(&mut dataset.walk_times[other].near_stops);
+        dataset.walk_times[kept].near_stops.extend(near_stops);
+    }
+    for walk_time in &mut dataset.walk_times {
+        let from = walk_time.stop_id;
+        for sd in &mut walk_time.near_stops {
+            replace(&mut sd.stop_id);
+        }
+        // The nearest of the merged stops is kept.
+        walk_time.near_stops = std::mem::take(&mut walk_time.near_stops)
+            .into_iter()
+            .filter(|sd| sd.stop_id != from)
+            .sorted_by_key(|sd| (sd.stop_id, sd.distance_meters))
+            .dedup_by(|a, b| a.stop_id == b.stop_id)
+            .sorted_by_key(|sd| sd.distance_meters)
+            .collect();
+    }
+}
@@ -0,0 +1,519 @@
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::NaiveDate;
+use geo::algorithm::contains::Contains;
+use geo::{LineString, Polygon};
+use itertools::Itertools;
+
+use crate::gtfs_data::{
+    Agency, AgencyId, FareAttribute, GtfsData, GtfsTime, LatLng, LocationType, Route, RouteId,
+    RouteType, Service, Shape, Stop, StopDistance, StopId, StopTime, StopTimes, StopWalkTime,
+    Transfer, Trip,
+};
+use crate::pathways::{Level, Pathway};
+use crate::service_calendar::ServiceCalendar;
+use crate::translations::TranslationTable;
+
+/// An area on the map, for `SubsetFilter::region`.
+#[derive(Debug, Clone)]
+pub enum Region {
+    /// The corners with the minimum and the maximum latitude and longitude.
+    BoundingBox { min: LatLng, max: LatLng },
+    /// The vertices of the polygon, in order. It doesn't need to be closed.
+    Polygon(Vec<LatLng>),
+}
+
+impl Region {
+    pub fn contains(&self, pos: &LatLng) -> bool {
+        match self {
+            Region::BoundingBox { min, max } => {
+                (min.lat..=max.lat).contains(&pos.lat) && (min.lng..=max.lng).contains(&pos.lng)
+            }
+            Region::Polygon(vertices) => {
+                let exterior = vertices.iter().map(|v| (v.lng, v.lat)).collect_vec();
+                Polygon::new(LineString::from(exterior), vec![]).contains(&pos.as_point())
+            }
+        }
+    }
+}
+
+/// What `GtfsData::subset` keeps. The trips kept satisfy all the conditions given: `None` and
+/// empty sets don't filter.
+#[derive(Debug, Clone, Default)]
+pub struct SubsetFilter {
+    /// Trips are cut to their stops inside, and dropped if less than two are.
+    pub region: Option<Region>,
+    /// Trips whose service runs on no date between `start_date` and `end_date` are dropped,
+    /// and the services are cut to those dates.
+    pub start_date: Option<GtfsTime>,
+    pub end_date: Option<GtfsTime>,
+    pub route_ids: BTreeSet<RouteId>,
+    pub agency_ids: BTreeSet<AgencyId>,
+    /// A basic type also selects the extended types of the same kind, see `RouteType::basic`.
+    pub route_types: Vec<RouteType>,
+}
+
+impl SubsetFilter {
+    fn has_dates(&self) -> bool {
+        self.start_date.is_some() || self.end_date.is_some()
+    }
+
+    fn selects_route(&self, route: &Route) -> bool {
+        (self.route_ids.is_empty() || self.route_ids.contains(&route.route_id))
+            && (self.agency_ids.is_empty()
+                || route
+                    .agency_id
+                    .map_or(false, |a| self.agency_ids.contains(&a)))
+            && (self.route_types.is_empty()
+                || self.route_types.contains(&route.route_type)
+                || self.route_types.contains(&route.route_type.basic()))
+    }
+
+    fn includes_date(&self, date: NaiveDate) -> bool {
+        self.start_date
+            .as_ref()
+            .map_or(true, |start| start.service_date() <= date)
+            && self
+                .end_date
+                .as_ref()
+                .map_or(true, |end| date <= end.service_date())
+    }
+}
+
+/// The new index of each element of the old vector, `None` for the ones dropped.
+/// The elements kept stay in the same order.
+struct Reindex {
+    new_ids: Vec<Option<usize>>,
+}
+
+impl Reindex {
+    fn new(old_len: usize, kept: impl IntoIterator<Item = usize>) -> Reindex {
+        let mut is_kept = vec![false; old_len];
+        kept.into_iter().for_each(|id| is_kept[id] = true);
+        let mut next_id = 0..;
+        let new_ids = is_kept
+            .into_iter()
+            .map(|kept| if kept { next_id.next() } else { None })
+            .collect();
+        Reindex { new_ids }
+    }
+
+    fn get(&self, id: usize) -> Option<usize> {
+        self.new_ids[id]
+    }
+
+    /// The new id of an element that is known to be kept.
+    fn kept(&self, id: usize) -> usize {
+        self.new_ids[id].unwrap()
+    }
+
+    /// `Some(None)` for `None`, `None` if `id` was dropped.
+    fn optional(&self, id: Option<usize>) -> Option<Option<usize>> {
+        match id {
+            Some(id) => self.get(id).map(Some),
+            None => Some(None),
+        }
+    }
+
+    fn set(&self, ids: &BTreeSet<usize>) -> BTreeSet<usize> {
+        ids.iter().filter_map(|&id| self.get(id)).collect()
+    }
+
+    /// The elements of `old` kept, in order.
+    fn keep<T: Clone>(&self, old: &[T]) -> Vec<T> {
+        old.iter()
+            .zip(&self.new_ids)
+            .filter(|(_, new_id)| new_id.is_some())
+            .map(|(element, _)| element.clone())
+            .collect()
+    }
+}
+
+impl GtfsData {
+    /// A new dataset with only the trips selected by `filter` and what they use: routes,
+    /// agencies, stops (with their stations, entrances and levels), shapes, services, walk
+    /// times, transfers, pathways, fares and translations. The ids are given again from 0,
+    /// in the same order; the source ids are kept.
+    pub fn subset(&self, filter: &SubsetFilter) -> GtfsData {
+        let stops_inside = self
+            .stops
+            .iter()
+            .map(|s| {
+                filter
+                    .region
+                    .as_ref()
+                    .map_or(true, |region| region.contains(&s.stop_pos))
+            })
+            .collect_vec();
+        let running_services = self
+            .services
+            .iter()
+            .map(|s| !filter.has_dates() || self.runs_on_some_date(s, filter))
+            .collect_vec();
+
+        let mut trips: Vec<Trip> = vec![];
+        let mut stop_times: Vec<StopTimes> = vec![];
+        let mut stop_times_ids: HashMap<Vec<StopTime>, usize> = HashMap::new();
+        let mut kept_trips = vec![];
+        for trip in &self.trips {
+            let service_running = match trip.service_id {
+                Some(service_id) => running_services[service_id],
+                None => !filter.has_dates(),
+            };
+            if !service_running || !filter.selects_route(self.get_route(trip.route_id)) {
+                continue;
+            }
+            let trip_stop_times = self.get_stop_times(trip.stop_times_id).stop_times.iter();
+            let (inside, shift) =
+                match cut_stop_times(trip_stop_times, |stop_id| stops_inside[stop_id]) {
+                    Some(cut) => cut,
+                    None => continue,
+                };
+            let stop_times_id = *stop_times_ids.entry(inside.clone()).or_insert_with(|| {
+                stop_times.push(StopTimes {
+                    stop_times_id: stop_times.len(),
+                    stop_times: inside,
+                });
+                stop_times.len() - 1
+            });
+            let mut trip = trip.clone();
+            trip.stop_times_id = stop_times_id;
+            trip.start_time += shift;
+            for frequency in &mut trip.frequencies {
+                frequency.start_time += shift;
+                frequency.end_time += shift;
+            }
+            kept_trips.push(trip.trip_id);
+            trips.push(trip);
+        }
+
+        let trip_ids = Reindex::new(self.trips.len(), kept_trips);
+        let route_ids = Reindex::new(self.routes.len(), trips.iter().map(|t| t.route_id));
+        let shape_ids = Reindex::new(self.shapes.len(), trips.iter().map(|t| t.shape_id));
+        let service_ids = Reindex::new(
+            self.services.len(),
+            trips.iter().filter_map(|t| t.service_id),
+        );
+        let stop_ids = Reindex::new(self.stops.len(), self.stops_needed_by(&stop_times));
+        let agency_ids = Reindex::new(
+            self.agencies.len(),
+            self.routes
+                .iter()
+                .filter(|r| route_ids.get(r.route_id).is_some())
+                .filter_map(|r| r.agency_id),
+        );
+        let level_ids = Reindex::new(
+            self.levels.len(),
+            self.stops
+                .iter()
+                .filter(|s| stop_ids.get(s.stop_id).is_some())
+                .filter_map(|s| s.level_id),
+        );
+
+        for trip in &mut trips {
+            trip.trip_id = trip_ids.kept(trip.trip_id);
+            trip.route_id = route_ids.kept(trip.route_id);
+            trip.shape_id = shape_ids.kept(trip.shape_id);
+            trip.service_id = trip.service_id.map(|s| service_ids.kept(s));
+        }
+        for stop_time in stop_times
+            .iter_mut()
+            .flat_map(|st| st.stop_times.iter_mut())
+        {
+            stop_time.stop_id = stop_ids.kept(stop_time.stop_id);
+        }
+
+        let mut routes = route_ids.keep(&self.routes);
+        for route in &mut routes {
+            route.route_id = route_ids.kept(route.route_id);
+            route.agency_id = route.agency_id.map(|a| agency_ids.kept(a));
+            route.trips = vec![];
+            route.stop_times = BTreeSet::new();
+        }
+        for trip in &trips {
+            let route = &mut routes[trip.route_id];
+            route.trips.push(trip.trip_id);
+            route.stop_times.insert(trip.stop_times_id);
+        }
+
+        let mut agencies: Vec<Agency> = agency_ids.keep(&self.agencies);
+        for agency in &mut agencies {
+            agency.agency_id = agency_ids.kept(agency.agency_id);
+            agency.routes = route_ids.set(&agency.routes);
+        }
+
+        let mut shapes: Vec<Shape> = shape_ids.keep(&self.shapes);
+        for shape in &mut shapes {
+            shape.shape_id = shape_ids.kept(shape.shape_id);
+        }
+
+        let mut services: Vec<Service> = service_ids.keep(&self.services);
+        for service in &mut services {
+            service.service_id = service_ids.kept(service.service_id);
+            cut_service(service, filter);
+        }
+
+        let mut levels: Vec<Level> = level_ids.keep(&self.levels);
+        for level in &mut levels {
+            level.level_id = level_ids.kept(level.level_id);
+        }
+
+        let pathways = self
+            .pathways
+            .iter()
+            .filter_map(|p| {
+                Some(Pathway {
+                    from_stop_id: stop_ids.get(p.from_stop_id)?,
+                    to_stop_id: stop_ids.get(p.to_stop_id)?,
+                    ..p.clone()
+                })
+            })
+            .enumerate()
+            .map(|(pathway_id, p)| Pathway { pathway_id, ..p })
+            .collect_vec();
+
+        let mut stops: Vec<Stop> = stop_ids.keep(&self.stops);
+        for stop in &mut stops {
+            stop.stop_id = stop_ids.kept(stop.stop_id);
+            stop.parent_station = stop.parent_station.and_then(|p| stop_ids.get(p));
+            stop.children = stop_ids.set(&stop.children);
+            stop.level_id = stop.level_id.map(|l| level_ids.kept(l));
+            stop.pathways = BTreeSet::new();
+            stop.routes = BTreeSet::new();
+        }
+        for pathway in &pathways {
+            stops[pathway.from_stop_id]
+                .pathways
+                .insert(pathway.pathway_id);
+            stops[pathway.to_stop_id]
+                .pathways
+                .insert(pathway.pathway_id);
+        }
+        for route in &routes {
+            for &stop_times_id in &route.stop_times {
+                for stop_time in &stop_times[stop_times_id].stop_times {
+                    stops[stop_time.stop_id].routes.insert(route.route_id);
+                }
+            }
+        }
+
+        // `walk_times` is indexed by stop, when there is one for each.
+        let walk_times = if self.walk_times.len() == self.stops.len() {
+            stop_ids
+                .keep(&self.walk_times)
+                .into_iter()
+                .map(|walk_time| StopWalkTime {
+                    stop_id: stop_ids.kept(walk_time.stop_id),
+                    near_stops: walk_time
+                        .near_stops
+                        .iter()
+                        .filter_map(|sd| {
+                            Some(StopDistance {
+                                stop_id: stop_ids.get(sd.stop_id)?,
+                                distance_meters: sd.distance_meters,
+                            })
+                        })
+                        .collect(),
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+
+        let transfers = self
+            .transfers
+            .iter()
+            .filter_map(|t| {
+                Some(Transfer {
+                    from_stop_id: stop_ids.get(t.from_stop_id)?,
+                    to_stop_id: stop_ids.get(t.to_stop_id)?,
+                    from_route_id: route_ids.optional(t.from_route_id)?,
+                    to_route_id: route_ids.optional(t.to_route_id)?,
+                    from_trip_id: trip_ids.optional(t.from_trip_id)?,
+                    to_trip_id: trip_ids.optional(t.to_trip_id)?,
+                    ..t.clone()
+                })
+            })
+            .collect();
+
+        // A fare whose rules were all for dropped routes would apply to every ride.
+        let fares = self
+            .fares
+            .iter()
+            .filter_map(|fare| {
+                let rules = fare
+                    .rules
+                    .iter()
+                    .filter_map(|rule| {
+                        let mut rule = rule.clone();
+                        rule.route_id = route_ids.optional(rule.route_id)?;
+                        Some(rule)
+                    })
+                    .collect_vec();
+                if rules.is_empty() && !fare.rules.is_empty() {
+                    return None;
+                }
+                Some(FareAttribute {
+                    rules,
+                    ..fare.clone()
+                })
+            })
+            .enumerate()
+            .map(|(fare_id, fare)| FareAttribute { fare_id, ..fare })
+            .collect();
+
+        let mut fares_v2 = self.fares_v2.clone();
+        for area in &mut fares_v2.areas {
+            area.stop_ids = stop_ids.set(&area.stop_ids);
+        }
+        for network in &mut fares_v2.networks {
+            network.route_ids = route_ids.set(&network.route_ids);
+        }
+
+        let translations = self
+            .translations
+            .remap_records(|table, record_id| match table {
+                TranslationTable::Agency => agency_ids.get(record_id),
+                TranslationTable::Stops => stop_ids.get(record_id),
+                TranslationTable::Routes => route_ids.get(record_id),
+                TranslationTable::Trips => trip_ids.get(record_id),
+                TranslationTable::Levels => level_ids.get(record_id),
+                TranslationTable::StopTimes | TranslationTable::FeedInfo => Some(record_id),
+            });
+
+        let mut subset = GtfsData {
+            dataset_id: self.dataset_id,
+            feeds: self.feeds.clone(),
+            agencies,
+            routes,
+            trips,
+            shapes,
+            stops,
+            service_calendar: ServiceCalendar::new(&services),
+            services,
+            stop_times,
+            walk_times,
+            transfers,
+            pathways,
+            levels,
+            fares,
+            fares_v2,
+            translations,
+            source_ids: HashMap::new(),
+        };
+        subset.index_source_ids();
+        subset
+    }
+
+    /// Whether `service` runs on a date of the window of `filter`.
+    fn runs_on_some_date(&self, service: &Service, filter: &SubsetFilter) -> bool {
+        let exception_dates = service.exceptions.iter().map(|e| e.date.service_date());
+        let first = exception_dates
+            .clone()
+            .chain(Some(service.start_date.service_date()))
+            .min()
+            .unwrap();
+        let last = exception_dates
+            .chain(Some(service.end_date.service_date()))
+            .max()
+            .unwrap();
+        let mut date = filter
+            .start_date
+            .as_ref()
+            .map_or(first, |start| start.service_date().max(first));
+        while date <= last && filter.includes_date(date) {
+            if self.service_calendar.is_active(service.service_id, date) {
+                return true;
+            }
+            date = date.succ();
+        }
+        false
+    }
+
+    /// The stops of `stop_times`, their stations, and the entrances, generic nodes and boarding
+    /// areas of those stations, to keep their pathways.
+    fn stops_needed_by(&self, stop_times: &[StopTimes]) -> BTreeSet<StopId> {
+        let mut needed = BTreeSet::new();
+        for stop_time in stop_times.iter().flat_map(|st| st.stop_times.iter()) {
+            let mut stop_id = Some(stop_time.stop_id);
+            while let Some(id) = stop_id {
+                if !needed.insert(id) {
+                    break;
+                }
+                stop_id = self.get_stop(id).parent_station;
+            }
+        }
+        let in_needed_station = |stop: &Stop| {
+            let mut parent = stop.parent_station;
+            while let Some(parent_id) = parent {
+                if needed.contains(&parent_id) {
+                    return true;
+                }
+                parent = self.get_stop(parent_id).parent_station;
+            }
+            false
+        };
+        let station_nodes = self
+            .stops
+            .iter()
+            .filter(|s| {
+                s.location_type != LocationType::StopOrPlatform
+                    && s.location_type != LocationType::Station
+            })
+            .filter(|s| in_needed_station(s))
+            .map(|s| s.stop_id)
+            .collect_vec();
+        needed.extend(station_nodes);
+        needed
+    }
+}
+
+/// The longest run of consecutive stop times at stops for which `inside` is true (the first of
+/// them on ties), and the seconds the trip now starts later. A trip that leaves the area and
+/// comes back would otherwise jump over the stops outside. `None` if the run has less than two
+/// stops.
+fn cut_stop_times<'a>(
+    stop_times: impl Iterator<Item = &'a StopTime>,
+    inside: impl Fn(StopId) -> bool,
+) -> Option<(Vec<StopTime>, i64)> {
+    let stop_times = stop_times.collect_vec();
+    let mut inside = stop_times
+        .split(|st| !inside(st.stop_id))
+        .rev()
+        .max_by_key(|run| run.len())?
+        .iter()
+        .map(|&st| st.clone())
+        .collect_vec();
+    if inside.len() < 2 {
+        return None;
+    }
+    // The times are relative to the first stop.
+    let shift = inside[0].arrival_time;
+    for stop_time in &mut inside {
+        stop_time.arrival_time -= shift;
+        stop_time.departure_time -= shift;
+    }
+    Some((inside, shift))
+}
+
+/// Limits `service` to the dates of `filter`. When its regular days are all outside of them, only
+/// the exceptions are kept.
+fn cut_service(service: &mut Service, filter: &SubsetFilter) {
+    if let Some(start) = &filter.start_date {
+        if service.start_date.service_date() < start.service_date() {
+            service.start_date = start.clone();
+        }
+    }
+    if let Some(end) = &filter.end_date {
+        if end.service_date() < service.end_date.service_date() {
+            service.end_date = end.clone();
+        }
+    }
+    if service.end_date.service_date() < service.start_date.service_date() {
+        service.days = vec![false; 7];
+        service.end_date = service.start_date.clone();
+    }
+    service
+        .exceptions
+        .retain(|e| filter.includes_date(e.date.service_date()));
+}
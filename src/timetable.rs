@@ -76,7 +76,8 @@ impl TimeTable {
                 if st.stop_id == target_stop_id {
                     times -= 1;
                     if times == 0 {
-                        target_time = Some(GtfsTime::new_from_midnight(st.time + trip.start_time));
+                        // Seconds since midnight of the service day: can be after `24:00:00`.
+                        target_time = Some(st.departure_offset_with_trip(trip.start_time) as usize);
                         break;
                     }
                 }
@@ -85,21 +86,58 @@ impl TimeTable {
             *past_appearence.entry(target_stop_id).or_insert(0) += 1;
         }
         results
+    }
+
+    /// The names of `stops` in `language`, falling back to the original ones.
+    pub fn get_stop_names<'a>(&self, ds: &'a GtfsData, language: &str) -> Vec<&'a str> {
+        self.stops
             .iter()
-            .map(|time| {
-                if let Some(t) = time {
-                    Some(t.since_midnight() as usize)
+            .map(|&stop_id| {
+                ds.get_stop(stop_id)
+                    .stop_name_in(&ds.translations, language)
+            })
+            .collect()
+    }
+
+    /// Frequency based trips don't have a single time at each stop: `get_column` returns the times
+    /// of the first run, and this describes the windows, e.g. `every 10 min (07:00-09:00)`.
+    pub fn get_frequency_description(&self, ds: &GtfsData, trip_id: TripId) -> Option<String> {
+        assert!(self.trips.contains(&trip_id));
+        let trip = ds.get_trip(trip_id);
+        if !trip.is_frequency_based() {
+            return None;
+        }
+        let hh_mm = |seconds: i64| format!("{:02}:{:02}", seconds / 3600, seconds % 3600 / 60);
+        let description = trip
+            .frequencies
+            .iter()
+            .map(|f| {
+                let headway = if f.headway_secs % 60 == 0 {
+                    format!("{} min", f.headway_secs / 60)
                 } else {
-                    None
-                }
+                    format!("{} s", f.headway_secs)
+                };
+                format!(
+                    "every {} ({}-{})",
+                    headway,
+                    hh_mm(f.start_time),
+                    hh_mm(f.end_time)
+                )
             })
-            .collect_vec()
+            .join(", ");
+        Some(description)
     }
 
+    /// The trips of the service date of `date`, including the ones running after midnight.
+    /// The service date is the one of `date` in the timezone of each trip's route.
     pub fn get_trips_active_on_date(&self, dataset: &GtfsData, date: &GtfsTime) -> Vec<TripId> {
         self.trips
             .iter()
-            .filter(|&&t| dataset.is_trip_id_active_on_time(t, date, Some(24 * 60 * 60)))
+            .filter(|&&t| {
+                let trip = dataset.get_trip(t);
+                let date = date.in_timezone(dataset.get_route_timezone(trip.route_id));
+                dataset.is_service_active_on_day(trip, &date)
+            })
             .sorted_by_key(|&&t| dataset.get_trip(t).start_time)
             .copied()
             .collect_vec()
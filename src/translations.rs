@@ -0,0 +1,155 @@
+use serde::{Deserialize, Serialize};
+
+/// Values of `table_name` in `translations.txt` whose records can be translated here.
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum TranslationTable {
+    Agency,
+    Stops,
+    Routes,
+    Trips,
+    StopTimes,
+    Levels,
+    FeedInfo,
+}
+
+impl Default for TranslationTable {
+    fn default() -> Self {
+        TranslationTable::Stops
+    }
+}
+
+impl TranslationTable {
+    /// `None` also for the tables of the specification that are not kept, as `attributions`.
+    pub fn from_gtfs(value: &str) -> Option<TranslationTable> {
+        match value.trim() {
+            "agency" => Some(TranslationTable::Agency),
+            "stops" => Some(TranslationTable::Stops),
+            "routes" => Some(TranslationTable::Routes),
+            "trips" => Some(TranslationTable::Trips),
+            "stop_times" => Some(TranslationTable::StopTimes),
+            "levels" => Some(TranslationTable::Levels),
+            "feed_info" => Some(TranslationTable::FeedInfo),
+            _ => None,
+        }
+    }
+
+    pub fn to_gtfs(&self) -> &'static str {
+        match self {
+            TranslationTable::Agency => "agency",
+            TranslationTable::Stops => "stops",
+            TranslationTable::Routes => "routes",
+            TranslationTable::Trips => "trips",
+            TranslationTable::StopTimes => "stop_times",
+            TranslationTable::Levels => "levels",
+            TranslationTable::FeedInfo => "feed_info",
+        }
+    }
+}
+
+/// What a row of `translations.txt` translates: a field of a record, or a field of all the
+/// records where it has a given value.
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Default, Serialize, Deserialize, Clone)]
+pub struct TranslationKey {
+    pub table: TranslationTable,
+    pub field_name: String,
+    /// As in `translations.txt`, e.g. `en` or `de`.
+    pub language: String,
+    /// The index of the record in `GtfsData` (e.g. the `StopId` for `stops`).
+    /// `None` when the translation is for `field_value`.
+    pub record_id: Option<usize>,
+    pub field_value: Option<String>,
+}
+
+/// The content of `translations.txt`, sorted by key.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct Translations {
+    translations: Vec<(TranslationKey, String)>,
+}
+
+impl Translations {
+    /// When a key is repeated, the last translation is kept.
+    pub fn new(mut translations: Vec<(TranslationKey, String)>) -> Translations {
+        translations.reverse();
+        translations.sort_by(|(a, _), (b, _)| a.cmp(b));
+        translations.dedup_by(|(a, _), (b, _)| a == b);
+        Translations { translations }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.translations.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.translations.len()
+    }
+
+    /// All the translations, sorted by key.
+    pub fn iter(&self) -> impl Iterator<Item = &(TranslationKey, String)> {
+        self.translations.iter()
+    }
+
+    /// The translation of `field_name` of the record `record_id` of `table`, whose original text
+    /// is `original`. The translations of the record come before the ones for its value.
+    pub fn get(
+        &self,
+        table: TranslationTable,
+        field_name: &str,
+        language: &str,
+        record_id: usize,
+        original: &str,
+    ) -> Option<&str> {
+        let key = |record_id, field_value| TranslationKey {
+            table,
+            field_name: field_name.to_string(),
+            language: language.to_string(),
+            record_id,
+            field_value,
+        };
+        self.find(&key(Some(record_id), None))
+            .or_else(|| self.find(&key(None, Some(original.to_string()))))
+    }
+
+    /// Same as `get`, but falls back to `original`.
+    pub fn translate<'a>(
+        &'a self,
+        table: TranslationTable,
+        field_name: &str,
+        language: &str,
+        record_id: usize,
+        original: &'a str,
+    ) -> &'a str {
+        self.get(table, field_name, language, record_id, original)
+            .unwrap_or(original)
+    }
+
+    /// The translations with the record ids changed by `new_record_id`, without the ones for
+    /// which it returns `None`. The translations for a value are all kept.
+    pub(crate) fn remap_records(
+        &self,
+        new_record_id: impl Fn(TranslationTable, usize) -> Option<usize>,
+    ) -> Translations {
+        let translations = self
+            .translations
+            .iter()
+            .filter_map(|(key, translation)| {
+                let record_id = match key.record_id {
+                    Some(record_id) => Some(new_record_id(key.table, record_id)?),
+                    None => None,
+                };
+                let key = TranslationKey {
+                    record_id,
+                    ..key.clone()
+                };
+                Some((key, translation.clone()))
+            })
+            .collect();
+        Translations::new(translations)
+    }
+
+    fn find(&self, key: &TranslationKey) -> Option<&str> {
+        self.translations
+            .binary_search_by(|(k, _)| k.cmp(key))
+            .ok()
+            .map(|inx| self.translations[inx].1.as_str())
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::feed_source::FeedSource;
+use crate::gtfs_data::{GtfsData, GtfsTime, LocationType, Service};
+use crate::raw_models::{
+    parse_gtfs_from_source, RawRoute, RawService, RawServiceException, RawShape, RawTrip,
+};
+
+/// Checks a parsed feed, and reports everything that looks wrong in it.
+///
+/// The parser already fails on rows it can't use, but it silently accepts data that is valid csv
+/// and still wrong (e.g. stops without a position, or services that ended years ago).
+/// The `ValidationReport` lists these problems, so that a feed can be rejected before being
+/// published:
+/// ```no_run
+///     use fastgtfs::feed_source::FeedSource;
+///     use fastgtfs::raw_parser::RawParser;
+///     use fastgtfs::validation::{validate, RawFeed, ValidationParams};
+///
+///     let mut parser = RawParser::new(vec!["actv_aut".to_string()]);
+///     parser.parse();
+///     let raw = RawFeed::read(&FeedSource::from_path("actv_aut"));
+///     let report = validate(&parser.dataset, &[raw], &ValidationParams::default());
+///     for issue in report.errors() {
+///         println!("{}", issue);
+///     }
+///     assert!(!report.has_errors());
+/// ```
+pub fn validate(
+    dataset: &GtfsData,
+    raw_feeds: &[RawFeed],
+    params: &ValidationParams,
+) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    for raw in raw_feeds {
+        check_references(raw, &mut report);
+    }
+    check_stop_times(dataset, &mut report);
+    check_unused_stops(dataset, &mut report);
+    check_unused_shapes(dataset, &mut report);
+    check_stop_positions(dataset, &mut report);
+    check_services(dataset, params, &mut report);
+    report
+}
+
+#[derive(Debug, Clone)]
+pub struct ValidationParams {
+    /// The period the feed is published for. Services completely outside it are reported.
+    pub feed_start: GtfsTime,
+    pub feed_end: GtfsTime,
+}
+
+impl Default for ValidationParams {
+    /// From today, without an end.
+    fn default() -> Self {
+        ValidationParams {
+            feed_start: GtfsTime::new_from_midnight(0),
+            feed_end: GtfsTime::new_infinite(),
+        }
+    }
+}
+
+/// The raw files of a feed, used for the checks that need the original ids.
+#[derive(Debug, Default)]
+pub struct RawFeed {
+    /// Used to tell the feeds apart in the issues.
+    pub name: String,
+    pub routes: Vec<RawRoute>,
+    pub trips: Vec<RawTrip>,
+    pub shapes: Vec<RawShape>,
+    pub services: Vec<RawService>,
+    pub service_exceptions: Vec<RawServiceException>,
+}
+
+impl RawFeed {
+    /// Missing files are read as empty.
+    pub fn read(feed: &FeedSource) -> RawFeed {
+        RawFeed {
+            name: feed.to_string(),
+            routes: parse_gtfs_from_source(feed, "routes.txt").unwrap_or_default(),
+            trips: parse_gtfs_from_source(feed, "trips.txt").unwrap_or_default(),
+            shapes: parse_gtfs_from_source(feed, "shapes.txt").unwrap_or_default(),
+            services: parse_gtfs_from_source(feed, "calendar.txt").unwrap_or_default(),
+            service_exceptions: parse_gtfs_from_source(feed, "calendar_dates.txt")
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    /// The feed should not be published.
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IssueKind {
+    /// `field` of the row with id `id` in `file` points to something not defined in the feed.
+    DanglingReference {
+        feed: String,
+        file: String,
+        id: String,
+        field: String,
+        value: String,
+    },
+    /// The stop at `stop_index` is reached before leaving the previous one, or left before
+    /// being reached.
+    NonMonotonicStopTimes { trip_id: usize, stop_index: usize },
+    /// No route passes by this stop (or platform). Stations, entrances and the other locations
+    /// are never served directly.
+    UnusedStop { stop_id: usize },
+    /// No trip uses this shape.
+    UnusedShape { shape_id: usize },
+    /// The stop is at (0, 0), usually because its coordinates were missing.
+    StopWithoutPosition { stop_id: usize },
+    /// The service is never active in the feed window.
+    ServiceOutsideFeedWindow { service_id: usize },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationIssue {
+    pub severity: Severity,
+    pub kind: IssueKind,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: ", self.severity)?;
+        match &self.kind {
+            IssueKind::DanglingReference {
+                feed,
+                file,
+                id,
+                field,
+                value,
+            } => write!(
+                f,
+                "{}/{} `{}`: {} `{}` is not defined",
+                feed, file, id, field, value
+            ),
+            IssueKind::NonMonotonicStopTimes {
+                trip_id,
+                stop_index,
+            } => write!(
+                f,
+                "trip {} goes back in time at stop number {}",
+                trip_id, stop_index
+            ),
+            IssueKind::UnusedStop { stop_id } => write!(f, "stop {} has no routes", stop_id),
+            IssueKind::UnusedShape { shape_id } => write!(f, "shape {} has no trips", shape_id),
+            IssueKind::StopWithoutPosition { stop_id } => {
+                write!(f, "stop {} is at (0, 0)", stop_id)
+            }
+            IssueKind::ServiceOutsideFeedWindow { service_id } => write!(
+                f,
+                "service {} is never active in the feed window",
+                service_id
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    pub fn errors(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.with_severity(Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.with_severity(Severity::Warning)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    fn with_severity(&self, severity: Severity) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues.iter().filter(move |i| i.severity == severity)
+    }
+
+    fn add(&mut self, severity: Severity, kind: IssueKind) {
+        self.issues.push(ValidationIssue { severity, kind });
+    }
+}
+
+fn check_references(raw: &RawFeed, report: &mut ValidationReport) {
+    let route_ids = raw
+        .routes
+        .iter()
+        .map(|r| &r.route_id)
+        .collect::<HashSet<_>>();
+    let shape_ids = raw
+        .shapes
+        .iter()
+        .map(|s| &s.shape_id)
+        .collect::<HashSet<_>>();
+    let service_ids = raw
+        .services
+        .iter()
+        .map(|s| &s.service_id)
+        .chain(raw.service_exceptions.iter().map(|e| &e.service_id))
+        .collect::<HashSet<_>>();
+
+    let mut dangling = |trip: &RawTrip, field: &str, value: &str| {
+        report.add(
+            Severity::Error,
+            IssueKind::DanglingReference {
+                feed: raw.name.clone(),
+                file: "trips.txt".to_string(),
+                id: trip.trip_id.clone(),
+                field: field.to_string(),
+                value: value.to_string(),
+            },
+        )
+    };
+    for trip in &raw.trips {
+        if !route_ids.contains(&trip.route_id) {
+            dangling(trip, "route_id", &trip.route_id);
+        }
+        // shape_id is optional
+        if !trip.shape_id.is_empty() && !shape_ids.contains(&trip.shape_id) {
+            dangling(trip, "shape_id", &trip.shape_id);
+        }
+        if !service_ids.contains(&trip.service_id) {
+            dangling(trip, "service_id", &trip.service_id);
+        }
+    }
+}
+
+fn check_stop_times(dataset: &GtfsData, report: &mut ValidationReport) {
+    for trip in &dataset.trips {
+        let stop_times = &dataset.get_stop_times(trip.stop_times_id).stop_times;
+        let going_back = stop_times.iter().enumerate().position(|(inx, st)| {
+            st.departure_time < st.arrival_time
+                || (inx > 0 && st.arrival_time < stop_times[inx - 1].departure_time)
+        });
+        if let Some(stop_index) = going_back {
+            report.add(
+                Severity::Error,
+                IssueKind::NonMonotonicStopTimes {
+                    trip_id: trip.trip_id,
+                    stop_index,
+                },
+            );
+        }
+    }
+}
+
+fn check_unused_stops(dataset: &GtfsData, report: &mut ValidationReport) {
+    let unused = dataset
+        .stops
+        .iter()
+        .filter(|s| s.location_type == LocationType::StopOrPlatform && s.routes.is_empty());
+    for stop in unused {
+        report.add(
+            Severity::Warning,
+            IssueKind::UnusedStop {
+                stop_id: stop.stop_id,
+            },
+        );
+    }
+}
+
+fn check_unused_shapes(dataset: &GtfsData, report: &mut ValidationReport) {
+    let used = dataset
+        .trips
+        .iter()
+        .map(|t| t.shape_id)
+        .collect::<HashSet<usize>>();
+    for shape_id in (0..dataset.shapes.len()).filter(|id| !used.contains(id)) {
+        report.add(Severity::Warning, IssueKind::UnusedShape { shape_id });
+    }
+}
+
+fn check_stop_positions(dataset: &GtfsData, report: &mut ValidationReport) {
+    for stop in dataset.stops.iter().filter(|s| s.stop_pos.is_unknown()) {
+        report.add(
+            Severity::Error,
+            IssueKind::StopWithoutPosition {
+                stop_id: stop.stop_id,
+            },
+        );
+    }
+}
+
+fn check_services(dataset: &GtfsData, params: &ValidationParams, report: &mut ValidationReport) {
+    for service in &dataset.services {
+        if !is_service_in_window(service, &params.feed_start, &params.feed_end) {
+            report.add(
+                Severity::Warning,
+                IssueKind::ServiceOutsideFeedWindow {
+                    service_id: service.service_id,
+                },
+            );
+        }
+    }
+}
+
+fn is_service_in_window(service: &Service, start: &GtfsTime, end: &GtfsTime) -> bool {
+    let regular_days = service.days.iter().any(|&d| d);
+    let overlaps = service.start_date <= *end && service.end_date >= *start;
+    let added_day_in_window = service
+        .exceptions
+        .iter()
+        .any(|e| e.running && e.date <= *end && (e.date >= *start || e.date.is_same_day(start)));
+    (regular_days && overlaps) || added_day_in_window
+}
@@ -0,0 +1,151 @@
+//! Feeds shared by the tests that need known ids: the ones in `test_data` are real feeds.
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::Path;
+
+/// A small feed: one route with two trips, `T1` at 8:00 and `T2` at 9:00, through the stops
+/// `S1` (First), `S2` (Second), `S3` (Third) and `S4` (Fourth) along the shape `SH1`.
+/// Both trips dwell a minute at `S2`. `S1` and `S2` are in the zone `Z1`, the others in `Z2`.
+pub fn small_feed() -> Vec<(&'static str, String)> {
+    let files = [
+        (
+            "agency.txt",
+            "agency_id,agency_name,agency_url,agency_timezone,agency_lang,agency_phone\n\
+             A1,Test Transit,http://example.com,Europe/Rome,it,041 000\n",
+        ),
+        (
+            "calendar.txt",
+            "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,\
+             start_date,end_date\n\
+             WK,1,1,1,1,1,1,1,20200101,20301231\n",
+        ),
+        (
+            "calendar_dates.txt",
+            "service_id,date,exception_type\nWK,20200830,1\n",
+        ),
+        (
+            "routes.txt",
+            "route_id,agency_id,route_short_name,route_long_name,route_type,route_color,\
+             route_text_color\n\
+             R1,A1,1,Line one,3,FF0000,FFFFFF\n",
+        ),
+        (
+            "shapes.txt",
+            "shape_id,shape_pt_lat,shape_pt_lon,shape_pt_sequence\n\
+             SH1,45.4380,12.3180,1\n\
+             SH1,45.4385,12.3190,2\n\
+             SH1,45.4390,12.3200,3\n\
+             SH1,45.4395,12.3210,4\n\
+             SH1,45.4400,12.3220,5\n\
+             SH1,45.4405,12.3230,6\n\
+             SH1,45.4410,12.3240,7\n",
+        ),
+        (
+            "stop_times.txt",
+            "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+             T1,08:00:00,08:00:00,S1,1\n\
+             T1,08:05:00,08:06:00,S2,2\n\
+             T1,08:10:00,08:10:00,S3,3\n\
+             T1,08:15:00,08:15:00,S4,4\n\
+             T2,09:00:00,09:00:00,S1,1\n\
+             T2,09:05:00,09:06:00,S2,2\n\
+             T2,09:10:00,09:10:00,S3,3\n\
+             T2,09:15:00,09:15:00,S4,4\n",
+        ),
+        (
+            "stops.txt",
+            "stop_id,stop_code,stop_name,stop_lat,stop_lon,zone_id,location_type,parent_station\n\
+             S1,1,First,45.4380,12.3180,Z1,0,\n\
+             S2,2,Second,45.4390,12.3200,Z1,0,\n\
+             S3,3,Third,45.4400,12.3220,Z2,0,\n\
+             S4,4,Fourth,45.4410,12.3240,Z2,0,\n",
+        ),
+        (
+            "trips.txt",
+            "route_id,service_id,trip_id,trip_headsign,direction_id,shape_id\n\
+             R1,WK,T1,Fourth,0,SH1\n\
+             R1,WK,T2,Fourth,0,SH1\n",
+        ),
+    ];
+    files
+        .iter()
+        .map(|(name, content)| (*name, content.to_string()))
+        .collect()
+}
+
+/// Zips `small_feed` in memory, passing each of its files through `edit`.
+pub fn zip_small_feed(edit: impl Fn(&str, Vec<u8>) -> Option<Vec<u8>>) -> Vec<u8> {
+    zip_small_feed_adding_files(edit, &[])
+}
+
+/// Same as `zip_small_feed`, adding `extra_files` (name, content) to the archive.
+pub fn zip_small_feed_adding_files(
+    edit: impl Fn(&str, Vec<u8>) -> Option<Vec<u8>>,
+    extra_files: &[(&str, &str)],
+) -> Vec<u8> {
+    let files = small_feed()
+        .into_iter()
+        .map(|(name, content)| (name.to_string(), content.into_bytes()));
+    zip_files(files, edit, extra_files)
+}
+
+/// Writes the files of `small_feed` in `folder`.
+pub fn write_small_feed(folder: &Path) {
+    fs::create_dir_all(folder).unwrap();
+    for (name, content) in small_feed() {
+        fs::write(folder.join(name), content).unwrap();
+    }
+}
+
+/// Zips the `.txt` files of `folder` in memory, passing each of them through `edit`.
+pub fn zip_feed(folder: &str, edit: impl Fn(&str, Vec<u8>) -> Option<Vec<u8>>) -> Vec<u8> {
+    zip_feed_adding_files(folder, edit, &[])
+}
+
+/// Same as `zip_feed`, adding `extra_files` (name, content) to the archive.
+pub fn zip_feed_adding_files(
+    folder: &str,
+    edit: impl Fn(&str, Vec<u8>) -> Option<Vec<u8>>,
+    extra_files: &[(&str, &str)],
+) -> Vec<u8> {
+    let files = fs::read_dir(folder)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map(|e| e == "txt").unwrap_or(false))
+        .map(|path| {
+            let name = path.file_name().unwrap().to_str().unwrap().to_string();
+            (name, fs::read(&path).unwrap())
+        });
+    zip_files(files, edit, extra_files)
+}
+
+fn zip_files(
+    files: impl Iterator<Item = (String, Vec<u8>)>,
+    edit: impl Fn(&str, Vec<u8>) -> Option<Vec<u8>>,
+    extra_files: &[(&str, &str)],
+) -> Vec<u8> {
+    let mut zip_content = vec![];
+    {
+        let mut writer = zip::ZipWriter::new(Cursor::new(&mut zip_content));
+        for (name, content) in files {
+            let content = match edit(&name, content) {
+                Some(content) => content,
+                None => continue,
+            };
+            writer
+                .start_file(name, zip::write::FileOptions::default())
+                .unwrap();
+            writer.write_all(&content).unwrap();
+        }
+        for (name, content) in extra_files {
+            writer
+                .start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+    zip_content
+}
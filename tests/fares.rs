@@ -0,0 +1,376 @@
+use fastgtfs::fares::calculate_fare;
+use fastgtfs::fares_v2::{
+    calculate_fare_candidates, DurationLimitType, FareLegRule, FareProduct, FareProductPrice,
+    FareTransferRule, FaresV2, Network,
+};
+use fastgtfs::gtfs_data::{FareAttribute, FareRule, GtfsData};
+use fastgtfs::navigator_models::{BusSolutionComponent, Solution, SolutionComponent};
+use fastgtfs::test_utils::make_dataset;
+
+#[test]
+fn no_fares_no_price() {
+    let ds = make_dataset();
+    let solution = two_legs(&ds, 0);
+    assert_eq!(calculate_fare(&ds, &solution), None);
+}
+
+#[test]
+fn flat_fare_with_transfers() {
+    let mut ds = make_dataset();
+    ds.fares = vec![fare(0, 1.5, None, None, vec![])];
+
+    let fare = calculate_fare(&ds, &two_legs(&ds, 0)).unwrap();
+    assert_eq!(fare.total_price, 1.5);
+    assert_eq!(fare.currency_type, "EUR");
+    let prices = fare.legs.iter().map(|l| l.price).collect::<Vec<f64>>();
+    assert_eq!(prices, vec![1.5, 0.0]);
+    let components = fare
+        .legs
+        .iter()
+        .map(|l| l.component_index)
+        .collect::<Vec<usize>>();
+    assert_eq!(components, vec![0, 1]);
+}
+
+#[test]
+fn transfers_are_limited() {
+    let mut ds = make_dataset();
+    let hour = 60 * 60;
+    ds.fares = vec![fare(0, 2.0, Some(0), None, vec![])];
+    assert_eq!(
+        calculate_fare(&ds, &two_legs(&ds, 0)).unwrap().total_price,
+        4.0
+    );
+
+    // The second leg departs too late for the ticket bought for the first.
+    ds.fares = vec![fare(0, 2.0, None, Some(hour), vec![])];
+    assert_eq!(
+        calculate_fare(&ds, &two_legs(&ds, hour + 1))
+            .unwrap()
+            .total_price,
+        4.0
+    );
+    assert_eq!(
+        calculate_fare(&ds, &two_legs(&ds, hour - 1))
+            .unwrap()
+            .total_price,
+        2.0
+    );
+}
+
+#[test]
+fn zone_fares() {
+    let mut ds = make_dataset();
+    let solution = two_legs(&ds, 0);
+    let stop_ids = match &solution.components[0] {
+        SolutionComponent::Bus(b) => b.path.stop_times.iter().map(|st| st.stop_id).collect(),
+        _ => vec![],
+    };
+    for (inx, stop_id) in stop_ids.into_iter().enumerate() {
+        ds.stops[stop_id].zone_id = if inx == 0 { "A" } else { "B" }.to_string();
+    }
+    let zone_rule = |origin: &str, destination: &str| FareRule {
+        origin_id: Some(origin.to_string()),
+        destination_id: Some(destination.to_string()),
+        ..Default::default()
+    };
+    let contains_rule = |zone: &str| FareRule {
+        contains_id: Some(zone.to_string()),
+        ..Default::default()
+    };
+    ds.fares = vec![
+        fare(0, 3.0, None, None, vec![zone_rule("A", "B")]),
+        fare(1, 1.0, None, None, vec![zone_rule("B", "B")]),
+        fare(
+            2,
+            2.5,
+            None,
+            None,
+            vec![contains_rule("A"), contains_rule("B")],
+        ),
+    ];
+
+    // The first leg goes from A to B, the second stays in B.
+    let fare = calculate_fare(&ds, &solution).unwrap();
+    let fare_ids = fare.legs.iter().map(|l| l.fare_id).collect::<Vec<usize>>();
+    assert_eq!(fare_ids, vec![2, 2]);
+    assert_eq!(fare.total_price, 2.5);
+
+    ds.fares.pop();
+    let fare = calculate_fare(&ds, &solution).unwrap();
+    let fare_ids = fare.legs.iter().map(|l| l.fare_id).collect::<Vec<usize>>();
+    assert_eq!(fare_ids, vec![0, 0]);
+    assert_eq!(fare.total_price, 3.0);
+
+    // No fare covers a ride in zone A only.
+    ds.fares = vec![fare_with_rules(vec![zone_rule("B", "B")])];
+    assert_eq!(calculate_fare(&ds, &solution), None);
+}
+
+#[test]
+fn stops_without_zone_not_contained() {
+    let mut ds = make_dataset();
+    let solution = two_legs(&ds, 0);
+    let stop_ids = match &solution.components[0] {
+        SolutionComponent::Bus(b) => b.path.stop_times.iter().map(|st| st.stop_id).collect(),
+        _ => vec![],
+    };
+    let last = stop_ids.len() - 1;
+    for (inx, stop_id) in stop_ids.into_iter().enumerate() {
+        ds.stops[stop_id].zone_id = match inx {
+            0 => "A",
+            inx if inx == last => "",
+            _ => "B",
+        }
+        .to_string();
+    }
+    let contains_rule = |zone: &str| FareRule {
+        contains_id: Some(zone.to_string()),
+        ..Default::default()
+    };
+    ds.fares = vec![fare_with_rules(vec![
+        contains_rule("A"),
+        contains_rule("B"),
+    ])];
+    assert!(calculate_fare(&ds, &solution).is_some());
+}
+
+#[test]
+fn fare_rules_not_combined() {
+    let mut ds = make_dataset();
+    let solution = two_legs(&ds, 0);
+    let (route_id, stop_ids): (_, Vec<_>) = match &solution.components[0] {
+        SolutionComponent::Bus(b) => (
+            b.route.route_id,
+            b.path.stop_times.iter().map(|st| st.stop_id).collect(),
+        ),
+        _ => unreachable!(),
+    };
+    for (inx, stop_id) in stop_ids.into_iter().enumerate() {
+        ds.stops[stop_id].zone_id = if inx == 0 { "A" } else { "B" }.to_string();
+    }
+    let rule = |route_id, origin: Option<&str>, contains: Option<&str>| FareRule {
+        route_id: Some(route_id),
+        origin_id: origin.map(str::to_string),
+        contains_id: contains.map(str::to_string),
+        ..Default::default()
+    };
+
+    // The route of one row and the origin of the other.
+    ds.fares = vec![fare_with_rules(vec![
+        rule(route_id, Some("B"), None),
+        rule(route_id + 1, Some("A"), None),
+    ])];
+    assert_eq!(calculate_fare(&ds, &solution), None);
+    ds.fares[0].rules.push(rule(route_id, Some("A"), None));
+    assert!(calculate_fare(&ds, &solution).is_some());
+
+    // The zones of rows for different routes.
+    ds.fares = vec![fare_with_rules(vec![
+        rule(route_id, None, Some("A")),
+        rule(route_id + 1, None, Some("B")),
+    ])];
+    assert_eq!(calculate_fare(&ds, &solution), None);
+    ds.fares[0].rules.push(rule(route_id, None, Some("B")));
+    assert!(calculate_fare(&ds, &solution).is_some());
+}
+
+#[test]
+fn route_fares() {
+    let mut ds = make_dataset();
+    let solution = two_legs(&ds, 0);
+    let route_id = match &solution.components[0] {
+        SolutionComponent::Bus(b) => b.route.route_id,
+        _ => unreachable!(),
+    };
+    let route_rule = |route_id| FareRule {
+        route_id: Some(route_id),
+        ..Default::default()
+    };
+    ds.fares = vec![fare_with_rules(vec![route_rule(route_id)])];
+    assert!(calculate_fare(&ds, &solution).is_some());
+
+    ds.fares = vec![fare_with_rules(vec![route_rule(route_id + 1)])];
+    assert_eq!(calculate_fare(&ds, &solution), None);
+}
+
+#[test]
+fn no_leg_rules_no_candidates() {
+    let mut ds = make_dataset();
+    ds.fares_v2.fare_products = vec![product(0, vec![price("", None, 2.0)])];
+    assert!(calculate_fare_candidates(&ds, &two_legs(&ds, 0)).is_empty());
+}
+
+#[test]
+fn candidates_per_rider_category_and_media() {
+    let mut ds = make_dataset();
+    ds.fares_v2 = FaresV2 {
+        fare_products: vec![product(
+            0,
+            vec![
+                price("", None, 2.0),
+                price("", Some(0), 1.6),
+                price("child", None, 1.0),
+            ],
+        )],
+        fare_leg_rules: vec![leg_rule(None, 0)],
+        ..Default::default()
+    };
+
+    let candidates = calculate_fare_candidates(&ds, &two_legs(&ds, 0));
+    let summary = candidates
+        .iter()
+        .map(|c| (c.rider_category_id.as_str(), c.fare_media_id, c.total_price))
+        .collect::<Vec<(&str, Option<usize>, f64)>>();
+    assert_eq!(
+        summary,
+        vec![
+            ("", Some(0), 3.2),
+            ("", None, 4.0),
+            ("child", None, 2.0),
+            ("child", Some(0), 2.0),
+        ]
+    );
+    assert_eq!(candidates[0].legs.len(), 2);
+    assert!(candidates[0].transfers.is_empty());
+}
+
+#[test]
+fn fare_transfer_rules() {
+    let mut ds = make_dataset();
+    let hour = 60 * 60;
+    ds.fares_v2 = FaresV2 {
+        fare_products: vec![
+            product(0, vec![price("", None, 2.0)]),
+            product(1, vec![price("", None, 0.5)]),
+        ],
+        fare_leg_rules: vec![leg_rule(None, 0)],
+        fare_transfer_rules: vec![FareTransferRule {
+            duration_limit: Some(hour),
+            duration_limit_type: DurationLimitType::DepartureToDeparture,
+            fare_product_id: Some(1),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let candidate = &calculate_fare_candidates(&ds, &two_legs(&ds, hour - 1))[0];
+    assert_eq!(candidate.total_price, 2.5);
+    let prices = candidate.legs.iter().map(|l| l.price).collect::<Vec<f64>>();
+    assert_eq!(prices, vec![2.0, 0.0]);
+    assert_eq!(candidate.transfers.len(), 1);
+    assert_eq!(candidate.transfers[0].fare_product_id, Some(1));
+
+    let candidate = &calculate_fare_candidates(&ds, &two_legs(&ds, hour + 1))[0];
+    assert_eq!(candidate.total_price, 4.0);
+    assert!(candidate.transfers.is_empty());
+}
+
+#[test]
+fn network_leg_rules() {
+    let mut ds = make_dataset();
+    let solution = two_legs(&ds, 0);
+    let route_id = match &solution.components[0] {
+        SolutionComponent::Bus(b) => b.route.route_id,
+        _ => unreachable!(),
+    };
+    ds.fares_v2 = FaresV2 {
+        fare_products: vec![
+            product(0, vec![price("", None, 3.0)]),
+            product(1, vec![price("", None, 1.0)]),
+        ],
+        fare_leg_rules: vec![leg_rule(Some(0), 0), leg_rule(None, 1)],
+        networks: vec![Network {
+            network_id: 0,
+            route_ids: vec![route_id].into_iter().collect(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    // The rule of the network is the only one for the legs on its routes, even if it costs more.
+    let candidate = &calculate_fare_candidates(&ds, &solution)[0];
+    assert_eq!(candidate.total_price, 6.0);
+
+    ds.fares_v2.networks[0].route_ids.clear();
+    let candidate = &calculate_fare_candidates(&ds, &solution)[0];
+    assert_eq!(candidate.total_price, 2.0);
+    assert_eq!(candidate.legs[0].fare_product_id, 1);
+}
+
+fn product(fare_product_id: usize, prices: Vec<FareProductPrice>) -> FareProduct {
+    FareProduct {
+        fare_product_id,
+        prices,
+        ..Default::default()
+    }
+}
+
+fn price(rider_category_id: &str, fare_media_id: Option<usize>, amount: f64) -> FareProductPrice {
+    FareProductPrice {
+        rider_category_id: rider_category_id.to_string(),
+        fare_media_id,
+        amount,
+        currency: "EUR".to_string(),
+    }
+}
+
+fn leg_rule(network_id: Option<usize>, fare_product_id: usize) -> FareLegRule {
+    FareLegRule {
+        network_id,
+        fare_product_id,
+        ..Default::default()
+    }
+}
+
+fn fare(
+    fare_id: usize,
+    price: f64,
+    transfers: Option<u32>,
+    transfer_duration: Option<i64>,
+    rules: Vec<FareRule>,
+) -> FareAttribute {
+    FareAttribute {
+        fare_id,
+        price,
+        currency_type: "EUR".to_string(),
+        transfers,
+        transfer_duration,
+        rules,
+        ..Default::default()
+    }
+}
+
+fn fare_with_rules(rules: Vec<FareRule>) -> FareAttribute {
+    fare(0, 1.0, None, None, rules)
+}
+
+/// A solution with two legs on runs of the first trip with at least 3 stops: from the first stop
+/// to the second, and from the second to the last, departing `second_leg_delay` seconds after the
+/// first leg.
+fn two_legs(ds: &GtfsData, second_leg_delay: i64) -> Solution {
+    let trip = ds
+        .trips
+        .iter()
+        .find(|t| ds.get_stop_times(t.stop_times_id).stop_times.len() >= 3)
+        .unwrap();
+    let path = ds.get_stop_times(trip.stop_times_id);
+    let leg = |from_inx: usize, to_inx: usize, delay: i64| {
+        let mut trip = trip.clone();
+        trip.start_time += delay - path.stop_times[from_inx].departure_time;
+        SolutionComponent::Bus(BusSolutionComponent {
+            route: ds.get_route(trip.route_id).clone(),
+            trip,
+            path: path.clone(),
+            from_inx,
+            to_inx,
+            ..Default::default()
+        })
+    };
+    Solution {
+        components: vec![
+            leg(0, 1, 0),
+            leg(1, path.stop_times.len() - 1, second_leg_delay),
+        ],
+        ..Default::default()
+    }
+}
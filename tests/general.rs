@@ -4,6 +4,10 @@ use std::iter::Iterator;
 #[cfg(test)]
 use itertools::Itertools;
 
+#[cfg(test)]
+use chrono::NaiveDate;
+#[cfg(test)]
+use chrono_tz::Europe::Rome;
 #[cfg(test)]
 use fastgtfs::gtfs_data::GtfsTime;
 
@@ -40,3 +44,48 @@ fn test_gtfs_time() {
     let from_mid = GtfsTime::new_from_midnight(42);
     assert_eq!(from_mid.since_midnight(), 42);
 }
+
+#[test]
+fn test_gtfs_time_in_timezone() {
+    let hour = 60 * 60;
+    let sunday = NaiveDate::from_ymd(2020, 8, 30);
+    let t = GtfsTime::from_service_date(sunday, 7 * hour, Rome);
+    assert_eq!(
+        t.timestamp(),
+        GtfsTime::from_date("20200830").timestamp() + 5 * hour
+    );
+    assert_eq!((t.h(), t.since_midnight()), (7, 7 * hour as u64));
+    assert_eq!(t.day_of_week(), 6);
+
+    // 23:30 of Sunday in UTC is already Monday in Venice.
+    let utc = GtfsTime::new_from_timestamp(t.timestamp() + 18 * hour + 30 * 60);
+    assert_eq!(utc.day_of_week(), 6);
+    let venice = utc.in_timezone(Rome);
+    assert_eq!(venice.day_of_week(), 0);
+    assert_eq!(venice.since_midnight(), (hour + 30 * 60) as u64);
+    assert_eq!(utc, venice);
+}
+
+#[test]
+fn test_gtfs_time_on_daylight_saving_changes() {
+    let hour = 60 * 60;
+    // The clocks go forward at 02:00: the service day starts at 23:00 of the day before.
+    let spring = NaiveDate::from_ymd(2021, 3, 28);
+    let t = GtfsTime::from_service_date(spring, 7 * hour, Rome);
+    assert_eq!(t.h(), 7);
+    assert_eq!(t.service_date(), spring);
+    let t = GtfsTime::from_service_date(spring, hour + 30 * 60, Rome);
+    assert_eq!((t.h(), t.m()), (0, 30));
+    assert_eq!(t.since_midnight(), (hour + 30 * 60) as u64);
+
+    // The clocks go back at 03:00: the service day starts at 01:00.
+    let autumn = NaiveDate::from_ymd(2021, 10, 31);
+    let t = GtfsTime::from_service_date(autumn, 7 * hour, Rome);
+    assert_eq!(t.h(), 7);
+    assert_eq!(t.new_replacing_time(0).h(), 1);
+    // Half past midnight is still in the service day before, at 24:30:00.
+    let late = t.new_replacing_time(-30 * 60);
+    assert_eq!((late.h(), late.m()), (0, 30));
+    assert_eq!(late.service_date(), autumn.pred());
+    assert_eq!(late.since_midnight(), (24 * hour + 30 * 60) as u64);
+}
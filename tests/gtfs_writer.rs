@@ -0,0 +1,271 @@
+use std::fs;
+
+use itertools::Itertools;
+
+use fastgtfs::feed_source::FeedSource;
+use fastgtfs::gtfs_data::{GtfsData, SourceIdKind};
+use fastgtfs::gtfs_writer::GtfsWriter;
+use fastgtfs::raw_parser::{str_time_to_seconds, RawParser};
+use fastgtfs::test_utils::get_test_paths;
+
+use common::{zip_small_feed, zip_small_feed_adding_files};
+
+mod common;
+
+#[test]
+fn dataset_written_as_gtfs() {
+    let folder = get_test_paths().into_iter().nth(1).unwrap();
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_path(&folder)]);
+    parser.parse();
+    let ds = parser.dataset;
+
+    let out = std::env::temp_dir().join(format!("fastgtfs_writer_{}", std::process::id()));
+    GtfsWriter::new(&ds).write_to_folder(&out).unwrap();
+    let mut parser =
+        RawParser::new_from_sources(vec![FeedSource::from_path(out.to_str().unwrap())]);
+    parser.parse();
+    let written = parser.dataset;
+    fs::remove_dir_all(&out).unwrap();
+
+    assert_eq!(written.stops.len(), ds.stops.len());
+    assert_eq!(written.routes.len(), ds.routes.len());
+    assert_eq!(written.trips.len(), ds.trips.len());
+    assert_eq!(written.shapes.len(), ds.shapes.len());
+    // The ids are the ones of the feed.
+    for stop in &ds.stops {
+        let stop_id = written
+            .lookup_by_source_id(1, SourceIdKind::Stop, &stop.source_id)
+            .unwrap();
+        assert_eq!(written.get_stop(stop_id).stop_name, stop.stop_name);
+    }
+    for trip in &ds.trips {
+        let trip_id = written
+            .lookup_by_source_id(1, SourceIdKind::Trip, &trip.source_id)
+            .unwrap();
+        let written_trip = written.get_trip(trip_id);
+        assert_eq!(written_trip.start_time, trip.start_time);
+        assert_eq!(
+            written
+                .get_stop_times(written_trip.stop_times_id)
+                .stop_times,
+            ds.get_stop_times(trip.stop_times_id).stop_times
+        );
+        assert_eq!(
+            written.get_shape(written_trip.shape_id).points.len(),
+            ds.get_shape(trip.shape_id).points.len()
+        );
+    }
+    let (service, written_service) = (&ds.services[0], &written.services[0]);
+    assert_eq!(written_service.source_id, service.source_id);
+    assert_eq!(written_service.days, service.days);
+    assert_eq!(written_service.start_date, service.start_date);
+    assert_eq!(written_service.end_date, service.end_date);
+    assert_eq!(
+        written_service
+            .exceptions
+            .iter()
+            .map(|e| (e.date.clone(), e.running))
+            .collect_vec(),
+        service
+            .exceptions
+            .iter()
+            .map(|e| (e.date.clone(), e.running))
+            .collect_vec()
+    );
+}
+
+#[test]
+fn merged_feeds_written_with_unique_ids() {
+    let sources = (0..2)
+        .map(|_| FeedSource::from_zip_bytes(zip_small_feed(|_, content| Some(content))))
+        .collect_vec();
+    let mut parser = RawParser::new_from_sources(sources);
+    parser.parse();
+    let ds = parser.dataset;
+
+    let zip = GtfsWriter::new(&ds).to_zip_bytes().unwrap();
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip)]);
+    parser.parse();
+    let written = parser.dataset;
+
+    assert_eq!(written.stops.len(), ds.stops.len());
+    assert_eq!(written.trips.len(), ds.trips.len());
+    assert_eq!(written.services.len(), ds.services.len());
+    let stop_ids = written
+        .stops
+        .iter()
+        .map(|s| s.source_id.as_str())
+        .collect_vec();
+    assert_eq!(
+        stop_ids,
+        vec!["S1_1", "S2_1", "S3_1", "S4_1", "S1_2", "S2_2", "S3_2", "S4_2"]
+    );
+    let trip_id = written
+        .lookup_by_source_id(1, SourceIdKind::Trip, "T2_2")
+        .unwrap();
+    let trip = written.get_trip(trip_id);
+    assert_eq!(written.get_route(trip.route_id).source_id, "R1_2");
+    let stop_times = &written.get_stop_times(trip.stop_times_id).stop_times;
+    assert!(stop_times
+        .iter()
+        .all(|st| written.get_stop(st.stop_id).source_id.ends_with("_2")));
+    assert_eq!(
+        stop_times[1].departure_offset_with_trip(trip.start_time),
+        str_time_to_seconds("09:06:00")
+    );
+}
+
+#[test]
+fn written_ids_dont_collide_with_source_ids() {
+    // `S1` of the first feed would become `S1_1`, which the second feed already has.
+    let with_stop = zip_small_feed(|name, content| match name {
+        "stops.txt" => {
+            let content = String::from_utf8(content).unwrap();
+            let content = content.trim_end().to_string() + "\nS1_1,5,Extra,45.4420,12.3260,Z2,0,\n";
+            Some(content.into_bytes())
+        }
+        _ => Some(content),
+    });
+    let sources = vec![
+        FeedSource::from_zip_bytes(zip_small_feed(|_, content| Some(content))),
+        FeedSource::from_zip_bytes(with_stop),
+    ];
+    let mut parser = RawParser::new_from_sources(sources);
+    parser.parse();
+    let ds = parser.dataset;
+
+    let zip = GtfsWriter::new(&ds).to_zip_bytes().unwrap();
+    let written = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip)])
+        .try_parse()
+        .unwrap();
+    assert_eq!(written.stops.len(), ds.stops.len());
+    let stop_name = |source_id| {
+        let stop_id = written
+            .lookup_by_source_id(1, SourceIdKind::Stop, source_id)
+            .unwrap();
+        written.get_stop(stop_id).stop_name.as_str()
+    };
+    assert_eq!(stop_name("S1_1"), "Extra");
+    assert_eq!(stop_name("S1_1_1"), "First");
+    assert_eq!(stop_name("S1_2"), "First");
+}
+
+#[test]
+fn fares_and_translations_written() {
+    let files = [
+        (
+            "fare_attributes.txt",
+            "fare_id,price,currency_type,payment_method,transfers,transfer_duration\n\
+             single,1.50,EUR,0,0,\n\
+             day,7.5,EUR,1,,86400\n",
+        ),
+        (
+            "fare_rules.txt",
+            "fare_id,route_id,origin_id,destination_id,contains_id\n\
+             single,R1,,,\n\
+             day,,Z1,Z2,\n",
+        ),
+        ("networks.txt", "network_id,network_name\nbus,Buses\n"),
+        ("route_networks.txt", "network_id,route_id\nbus,R1\n"),
+        ("areas.txt", "area_id,area_name\ncenter,Center\n"),
+        ("stop_areas.txt", "area_id,stop_id\ncenter,S1\ncenter,S2\n"),
+        (
+            "fare_media.txt",
+            "fare_media_id,fare_media_name,fare_media_type\ncard,Card,2\n",
+        ),
+        (
+            "fare_products.txt",
+            "fare_product_id,fare_product_name,rider_category_id,fare_media_id,amount,currency\n\
+             single,Single,,,2.00,EUR\n\
+             single,Single,,card,1.60,EUR\n\
+             discount,Discount,,,-0.50,EUR\n",
+        ),
+        (
+            "fare_leg_rules.txt",
+            "leg_group_id,network_id,from_area_id,to_area_id,fare_product_id,rule_priority\n\
+             bus,bus,center,,single,\n\
+             ,,,,single,1\n",
+        ),
+        (
+            "fare_transfer_rules.txt",
+            "from_leg_group_id,to_leg_group_id,transfer_count,duration_limit,\
+             duration_limit_type,fare_transfer_type,fare_product_id\n\
+             bus,bus,-1,5400,1,0,\n\
+             bus,,,,,2,discount\n",
+        ),
+        (
+            "translations.txt",
+            "table_name,field_name,language,translation,record_id,record_sub_id,field_value\n\
+             stops,stop_name,en,First stop,S1,,\n\
+             stops,stop_name,de,Zweite,,,Second\n\
+             routes,route_long_name,en,Line one,R1,,\n",
+        ),
+    ];
+    let zip_content = zip_small_feed_adding_files(|_, content| Some(content), &files);
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+    parser.parse();
+    let ds = parser.dataset;
+
+    let bytes = GtfsWriter::new(&ds).to_zip_bytes().unwrap();
+    let written = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(bytes)])
+        .try_parse()
+        .unwrap();
+
+    assert_eq!(written.fares, ds.fares);
+    let (fares, written_fares) = (&ds.fares_v2, &written.fares_v2);
+    assert_eq!(written_fares.fare_media, fares.fare_media);
+    assert_eq!(written_fares.fare_products, fares.fare_products);
+    assert_eq!(written_fares.fare_leg_rules, fares.fare_leg_rules);
+    assert_eq!(written_fares.fare_transfer_rules, fares.fare_transfer_rules);
+    assert_eq!(written_fares.networks.len(), 1);
+    assert_eq!(
+        written_fares.networks[0].route_ids,
+        fares.networks[0].route_ids
+    );
+    assert_eq!(written_fares.areas.len(), 1);
+    assert_eq!(written_fares.areas[0].stop_ids, fares.areas[0].stop_ids);
+    assert_eq!(written.translations.len(), 3);
+    assert_eq!(
+        written.translations.iter().collect_vec(),
+        ds.translations.iter().collect_vec()
+    );
+}
+
+#[test]
+fn headway_trips_written_with_first_departures() {
+    // `T1` dwells 2 minutes at its first stop.
+    let zip_content = zip_small_feed_adding_files(
+        |name, content| match name {
+            "stop_times.txt" => {
+                let content = String::from_utf8(content).unwrap();
+                let content = content.replace("T1,08:00:00,08:00:00,", "T1,08:00:00,08:02:00,");
+                Some(content.into_bytes())
+            }
+            _ => Some(content),
+        },
+        &[(
+            "frequencies.txt",
+            "trip_id,start_time,end_time,headway_secs,exact_times\n\
+             T1,06:00:00,07:00:00,600,0\n",
+        )],
+    );
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+    parser.parse();
+    let ds = parser.dataset;
+
+    let zip = GtfsWriter::new(&ds).to_zip_bytes().unwrap();
+    let written = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip)])
+        .try_parse()
+        .unwrap();
+    let trip = |ds: &GtfsData| {
+        let trip_id = ds.lookup_by_source_id(1, SourceIdKind::Trip, "T1").unwrap();
+        ds.get_trip(trip_id).clone()
+    };
+    let (original, written_trip) = (trip(&ds), trip(&written));
+    assert_eq!(written_trip.frequencies, original.frequencies);
+    assert_eq!(written_trip.start_time, original.start_time);
+    assert_eq!(
+        written_trip.start_time,
+        str_time_to_seconds("06:00:00") - 120
+    );
+}
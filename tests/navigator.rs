@@ -1,14 +1,21 @@
 #[macro_use]
 extern crate log;
 
+use std::fs;
+
 use chrono::NaiveDate;
 use geo::algorithm::contains::Contains;
 use geo::{Coordinate, Rect};
 use log::debug;
 use rand::Rng;
 
-use fastgtfs::gtfs_data::{GtfsData, GtfsTime, LatLng};
+use fastgtfs::feed_source::FeedSource;
+use fastgtfs::gtfs_data::{
+    GtfsData, GtfsTime, LatLng, PickupDropOffType, SourceIdKind, StopWalkTime, Transfer,
+    TransferType,
+};
 use fastgtfs::navigator::RaptorNavigator;
+use fastgtfs::navigator_models::SolutionComponent::{Bus, Walk};
 use fastgtfs::navigator_models::{NavigationParams, Solution};
 use fastgtfs::raw_parser::RawParser;
 use fastgtfs::test_utils::get_test_paths;
@@ -41,16 +48,7 @@ mod tests {
         init();
         let dataset = get_dataset();
 
-        let venice = LatLng {
-            lat: 45.437_771_117_019_466,
-            lng: 12.318_656_444_549_56,
-        };
-        let nave_de_vero = LatLng {
-            lat: 45.459_262_090_230_05,
-            lng: 12.212_569_713_592_53,
-        };
-
-        let solutions = navigate(&dataset, &venice, &nave_de_vero);
+        let solutions = navigate(&dataset, &venice(), &nave_de_vero());
         assert!(!solutions.is_empty());
         assert_eq!(solutions.len(), 3);
 
@@ -80,6 +78,171 @@ mod tests {
         test_many_random_navigations(&dataset, get_marghera_rect(), get_lido_rect());
     }
 
+    #[test]
+    fn test_no_boarding_without_pickup() {
+        init();
+        let mut dataset = get_dataset();
+        for stop_times in &mut dataset.stop_times {
+            for stop_time in &mut stop_times.stop_times {
+                stop_time.pickup_type = PickupDropOffType::NotAvailable;
+            }
+        }
+
+        let solutions = navigate(&dataset, &venice(), &nave_de_vero());
+        for sol in solutions {
+            assert!(sol.components.iter().all(|c| matches!(c, Walk(_))));
+        }
+    }
+
+    #[test]
+    fn test_call_ahead_solutions() {
+        init();
+        let mut dataset = get_dataset();
+        for stop_times in &mut dataset.stop_times {
+            for stop_time in &mut stop_times.stop_times {
+                stop_time.drop_off_type = PickupDropOffType::CoordinateWithDriver;
+            }
+        }
+
+        let solutions = navigate(&dataset, &venice(), &nave_de_vero());
+        assert!(!solutions.is_empty());
+        for sol in solutions {
+            let has_bus = sol.components.iter().any(|c| matches!(c, Bus(_)));
+            assert_eq!(sol.needs_call_ahead(), has_bus);
+        }
+    }
+
+    #[test]
+    fn test_impossible_transfers() {
+        init();
+        let mut dataset = get_dataset();
+        add_transfer_at_each_stop(&mut dataset, TransferType::NotPossible, None);
+
+        let solutions = navigate(&dataset, &venice(), &nave_de_vero());
+        for sol in solutions {
+            assert!(changes_at_same_stop(&sol).is_empty(), "{}", sol);
+        }
+    }
+
+    #[test]
+    fn test_minimum_transfer_time() {
+        init();
+        let mut dataset = get_dataset();
+        let min_transfer_time = 10 * 60;
+        add_transfer_at_each_stop(
+            &mut dataset,
+            TransferType::MinimumTime,
+            Some(min_transfer_time),
+        );
+
+        let solutions = navigate(&dataset, &venice(), &nave_de_vero());
+        assert!(!solutions.is_empty());
+        for sol in solutions {
+            for (arrival, departure) in changes_at_same_stop(&sol) {
+                assert!(departure.timestamp() - arrival.timestamp() >= min_transfer_time);
+            }
+        }
+    }
+
+    #[test]
+    fn test_change_after_midnight() {
+        init();
+        let dataset = night_dataset();
+        // 23:30 in Rome.
+        let start_time = GtfsTime::new_from_timestamp(
+            NaiveDate::from_ymd(2020, 8, 30)
+                .and_hms(21, 30, 0)
+                .timestamp(),
+        );
+        let params = NavigationParams {
+            from: dataset.stops[0].stop_pos.clone(),
+            to: dataset.stops[2].stop_pos.clone(),
+            max_changes: 2,
+            start_time: start_time.clone(),
+            num_solutions_to_find: 1,
+            avoid_stairs: false,
+        };
+        let solutions = RaptorNavigator::navigate_blocking(&dataset, params);
+        assert_eq!(solutions.len(), 1);
+        RaptorNavigator::validate_solution(&solutions[0], &start_time);
+
+        let legs = solutions[0]
+            .components
+            .iter()
+            .filter_map(|c| match c {
+                Bus(b) => Some((b.departure_time().timestamp(), b.arrival_time().timestamp())),
+                Walk(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let utc = |h, m| {
+            NaiveDate::from_ymd(2020, 8, 30)
+                .and_hms(h, m, 0)
+                .timestamp()
+        };
+        // The second leg is on the service day after the one of the start.
+        assert_eq!(
+            legs,
+            vec![(utc(21, 50), utc(22, 5)), (utc(22, 10), utc(22, 20))]
+        );
+    }
+
+    #[test]
+    fn test_trip_transfer_rule_over_route_rule() {
+        init();
+        let mut dataset = night_dataset();
+        let id = |kind, source_id| dataset.lookup_by_source_id(1, kind, source_id).unwrap();
+        let (s2, r1, r2) = (
+            id(SourceIdKind::Stop, "S2"),
+            id(SourceIdKind::Route, "R1"),
+            id(SourceIdKind::Route, "R2"),
+        );
+        let (t1, t3) = (id(SourceIdKind::Trip, "T1"), id(SourceIdKind::Trip, "T3"));
+        // No change from R1 to R2, except to T3.
+        let transfer = Transfer {
+            from_stop_id: s2,
+            to_stop_id: s2,
+            ..Default::default()
+        };
+        dataset.transfers.push(Transfer {
+            from_route_id: Some(r1),
+            to_route_id: Some(r2),
+            transfer_type: TransferType::NotPossible,
+            ..transfer.clone()
+        });
+        dataset.transfers.push(Transfer {
+            from_trip_id: Some(t1),
+            to_trip_id: Some(t3),
+            ..transfer
+        });
+
+        // 23:30 in Rome.
+        let start_time = GtfsTime::new_from_timestamp(
+            NaiveDate::from_ymd(2020, 8, 30)
+                .and_hms(21, 30, 0)
+                .timestamp(),
+        );
+        let params = NavigationParams {
+            from: dataset.stops[0].stop_pos.clone(),
+            to: dataset.stops[2].stop_pos.clone(),
+            max_changes: 2,
+            start_time: start_time.clone(),
+            num_solutions_to_find: 1,
+            avoid_stairs: false,
+        };
+        let solutions = RaptorNavigator::navigate_blocking(&dataset, params);
+        assert_eq!(solutions.len(), 1);
+        RaptorNavigator::validate_solution(&solutions[0], &start_time);
+        let trips = solutions[0]
+            .components
+            .iter()
+            .filter_map(|c| match c {
+                Bus(b) => Some(b.trip.trip_id),
+                Walk(_) => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(trips, vec![t1, t3]);
+    }
+
     #[test]
     fn test_random_point_in_rect() {
         init();
@@ -95,6 +258,109 @@ mod tests {
     }
 }
 
+fn venice() -> LatLng {
+    LatLng {
+        lat: 45.437_771_117_019_466,
+        lng: 12.318_656_444_549_56,
+    }
+}
+
+fn nave_de_vero() -> LatLng {
+    LatLng {
+        lat: 45.459_262_090_230_05,
+        lng: 12.212_569_713_592_53,
+    }
+}
+
+fn add_transfer_at_each_stop(
+    dataset: &mut GtfsData,
+    transfer_type: TransferType,
+    min_transfer_time: Option<i64>,
+) {
+    for stop_id in 0..dataset.stops.len() {
+        dataset.transfers.push(Transfer {
+            from_stop_id: stop_id,
+            to_stop_id: stop_id,
+            transfer_type,
+            min_transfer_time,
+            ..Default::default()
+        });
+    }
+}
+
+/// (arrival, departure) for each change from a bus to another at the same stop.
+fn changes_at_same_stop(sol: &Solution) -> Vec<(GtfsTime, GtfsTime)> {
+    sol.components
+        .windows(2)
+        .filter_map(|pair| match pair {
+            [Bus(from), Bus(to)]
+                if from.path.stop_times[from.to_inx].stop_id
+                    == to.path.stop_times[to.from_inx].stop_id =>
+            {
+                Some((from.arrival_time(), to.departure_time()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Three stops far from each other: a trip from the first to the second arrives after midnight,
+/// and one from the second to the third leaves right after. Another one leaves 20 minutes later.
+fn night_dataset() -> GtfsData {
+    let folder = std::env::temp_dir().join(format!("fastgtfs_night_{}", std::process::id()));
+    fs::create_dir_all(&folder).unwrap();
+    let files = [
+        (
+            "agency.txt",
+            "agency_id,agency_name,agency_url,agency_timezone\n\
+             A1,Night,http://example.com,Europe/Rome\n",
+        ),
+        (
+            "stops.txt",
+            "stop_id,stop_name,stop_lat,stop_lon\n\
+             S1,First,45.3,12.3\nS2,Second,45.4,12.3\nS3,Third,45.5,12.3\n",
+        ),
+        (
+            "routes.txt",
+            "route_id,agency_id,route_short_name,route_type\nR1,A1,1,3\nR2,A1,2,3\n",
+        ),
+        (
+            "calendar.txt",
+            "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,\
+             start_date,end_date\nALL,1,1,1,1,1,1,1,20200101,20301231\n",
+        ),
+        (
+            "trips.txt",
+            "route_id,service_id,trip_id\nR1,ALL,T1\nR2,ALL,T2\nR2,ALL,T3\n",
+        ),
+        (
+            "stop_times.txt",
+            "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+             T1,23:50:00,23:50:00,S1,1\nT1,24:05:00,24:05:00,S2,2\n\
+             T2,00:10:00,00:10:00,S2,1\nT2,00:20:00,00:20:00,S3,2\n\
+             T3,00:30:00,00:30:00,S2,1\nT3,00:40:00,00:40:00,S3,2\n",
+        ),
+    ];
+    for (name, content) in &files {
+        fs::write(folder.join(name), content).unwrap();
+    }
+    let source = FeedSource::from_path(folder.to_str().unwrap());
+    let mut parser = RawParser::new_from_sources(vec![source]);
+    parser.parse();
+    fs::remove_dir_all(&folder).unwrap();
+
+    let mut dataset = parser.dataset;
+    dataset.walk_times = dataset
+        .stops
+        .iter()
+        .map(|s| StopWalkTime {
+            stop_id: s.stop_id,
+            near_stops: vec![],
+        })
+        .collect();
+    dataset
+}
+
 fn get_dataset() -> GtfsData {
     let test_paths = get_test_paths();
     let mut parser = RawParser::new(test_paths);
@@ -127,6 +393,7 @@ fn navigate(dataset: &GtfsData, from: &LatLng, to: &LatLng) -> Vec<Solution> {
         max_changes: 4,
         start_time: default_start_time(),
         num_solutions_to_find: 3,
+        avoid_stairs: false,
     };
     let solutions = RaptorNavigator::navigate_blocking(dataset, params);
     validate_solutions(&solutions);
@@ -1,12 +1,31 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::path::Path;
 
+use chrono::NaiveDate;
 use itertools::Itertools;
 
-use fastgtfs::gtfs_data::{StopTime, StopTimes};
-use fastgtfs::raw_models::{parse_gtfs, RawCalendar, RawRoute};
-use fastgtfs::raw_parser::{str_time_to_seconds, RawParser};
+use fastgtfs::fares_v2::{DurationLimitType, FareMediaType, FareTransferType};
+use fastgtfs::feed_source::FeedSource;
+use fastgtfs::gtfs_data::{
+    GtfsTime, LatLng, LocationType, PaymentMethod, RouteType, Service, SourceIdKind, StopTime,
+    StopTimes, TransferType,
+};
+use fastgtfs::navigator_models::{BusSolutionComponent, Solution, SolutionComponent};
+use fastgtfs::parse_error::ParseErrorKind;
+use fastgtfs::pathways::{platform_walk_times, PathwayMode};
+use fastgtfs::raw_models::{parse_gtfs, RawCalendar, RawRoute, RawStop, RawTrip};
+use fastgtfs::raw_parser::{str_time_to_seconds, try_str_time_to_seconds, RawParser};
+use fastgtfs::realtime_position::TripRealTimePositionData;
+use fastgtfs::service_calendar::ServiceCalendar;
 use fastgtfs::test_utils::{generate_serialized_data, get_test_paths, make_dataset};
+use fastgtfs::timetable::TimeTable;
+
+use common::{
+    write_small_feed, zip_feed, zip_feed_adding_files, zip_small_feed, zip_small_feed_adding_files,
+};
+
+mod common;
 
 #[test]
 fn routes_parsing() {
@@ -55,6 +74,860 @@ fn dataset_parsing() {
     assert!(!dataset.trips.is_empty());
 }
 
+/// Adds to `stop_times.txt` a row that references a trip that doesn't exist.
+/// Returns the zip and the line of the row.
+fn zip_feed_with_bad_stop_time(folder: &str) -> (Vec<u8>, u64) {
+    let stop_times = fs::read_to_string(Path::new(folder).join("stop_times.txt")).unwrap();
+    let bad_line = stop_times.trim_end().lines().count() as u64 + 1;
+    let zip_content = zip_feed(folder, |name, content| {
+        if name != "stop_times.txt" {
+            return Some(content);
+        }
+        let mut content = String::from_utf8(content).unwrap().trim_end().to_string();
+        let columns = content.lines().next().unwrap().split(',').count();
+        content.push('\n');
+        content.push_str(&vec!["unknown"; columns].join(","));
+        content.push('\n');
+        Some(content.into_bytes())
+    });
+    (zip_content, bad_line)
+}
+
+#[test]
+fn zip_parsing() {
+    let folder = get_test_paths().into_iter().nth(1).unwrap();
+    let zip_content = zip_feed(&folder, |_, content| Some(content));
+
+    let mut from_folder = RawParser::new(vec![folder]);
+    from_folder.parse();
+    let mut from_zip = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+    from_zip.parse();
+
+    assert_eq!(
+        from_folder.dataset.stops.len(),
+        from_zip.dataset.stops.len()
+    );
+    assert_eq!(
+        from_folder.dataset.trips.len(),
+        from_zip.dataset.trips.len()
+    );
+    assert_eq!(
+        from_folder.dataset.stop_times.len(),
+        from_zip.dataset.stop_times.len()
+    );
+}
+
+#[test]
+fn strict_parsing_reports_errors() {
+    let folder = get_test_paths().into_iter().nth(1).unwrap();
+    let (zip_content, bad_line) = zip_feed_with_bad_stop_time(&folder);
+
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+    let error = parser.try_parse().unwrap_err();
+    assert_eq!(error.file, "stop_times.txt");
+    assert_eq!(error.line, Some(bad_line));
+    assert_eq!(error.field.as_deref(), Some("trip_id"));
+    assert_eq!(
+        error.kind,
+        ParseErrorKind::UnknownReference("unknown".to_string())
+    );
+
+    let without_trips = zip_feed(&folder, |name, content| {
+        if name == "trips.txt" {
+            None
+        } else {
+            Some(content)
+        }
+    });
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(without_trips)]);
+    let error = parser.try_parse().unwrap_err();
+    assert_eq!(error.file, "trips.txt");
+    assert_eq!(error.kind, ParseErrorKind::MissingFile);
+}
+
+#[test]
+fn lenient_parsing_skips_bad_rows() {
+    let folder = get_test_paths().into_iter().nth(1).unwrap();
+    let (zip_content, bad_line) = zip_feed_with_bad_stop_time(&folder);
+
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+    parser.lenient = true;
+    let dataset = parser.try_parse().unwrap();
+    assert_eq!(parser.warnings.len(), 1);
+    assert_eq!(parser.warnings[0].line, Some(bad_line));
+
+    let mut from_folder = RawParser::new(vec![folder]);
+    from_folder.parse();
+    assert_eq!(from_folder.dataset.trips.len(), dataset.trips.len());
+    assert_eq!(
+        from_folder.dataset.stop_times.len(),
+        dataset.stop_times.len()
+    );
+}
+
+#[test]
+fn unknown_services_and_exception_types_reported() {
+    let edit = |unknown_service: bool, bad_exception: bool| {
+        zip_small_feed(move |name, content| {
+            let content = String::from_utf8(content).unwrap();
+            let content = match name {
+                "trips.txt" if unknown_service => content.replace("R1,WK,T2", "R1,XX,T2"),
+                "calendar_dates.txt" if bad_exception => content + "WK,20200831,3\n",
+                _ => content,
+            };
+            Some(content.into_bytes())
+        })
+    };
+
+    let mut parser =
+        RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(edit(true, false))]);
+    let error = parser.try_parse().unwrap_err();
+    assert_eq!(error.file, "trips.txt");
+    assert_eq!(error.line, Some(3));
+    assert_eq!(error.field.as_deref(), Some("service_id"));
+    assert_eq!(
+        error.kind,
+        ParseErrorKind::UnknownReference("XX".to_string())
+    );
+
+    let mut parser =
+        RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(edit(false, true))]);
+    let error = parser.try_parse().unwrap_err();
+    assert_eq!(error.file, "calendar_dates.txt");
+    assert_eq!(error.line, Some(3));
+    assert_eq!(error.field.as_deref(), Some("exception_type"));
+    assert_eq!(error.kind, ParseErrorKind::InvalidValue("3".to_string()));
+
+    let mut parser =
+        RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(edit(true, true))]);
+    parser.lenient = true;
+    let ds = parser.try_parse().unwrap();
+    // The stop times of the skipped trip are reported too.
+    let warnings_in = |file| parser.warnings.iter().filter(|w| w.file == file).count();
+    assert_eq!(warnings_in("calendar_dates.txt"), 1);
+    assert_eq!(warnings_in("trips.txt"), 1);
+    assert_eq!(warnings_in("stop_times.txt"), 4);
+    assert_eq!(ds.trips.len(), 1);
+    assert_eq!(ds.services[0].exceptions.len(), 1);
+}
+
+#[test]
+fn unsorted_stop_times_parsing() {
+    let folder = get_test_paths().into_iter().nth(1).unwrap();
+    // Same rows, in reverse order: trips are split, and sequences decreasing.
+    let zip_content = zip_feed(&folder, |name, content| {
+        if name != "stop_times.txt" {
+            return Some(content);
+        }
+        let content = String::from_utf8(content).unwrap();
+        let mut lines = content.trim_end().lines();
+        let header = lines.next().unwrap();
+        let reversed = std::iter::once(header).chain(lines.rev()).join("\n");
+        Some(reversed.into_bytes())
+    });
+
+    let mut sorted = RawParser::new(vec![folder]);
+    sorted.parse();
+    let mut unsorted = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+    unsorted.parse();
+
+    let (sorted, unsorted) = (sorted.dataset, unsorted.dataset);
+    assert_eq!(sorted.trips.len(), unsorted.trips.len());
+    for (a, b) in sorted.trips.iter().zip(unsorted.trips.iter()) {
+        assert_eq!(a.start_time, b.start_time);
+        assert_eq!(
+            sorted.get_stop_times(a.stop_times_id).stop_times,
+            unsorted.get_stop_times(b.stop_times_id).stop_times
+        );
+        let sequences = unsorted
+            .get_stop_times(b.stop_times_id)
+            .stop_times
+            .iter()
+            .map(|st| st.stop_sequence)
+            .collect_vec();
+        assert!(sequences.windows(2).all(|w| w[0] < w[1]));
+    }
+}
+
+#[test]
+fn stop_times_without_times_parsing() {
+    // `S3` of `T1` is not a timepoint, nor is the last stop of `T2`, which is not allowed.
+    let zip_content = zip_small_feed(|name, content| {
+        if name != "stop_times.txt" {
+            return Some(content);
+        }
+        let content = String::from_utf8(content)
+            .unwrap()
+            .replace("T1,08:10:00,08:10:00,S3", "T1,,,S3")
+            .replace("T2,09:15:00,09:15:00,S4", "T2,,,S4");
+        Some(content.into_bytes())
+    });
+
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+    parser.lenient = true;
+    let ds = parser.try_parse().unwrap();
+    assert_eq!(parser.warnings.len(), 1);
+    assert_eq!(parser.warnings[0].line, Some(9));
+    assert_eq!(parser.warnings[0].field.as_deref(), Some("arrival_time"));
+
+    let t1 = ds.lookup_by_source_id(1, SourceIdKind::Trip, "T1").unwrap();
+    let stop_times = &ds.get_stop_times(ds.get_trip(t1).stop_times_id).stop_times;
+    assert_eq!(stop_times.len(), 4);
+    // Halfway between the departure from `S2` and the arrival at `S4`, as the stops are evenly
+    // spaced.
+    let third = &stop_times[2];
+    assert_eq!(third.arrival_time, third.departure_time);
+    assert!((third.arrival_time - 630).abs() <= 2);
+    assert_eq!(stop_times[3].arrival_time, 900);
+}
+
+#[test]
+fn frequencies_parsing() {
+    let folder = get_test_paths().into_iter().nth(1).unwrap();
+    let raw_trips: Vec<RawTrip> = parse_gtfs(&Path::new(&folder).join("trips.txt")).unwrap();
+    let (exact_trip, headway_trip) = (&raw_trips[0].trip_id, &raw_trips[1].trip_id);
+    let frequencies = format!(
+        "trip_id,start_time,end_time,headway_secs,exact_times\n\
+         {},06:00:00,07:00:00,600,1\n\
+         {},10:00:00,12:00:00,900,0\n",
+        exact_trip, headway_trip
+    );
+    let zip_content = zip_feed_adding_files(
+        &folder,
+        |_, content| Some(content),
+        &[("frequencies.txt", &frequencies)],
+    );
+
+    let mut without_frequencies = RawParser::new(vec![folder]);
+    without_frequencies.parse();
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+    parser.parse();
+    let ds = parser.dataset;
+
+    // The exact times trip is expanded into 6 runs, 10 minutes apart.
+    let hour = 60 * 60;
+    assert_eq!(ds.trips.len(), without_frequencies.dataset.trips.len() + 5);
+    let exact_runs = ds
+        .trips
+        .iter()
+        .filter(|t| {
+            t.stop_times_id == ds.trips[0].stop_times_id && t.route_id == ds.trips[0].route_id
+        })
+        .filter(|t| t.start_time >= 6 * hour && t.start_time < 7 * hour)
+        .map(|t| t.start_time)
+        .sorted()
+        .collect_vec();
+    assert_eq!(exact_runs, (0..6).map(|i| 6 * hour + i * 600).collect_vec());
+
+    // The other keeps the window.
+    let headway_trip = &ds.trips[1];
+    assert!(headway_trip.is_frequency_based());
+    assert_eq!(headway_trip.start_time, 10 * hour);
+    assert_eq!(
+        headway_trip.first_run_starting_from(10 * hour + 60),
+        Some(10 * hour + 900)
+    );
+    assert_eq!(headway_trip.last_run_start(), 12 * hour - 900);
+    assert_eq!(headway_trip.first_run_starting_from(12 * hour), None);
+
+    let direction = headway_trip.direction_id.parse::<i8>().unwrap_or(0);
+    let timetable = TimeTable::new(&ds, vec![headway_trip.route_id], direction).unwrap();
+    assert_eq!(
+        timetable.get_frequency_description(&ds, headway_trip.trip_id),
+        Some("every 15 min (10:00-12:00)".to_string())
+    );
+}
+
+#[test]
+fn frequencies_follow_first_departure() {
+    // T1 dwells 2 minutes at its first stop, T2 1 minute. The feed already has a trip `T1_1`.
+    let frequencies = "trip_id,start_time,end_time,headway_secs,exact_times\n\
+                       T1,06:00:00,06:30:00,600,1\n\
+                       T2,10:00:00,12:00:00,900,0\n";
+    let zip_content = zip_small_feed_adding_files(
+        |name, content| {
+            let content = String::from_utf8(content).unwrap();
+            let content = match name {
+                "stop_times.txt" => content
+                    .replace("T1,08:00:00,08:00:00,", "T1,08:00:00,08:02:00,")
+                    .replace("T2,09:00:00,09:00:00,", "T2,09:00:00,09:01:00,"),
+                "trips.txt" => content + "R1,WK,T1_1,Extra,0,SH1\n",
+                _ => content,
+            };
+            Some(content.into_bytes())
+        },
+        &[("frequencies.txt", frequencies)],
+    );
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+    parser.parse();
+    let ds = parser.dataset;
+
+    // The runs leave the first stop at the times of frequencies.txt, and each has its own id.
+    let hour = 60 * 60;
+    let runs = ["T1", "T1_2", "T1_3"]
+        .iter()
+        .map(|id| {
+            let inx = ds.lookup_by_source_id(1, SourceIdKind::Trip, id).unwrap();
+            let trip = &ds.trips[inx];
+            trip.start_time + ds.stop_times[trip.stop_times_id].stop_times[0].departure_time
+        })
+        .collect_vec();
+    assert_eq!(runs, vec![6 * hour, 6 * hour + 600, 6 * hour + 1200]);
+    assert_eq!(ds.lookup_by_source_id(1, SourceIdKind::Trip, "T1_4"), None);
+    let existing = ds
+        .lookup_by_source_id(1, SourceIdKind::Trip, "T1_1")
+        .unwrap();
+    assert_eq!(ds.trips[existing].trip_headsign, "Extra");
+
+    let headway_trip = &ds.trips[ds.lookup_by_source_id(1, SourceIdKind::Trip, "T2").unwrap()];
+    assert_eq!(headway_trip.start_time, 10 * hour - 60);
+    assert_eq!(headway_trip.last_run_start(), 12 * hour - 900 - 60);
+}
+
+#[test]
+fn transfers_parsing() {
+    let folder = get_test_paths().into_iter().nth(1).unwrap();
+    let path = Path::new(&folder);
+    let raw_stops: Vec<RawStop> = parse_gtfs(&path.join("stops.txt")).unwrap();
+    let raw_routes: Vec<RawRoute> = parse_gtfs(&path.join("routes.txt")).unwrap();
+    let raw_trips: Vec<RawTrip> = parse_gtfs(&path.join("trips.txt")).unwrap();
+    let (stop_a, stop_b) = (&raw_stops[0].stop_id, &raw_stops[1].stop_id);
+    let transfers = format!(
+        "from_stop_id,to_stop_id,from_route_id,to_route_id,from_trip_id,to_trip_id,\
+         transfer_type,min_transfer_time\n\
+         {a},{a},,,,,,\n\
+         {a},{b},,,,,1,\n\
+         {a},{b},{route},,,,2,180\n\
+         {b},{a},,,{trip},,3,\n",
+        a = stop_a,
+        b = stop_b,
+        route = raw_routes[0].route_id,
+        trip = raw_trips[0].trip_id,
+    );
+    let zip_content = zip_feed_adding_files(
+        &folder,
+        |_, content| Some(content),
+        &[("transfers.txt", &transfers)],
+    );
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+    parser.parse();
+    let ds = parser.dataset;
+
+    let stop_a = parser.stop_name_to_inx[stop_a];
+    let stop_b = parser.stop_name_to_inx[stop_b];
+    let types = ds
+        .transfers
+        .iter()
+        .map(|t| (t.from_stop_id, t.to_stop_id, t.transfer_type))
+        .collect_vec();
+    assert_eq!(
+        types,
+        vec![
+            (stop_a, stop_a, TransferType::Recommended),
+            (stop_a, stop_b, TransferType::Timed),
+            (stop_a, stop_b, TransferType::MinimumTime),
+            (stop_b, stop_a, TransferType::NotPossible),
+        ]
+    );
+    let route_rule = &ds.transfers[2];
+    assert_eq!(
+        route_rule.from_route_id,
+        Some(parser.routes_name_to_inx[&raw_routes[0].route_id])
+    );
+    assert_eq!(route_rule.to_route_id, None);
+    assert_eq!(route_rule.min_transfer_time, Some(180));
+    let trip_rule = &ds.transfers[3];
+    assert_eq!(
+        trip_rule.from_trip_id,
+        Some(parser.trip_name_to_inx[&raw_trips[0].trip_id])
+    );
+    assert!(trip_rule.specificity() > route_rule.specificity());
+    assert!(ds.transfers[0].is_between_stops_only());
+}
+
+#[test]
+fn fares_parsing() {
+    let folder = get_test_paths().into_iter().nth(1).unwrap();
+    let raw_routes: Vec<RawRoute> = parse_gtfs(&Path::new(&folder).join("routes.txt")).unwrap();
+    let route = &raw_routes[0].route_id;
+    let fare_attributes =
+        "fare_id,price,currency_type,payment_method,transfers,transfer_duration\n\
+                           single,1.50,EUR,0,0,\n\
+                           day,7.5,EUR,1,,86400\n";
+    let fare_rules = format!(
+        "fare_id,route_id,origin_id,destination_id,contains_id\n\
+         single,{},,,\n\
+         day,,A,B,\n",
+        route
+    );
+    let zip_content = zip_feed_adding_files(
+        &folder,
+        |_, content| Some(content),
+        &[
+            ("fare_attributes.txt", fare_attributes),
+            ("fare_rules.txt", &fare_rules),
+        ],
+    );
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+    parser.parse();
+    let ds = parser.dataset;
+
+    assert_eq!(ds.fares.len(), 2);
+    let (single, day) = (&ds.fares[0], &ds.fares[1]);
+    assert_eq!(single.price, 1.5);
+    assert_eq!(single.payment_method, PaymentMethod::OnBoard);
+    assert_eq!(single.transfers, Some(0));
+    assert_eq!(single.transfer_duration, None);
+    assert_eq!(
+        single.rules[0].route_id,
+        Some(parser.routes_name_to_inx[route])
+    );
+    assert_eq!(day.payment_method, PaymentMethod::BeforeBoarding);
+    assert_eq!(day.transfers, None);
+    assert_eq!(day.transfer_duration, Some(86400));
+    assert_eq!(day.rules[0].origin_id.as_deref(), Some("A"));
+    assert_eq!(day.rules[0].destination_id.as_deref(), Some("B"));
+    assert_eq!(day.rules[0].contains_id, None);
+}
+
+#[test]
+fn fares_v2_parsing() {
+    let folder = get_test_paths().into_iter().nth(1).unwrap();
+    let raw_routes: Vec<RawRoute> = parse_gtfs(&Path::new(&folder).join("routes.txt")).unwrap();
+    let raw_stops: Vec<RawStop> = parse_gtfs(&Path::new(&folder).join("stops.txt")).unwrap();
+    let (route, stop) = (&raw_routes[0].route_id, &raw_stops[0].stop_id);
+    let files = [
+        (
+            "networks.txt",
+            "network_id,network_name\nbus,Buses\n".to_string(),
+        ),
+        (
+            "route_networks.txt",
+            format!("network_id,route_id\nbus,{}\n", route),
+        ),
+        (
+            "areas.txt",
+            "area_id,area_name\ncenter,Center\n".to_string(),
+        ),
+        (
+            "stop_areas.txt",
+            format!("area_id,stop_id\ncenter,{}\n", stop),
+        ),
+        (
+            "fare_media.txt",
+            "fare_media_id,fare_media_name,fare_media_type\ncard,Card,2\n".to_string(),
+        ),
+        (
+            "fare_products.txt",
+            "fare_product_id,fare_product_name,rider_category_id,fare_media_id,amount,currency\n\
+             single,Single,,,2.00,EUR\n\
+             single,Single,,card,1.60,EUR\n\
+             single,Single,child,,1.00,EUR\n\
+             discount,Discount,,,-0.50,EUR\n"
+                .to_string(),
+        ),
+        (
+            "fare_leg_rules.txt",
+            "leg_group_id,network_id,from_area_id,to_area_id,fare_product_id,rule_priority\n\
+             bus,bus,center,,single,\n\
+             ,,,,single,1\n"
+                .to_string(),
+        ),
+        (
+            "fare_transfer_rules.txt",
+            "from_leg_group_id,to_leg_group_id,transfer_count,duration_limit,\
+             duration_limit_type,fare_transfer_type,fare_product_id\n\
+             bus,bus,-1,5400,1,0,\n\
+             bus,,,,,2,discount\n"
+                .to_string(),
+        ),
+    ];
+    let files = files
+        .iter()
+        .map(|(name, content)| (*name, content.as_str()))
+        .collect::<Vec<(&str, &str)>>();
+    let zip_content = zip_feed_adding_files(&folder, |_, content| Some(content), &files);
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+    parser.parse();
+    let fares = &parser.dataset.fares_v2;
+
+    assert_eq!(fares.networks.len(), 1);
+    assert_eq!(fares.networks[0].network_name, "Buses");
+    assert!(fares.networks[0]
+        .route_ids
+        .contains(&parser.routes_name_to_inx[route]));
+    assert!(fares.areas[0]
+        .stop_ids
+        .contains(&parser.stop_name_to_inx[stop]));
+    assert_eq!(
+        fares.fare_media[0].fare_media_type,
+        FareMediaType::TransitCard
+    );
+
+    assert_eq!(fares.fare_products.len(), 2);
+    let single = &fares.fare_products[0];
+    assert_eq!(single.prices.len(), 3);
+    assert_eq!(single.prices[1].fare_media_id, Some(0));
+    assert_eq!(single.prices[1].amount, 1.6);
+    assert_eq!(single.prices[2].rider_category_id, "child");
+    assert_eq!(fares.fare_products[1].prices[0].amount, -0.5);
+
+    let (explicit, fallback) = (&fares.fare_leg_rules[0], &fares.fare_leg_rules[1]);
+    assert_eq!(explicit.leg_group_id.as_deref(), Some("bus"));
+    assert_eq!(explicit.network_id, Some(0));
+    assert_eq!(explicit.from_area_id, Some(0));
+    assert_eq!(explicit.to_area_id, None);
+    assert_eq!(explicit.rule_priority, None);
+    assert_eq!(fallback.leg_group_id, None);
+    assert_eq!(fallback.rule_priority, Some(1));
+
+    let (free, discounted) = (&fares.fare_transfer_rules[0], &fares.fare_transfer_rules[1]);
+    assert_eq!(free.transfer_count, Some(-1));
+    assert_eq!(free.duration_limit, Some(5400));
+    assert_eq!(
+        free.duration_limit_type,
+        DurationLimitType::DepartureToDeparture
+    );
+    assert_eq!(free.fare_product_id, None);
+    assert_eq!(discounted.to_leg_group_id, None);
+    assert_eq!(
+        discounted.fare_transfer_type,
+        FareTransferType::TransferOnly
+    );
+    assert_eq!(discounted.fare_product_id, Some(1));
+}
+
+#[test]
+fn non_finite_fare_amounts_rejected() {
+    let folder = get_test_paths().into_iter().nth(1).unwrap();
+    let parse_error = |file: &str, content: String| {
+        let zip_content =
+            zip_feed_adding_files(&folder, |_, content| Some(content), &[(file, &content)]);
+        RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)])
+            .try_parse()
+            .unwrap_err()
+    };
+    for amount in &["NaN", "inf", "-inf"] {
+        let error = parse_error(
+            "fare_products.txt",
+            format!(
+                "fare_product_id,fare_product_name,amount,currency\nsingle,Single,{},EUR\n",
+                amount
+            ),
+        );
+        assert_eq!(error.file, "fare_products.txt");
+        assert_eq!(error.field.as_deref(), Some("amount"));
+    }
+    let error = parse_error(
+        "fare_attributes.txt",
+        "fare_id,price,currency_type,payment_method,transfers\nsingle,inf,EUR,0,\n".to_string(),
+    );
+    assert_eq!(error.file, "fare_attributes.txt");
+    assert_eq!(error.field.as_deref(), Some("price"));
+}
+
+#[test]
+fn agencies_parsing() {
+    let mut parser = RawParser::new(get_test_paths());
+    parser.parse();
+    let ds = parser.dataset;
+
+    assert!(!ds.agencies.is_empty());
+    for route in &ds.routes {
+        let agency = ds.get_route_agency(route.route_id).unwrap();
+        assert_eq!(agency.dataset_index, route.dataset_index);
+        assert!(ds
+            .get_agency_routes(agency.agency_id)
+            .iter()
+            .any(|r| r.route_id == route.route_id));
+    }
+}
+
+#[test]
+fn routes_without_agency_id() {
+    let folder = get_test_paths().into_iter().nth(1).unwrap();
+    let raw_routes: Vec<RawRoute> = parse_gtfs(&Path::new(&folder).join("routes.txt")).unwrap();
+    let feed = |agencies: &str, route_agency_id: &str| {
+        let routes = raw_routes
+            .iter()
+            .map(|r| {
+                format!(
+                    "{},{},{},3",
+                    r.route_id, route_agency_id, r.route_short_name
+                )
+            })
+            .join("\n");
+        let routes = format!(
+            "route_id,agency_id,route_short_name,route_type\n{}\n",
+            routes
+        );
+        let zip_content = zip_feed(&folder, |name, content| match name {
+            "agency.txt" => Some(agencies.as_bytes().to_vec()),
+            "routes.txt" => Some(routes.as_bytes().to_vec()),
+            _ => Some(content),
+        });
+        RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)])
+    };
+    let header = "agency_id,agency_name,agency_url,agency_timezone\n";
+
+    // With a single agency, it runs all the routes.
+    let actv = "A,ACTV,http://actv.it,Europe/Rome\n";
+    let mut parser = feed(&format!("{}{}", header, actv), "");
+    parser.parse();
+    let ds = parser.dataset;
+    assert!(ds.routes.iter().all(|r| r.agency_id == Some(0)));
+    assert_eq!(ds.get_agency_routes(0).len(), ds.routes.len());
+    assert_eq!(ds.get_agency(0).agency_name, "ACTV");
+    assert_eq!(ds.get_agency(0).agency_timezone, chrono_tz::Europe::Rome);
+    assert_eq!(ds.get_route_timezone(0), chrono_tz::Europe::Rome);
+
+    let two_agencies = format!(
+        "{}{}B,Alilaguna,http://alilaguna.it,Europe/Rome\n",
+        header, actv
+    );
+    let mut parser = feed(&two_agencies, "");
+    parser.parse();
+    assert!(parser.dataset.routes.iter().all(|r| r.agency_id.is_none()));
+
+    let mut parser = feed(&two_agencies, "B");
+    parser.parse();
+    let ds = parser.dataset;
+    assert!(ds.routes.iter().all(|r| r.agency_id == Some(1)));
+    assert!(ds.get_agency_routes(0).is_empty());
+
+    let mut parser = feed(&format!("{}A,ACTV,http://actv.it,Venice\n", header), "");
+    let error = parser.try_parse().unwrap_err();
+    assert_eq!(error.field.as_deref(), Some("agency_timezone"));
+
+    let mut parser = feed(&two_agencies, "C");
+    let error = parser.try_parse().unwrap_err();
+    assert_eq!(error.file, "routes.txt");
+    assert_eq!(error.field.as_deref(), Some("agency_id"));
+    assert_eq!(
+        error.kind,
+        ParseErrorKind::UnknownReference("C".to_string())
+    );
+}
+
+#[test]
+fn route_metadata_parsing() {
+    let folder = get_test_paths().into_iter().next().unwrap();
+    let raw_routes: Vec<RawRoute> = parse_gtfs(&Path::new(&folder).join("routes.txt")).unwrap();
+    let mut parser = RawParser::new(vec![folder]);
+    parser.parse();
+    for (raw, route) in raw_routes.iter().zip(&parser.dataset.routes) {
+        assert_eq!(route.route_type.to_gtfs().to_string(), raw.route_type);
+        assert_eq!(route.route_color, raw.route_color.to_uppercase());
+        assert_eq!(route.route_text_color, raw.route_text_color.to_uppercase());
+        assert_eq!(route.route_desc, raw.route_desc);
+        assert_eq!(route.route_url, raw.route_url);
+    }
+
+    // The trips of the small feed are all on `R1`.
+    let feed = |route: &str| {
+        let routes = format!(
+            "route_id,route_short_name,route_desc,route_type,route_url,route_color,route_text_color\n{}\n",
+            route
+        );
+        let zip_content = zip_small_feed(|name, content| match name {
+            "routes.txt" => Some(routes.as_bytes().to_vec()),
+            _ => Some(content),
+        });
+        RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)])
+    };
+    let route_id = "R1";
+
+    let mut parser = feed(&format!(
+        "{},1,Night ferry,1200,http://actv.it,00aa00,",
+        route_id
+    ));
+    let route = parser.try_parse().unwrap().routes.remove(0);
+    assert_eq!(route.route_type, RouteType::Extended(1200));
+    assert_eq!(route.route_type.basic(), RouteType::Ferry);
+    assert_eq!(route.route_desc, "Night ferry");
+    assert_eq!(route.route_url, "http://actv.it");
+    assert_eq!(route.route_color, "00AA00");
+    assert_eq!(route.route_text_color, "000000");
+
+    let mut parser = feed(&format!("{},1,,3,,,", route_id));
+    let route = parser.try_parse().unwrap().routes.remove(0);
+    assert_eq!(route.route_type, RouteType::Bus);
+    assert_eq!(route.route_color, "FFFFFF");
+
+    let mut parser = feed(&format!("{},1,,3,,#00AA00,", route_id));
+    let error = parser.try_parse().unwrap_err();
+    assert_eq!(error.field.as_deref(), Some("route_color"));
+    let mut parser = feed(&format!("{},1,,8,,,", route_id));
+    let error = parser.try_parse().unwrap_err();
+    assert_eq!(error.field.as_deref(), Some("route_type"));
+}
+
+#[test]
+fn station_hierarchy_parsing() {
+    let feed = |extra_stops: &str| {
+        let zip_content = zip_small_feed(|name, content| {
+            if name != "stops.txt" {
+                return Some(content);
+            }
+            let stops = String::from_utf8(content)
+                .unwrap()
+                .lines()
+                .map(|line| match line.split(',').next() {
+                    // The first two stops are platforms of the station.
+                    Some("S1") | Some("S2") => format!("{}ROMA", line),
+                    _ => line.to_string(),
+                })
+                .join("\n");
+            Some(format!("{}\n{}\n", stops, extra_stops).into_bytes())
+        });
+        RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)])
+    };
+    let station = "ROMA,,Piazzale Roma,45.4385,12.3190,,1,";
+    let entrance = "E1,,Entrance,45.4386,12.3191,,2,ROMA";
+    let node = "N1,,Node,,,,3,ROMA";
+
+    let mut parser = feed(&[station, entrance, node].join("\n"));
+    let ds = parser.try_parse().unwrap();
+    let stop_id = |name: &str| {
+        ds.stops
+            .iter()
+            .position(|s| s.stop_code == name || s.stop_name == name)
+            .unwrap()
+    };
+    let (s1, s2, s3) = (stop_id("1"), stop_id("2"), stop_id("3"));
+    let (roma, e1, n1) = (
+        stop_id("Piazzale Roma"),
+        stop_id("Entrance"),
+        stop_id("Node"),
+    );
+
+    assert_eq!(ds.get_stop(roma).location_type, LocationType::Station);
+    assert_eq!(ds.get_stop(e1).location_type, LocationType::Entrance);
+    assert_eq!(ds.get_stop(s1).location_type, LocationType::StopOrPlatform);
+    assert_eq!(ds.get_stop(s1).parent_station, Some(roma));
+    assert_eq!(ds.get_stop(s3).parent_station, None);
+    let children = ds
+        .get_stop_children(roma)
+        .iter()
+        .map(|s| s.stop_id)
+        .collect::<Vec<_>>();
+    assert_eq!(children, vec![s1, s2, e1, n1]);
+    // The node has no position: it is the one of the station.
+    assert_eq!(ds.get_stop(n1).stop_pos.lat, ds.get_stop(roma).stop_pos.lat);
+
+    assert_eq!(ds.get_station(s2).unwrap().stop_id, roma);
+    assert_eq!(ds.get_station(roma).unwrap().stop_id, roma);
+    assert!(ds.get_station(s3).is_none());
+    assert_eq!(ds.get_station_platforms(e1), vec![s1, s2]);
+    assert_eq!(ds.get_station_platforms(s3), vec![s3]);
+
+    // Stations can be found, entrances are skipped.
+    let near_entrance = ds.get_stop(e1).stop_pos.clone();
+    assert_eq!(ds.find_nearest_stop(&near_entrance).stop_id, roma);
+
+    let mut parser = feed(&[station, "E1,,Entrance,45.4386,12.3191,,2,MISSING"].join("\n"));
+    let error = parser.try_parse().unwrap_err();
+    assert_eq!(error.field.as_deref(), Some("parent_station"));
+    assert_eq!(
+        error.kind,
+        ParseErrorKind::UnknownReference("MISSING".to_string())
+    );
+
+    let mut parser = feed(&[station, "E1,,Entrance,,,,2,ROMA"].join("\n"));
+    let error = parser.try_parse().unwrap_err();
+    assert_eq!(error.field.as_deref(), Some("stop_lat"));
+}
+
+#[test]
+fn pathways_parsing() {
+    let feed = |pathways: &str| {
+        let levels = "level_id,level_index,level_name\nL0,0,Street\nL1,-1,Hall\n";
+        let pathways = format!(
+            "pathway_id,from_stop_id,to_stop_id,pathway_mode,is_bidirectional,traversal_time,\
+             stair_count\n{}\n",
+            pathways
+        );
+        let zip_content = zip_small_feed_adding_files(
+            |name, content| {
+                if name != "stops.txt" {
+                    return Some(content);
+                }
+                let stops = String::from_utf8(content)
+                    .unwrap()
+                    .lines()
+                    .map(|line| match line.split(',').next() {
+                        Some("S1") | Some("S2") => format!("{}ROMA,L0", line),
+                        _ => format!("{},", line),
+                    })
+                    .join("\n")
+                    .replacen("parent_station,", "parent_station,level_id", 1);
+                let stops = format!(
+                    "{}\nROMA,,Piazzale Roma,45.4385,12.3190,,1,,\nN1,,Hall,,,,3,ROMA,L1\n",
+                    stops
+                );
+                Some(stops.into_bytes())
+            },
+            &[("levels.txt", levels), ("pathways.txt", &pathways)],
+        );
+        RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)])
+    };
+    let pathways = [
+        "P1,S1,N1,1,1,10,",
+        "P2,N1,S2,2,1,20,-12",
+        "P3,N1,S2,5,1,90,",
+        // Only from S2 to S1.
+        "P4,S2,S1,7,0,5,",
+    ];
+
+    let mut parser = feed(&pathways.join("\n"));
+    let ds = parser.try_parse().unwrap();
+    let stop_id = |name: &str| ds.stops.iter().position(|s| s.stop_name == name).unwrap();
+    let (s1, s2, hall) = (stop_id("First"), stop_id("Second"), stop_id("Hall"));
+
+    assert_eq!(ds.levels.len(), 2);
+    assert_eq!(ds.levels[1].level_index, -1.0);
+    assert_eq!(ds.get_stop(s1).level_id, Some(0));
+    assert_eq!(ds.get_stop(hall).level_id, Some(1));
+    assert_eq!(ds.pathways.len(), 4);
+    let stairs = &ds.pathways[1];
+    assert_eq!(stairs.pathway_mode, PathwayMode::Stairs);
+    assert_eq!((stairs.from_stop_id, stairs.to_stop_id), (hall, s2));
+    assert_eq!(stairs.stair_count, Some(-12));
+    assert!(stairs.has_stairs() && stairs.is_bidirectional);
+    assert!(ds.pathways[2].is_elevator());
+    assert!(!ds.pathways[3].is_bidirectional);
+    assert_eq!(
+        ds.get_stop(hall)
+            .pathways
+            .iter()
+            .copied()
+            .collect::<Vec<_>>(),
+        vec![0, 1, 2]
+    );
+
+    let times = |from, avoid_stairs| {
+        platform_walk_times(&ds, from, avoid_stairs)
+            .into_iter()
+            .sorted()
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(times(s1, false), vec![(s2, 30)]);
+    assert_eq!(times(s1, true), vec![(s2, 100)]);
+    assert_eq!(times(s2, false), vec![(s1, 5)]);
+    assert!(times(stop_id("Third"), false).is_empty());
+
+    let mut parser = feed("P1,S1,MISSING,1,1,10,");
+    let error = parser.try_parse().unwrap_err();
+    assert_eq!(error.file, "pathways.txt");
+    assert_eq!(error.field.as_deref(), Some("to_stop_id"));
+    let mut parser = feed("P1,S1,N1,8,1,10,");
+    let error = parser.try_parse().unwrap_err();
+    assert_eq!(error.field.as_deref(), Some("pathway_mode"));
+    let mut parser = feed("P1,S1,N1,1,1,-10,");
+    let error = parser.try_parse().unwrap_err();
+    assert_eq!(error.field.as_deref(), Some("traversal_time"));
+}
+
 #[test]
 fn serialize_and_deserialize() {
     generate_serialized_data();
@@ -136,14 +1009,24 @@ fn test_vector_equality() {
         stop_times_id: 0,
         stop_times: vec![StopTime {
             stop_id: 1,
-            time: 1,
+            arrival_time: 1,
+            departure_time: 1,
+            stop_sequence: 1,
+            pickup_type: Default::default(),
+            drop_off_type: Default::default(),
+            shape_dist_traveled: None,
         }],
     };
     let v2 = StopTimes {
         stop_times_id: 0,
         stop_times: vec![StopTime {
             stop_id: 1,
-            time: 1,
+            arrival_time: 1,
+            departure_time: 1,
+            stop_sequence: 1,
+            pickup_type: Default::default(),
+            drop_off_type: Default::default(),
+            shape_dist_traveled: None,
         }],
     };
 
@@ -167,7 +1050,7 @@ fn valid_stop_times() {
     ds.stop_times.iter().for_each(|st| {
         assert!(!st.stop_times.is_empty());
         for stop_time in &st.stop_times {
-            assert!(stop_time.time < seconds_in_hour * 4); // is there a trip that is more than 4 h? if so, remove this
+            assert!(stop_time.departure_time < seconds_in_hour * 4); // is there a trip that is more than 4 h? if so, remove this
         }
     });
 }
@@ -206,12 +1089,16 @@ fn valid_trip_times() {
     let ds = RawParser::read_preprocessed_data_from_default();
     for stop_times in &ds.stop_times {
         let st = &stop_times.stop_times;
-        let mut prec_time = st.first().unwrap().time;
+        let mut prec_time = st.first().unwrap().arrival_time;
         for i in st {
-            assert!(i.time >= prec_time);
-            prec_time = i.time;
+            assert!(i.arrival_time >= prec_time);
+            assert!(i.departure_time >= i.arrival_time);
+            prec_time = i.departure_time;
         }
-        assert_ne!(st.first().unwrap().time, st.last().unwrap().time);
+        assert_ne!(
+            st.first().unwrap().departure_time,
+            st.last().unwrap().arrival_time
+        );
     }
 }
 
@@ -241,6 +1128,12 @@ fn date_parsing() {
     let seconds_in_hour = 60 * 60;
     let five_am = 5 * seconds_in_hour;
     assert_eq!(str_time_to_seconds("05:01:02"), five_am + 60 + 2);
+    assert_eq!(
+        try_str_time_to_seconds("25:00:00"),
+        Some(25 * seconds_in_hour)
+    );
+    assert_eq!(try_str_time_to_seconds("5:01"), None);
+    assert_eq!(try_str_time_to_seconds("05:aa:02"), None);
 }
 
 #[test]
@@ -269,3 +1162,504 @@ fn test_walk_distance() {
         }
     }*/
 }
+
+#[test]
+fn trips_after_midnight() {
+    let mut dataset = make_dataset();
+    let trip_id = dataset
+        .trips
+        .iter()
+        .find(|t| !t.is_frequency_based() && t.start_time < 20 * 60 * 60)
+        .unwrap()
+        .trip_id;
+    // Only on Sundays, 24 hours later: it runs on Monday mornings.
+    let service_id = dataset.services.len();
+    dataset.services.push(Service {
+        service_id,
+        days: vec![false, false, false, false, false, false, true],
+        start_date: GtfsTime::try_from_date("20000101").unwrap(),
+        end_date: GtfsTime::try_from_date("21000101").unwrap(),
+        exceptions: vec![],
+        ..Default::default()
+    });
+    dataset.service_calendar = ServiceCalendar::new(&dataset.services);
+    let trip = &mut dataset.trips[trip_id];
+    trip.service_id = Some(service_id);
+    trip.start_time += 24 * 60 * 60;
+
+    let trip = dataset.get_trip(trip_id);
+    let (route_id, stop_times_id) = (trip.route_id, trip.stop_times_id);
+    let start_time = trip.start_time - 24 * 60 * 60;
+    let stop_id = dataset.get_stop_times(stop_times_id).stop_times[0].stop_id;
+    let timezone = dataset.get_route_timezone(route_id);
+    let monday = NaiveDate::from_ymd(2020, 11, 2);
+    let sunday = NaiveDate::from_ymd(2020, 11, 1);
+    let time_on = |date| GtfsTime::from_service_date(date, start_time - 60, timezone);
+
+    assert!(dataset.is_trip_active_on_time(trip, &time_on(monday), Some(3600)));
+    let (found, stop_inx, run_start) = dataset
+        .trip_after_time(
+            &[trip_id],
+            stop_id,
+            &time_on(monday),
+            0,
+            stop_times_id,
+            &HashSet::new(),
+        )
+        .unwrap();
+    assert_eq!(found.trip_id, trip_id);
+    assert_eq!(stop_inx, 0);
+    assert_eq!(run_start, start_time);
+    assert!(dataset
+        .trips_active_in_stop_at_time_range(route_id, stop_id, time_on(monday), 3600)
+        .contains(&(trip_id, 0)));
+
+    assert!(!dataset.is_trip_active_on_time(trip, &time_on(sunday), Some(3600)));
+    assert!(dataset
+        .trip_after_time(
+            &[trip_id],
+            stop_id,
+            &time_on(sunday),
+            0,
+            stop_times_id,
+            &HashSet::new(),
+        )
+        .map_or(true, |(_, _, run_start)| run_start > 3600));
+    assert!(!dataset
+        .trips_active_in_stop_at_time_range(route_id, stop_id, time_on(sunday), 3600)
+        .contains(&(trip_id, 0)));
+}
+
+#[test]
+fn stops_aligned_with_shape_dist_traveled() {
+    // The shape passes near "S3" before reaching "S2", but T1 stops there only on the second
+    // pass. T2 has no distances and falls back to the nearest point.
+    let zip_content = zip_small_feed(|name, content| match name {
+        "shapes.txt" => Some(
+            "shape_id,shape_pt_lat,shape_pt_lon,shape_pt_sequence,shape_dist_traveled\n\
+             SH1,45.4380,12.3180,1,0\n\
+             SH1,45.4400,12.3220,2,100\n\
+             SH1,45.4390,12.3200,3,200\n\
+             SH1,45.4400,12.3220,4,300\n\
+             SH1,45.4410,12.3240,5,400\n"
+                .into(),
+        ),
+        "stop_times.txt" => Some(
+            "trip_id,arrival_time,departure_time,stop_id,stop_sequence,shape_dist_traveled\n\
+             T1,08:00:00,08:00:00,S1,1,0\n\
+             T1,08:10:00,08:10:00,S3,2,300\n\
+             T1,08:15:00,08:15:00,S4,3,400\n\
+             T2,09:00:00,09:00:00,S1,1,\n\
+             T2,09:10:00,09:10:00,S3,2,\n\
+             T2,09:15:00,09:15:00,S4,3,\n"
+                .into(),
+        ),
+        _ => Some(content),
+    });
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+    parser.parse();
+    let ds = &parser.dataset;
+    let shape_id = parser.shape_name_to_inx["SH1"];
+    assert_eq!(ds.get_shape(shape_id).dist_traveled.len(), 5);
+
+    let stop_times_of = |trip: &str| {
+        let trip = ds.get_trip(parser.trip_name_to_inx[trip]);
+        ds.get_stop_times(trip.stop_times_id)
+    };
+    let with_distances = stop_times_of("T1");
+    assert_eq!(
+        with_distances.stop_times[1].shape_dist_traveled,
+        Some(300.0)
+    );
+    assert_eq!(
+        ds.get_stop_points_in_shape(shape_id, with_distances),
+        vec![0, 3, 4]
+    );
+    assert_eq!(
+        ds.get_shape_between_stops(shape_id, with_distances, 0, 1)
+            .len(),
+        4
+    );
+    assert_eq!(
+        ds.get_stop_points_in_shape(shape_id, stop_times_of("T2")),
+        vec![0, 1, 4]
+    );
+
+    let zip_content = zip_small_feed(|name, content| match name {
+        "stop_times.txt" => Some(
+            "trip_id,arrival_time,departure_time,stop_id,stop_sequence,shape_dist_traveled\n\
+             T1,08:00:00,08:00:00,S1,1,-5\n"
+                .into(),
+        ),
+        _ => Some(content),
+    });
+    let error = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)])
+        .try_parse()
+        .unwrap_err();
+    assert_eq!(error.file, "stop_times.txt");
+    assert_eq!(error.field.as_deref(), Some("shape_dist_traveled"));
+}
+
+#[test]
+fn synthetic_shapes_without_shapes_txt() {
+    let zip_content = zip_small_feed(|name, content| match name {
+        "shapes.txt" => None,
+        "trips.txt" => {
+            let content = String::from_utf8(content).unwrap();
+            Some(content.replace(",SH1", ",").into_bytes())
+        }
+        _ => Some(content),
+    });
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+    parser.parse();
+    let ds = &parser.dataset;
+
+    // T1 and T2 have the same stop times, so they share the shape.
+    assert_eq!(ds.shapes.len(), 1);
+    let shape = &ds.shapes[0];
+    assert!(shape.synthetic);
+    let trip = ds.get_trip(parser.trip_name_to_inx["T1"]);
+    assert_eq!(trip.shape_id, 0);
+    assert_eq!(ds.get_trip(parser.trip_name_to_inx["T2"]).shape_id, 0);
+
+    let stop_times = ds.get_stop_times(trip.stop_times_id);
+    let stop_positions = stop_times
+        .stop_times
+        .iter()
+        .map(|st| ds.get_stop(st.stop_id).stop_pos.clone())
+        .collect_vec();
+    let coordinates = |points: &[LatLng]| points.iter().map(|p| (p.lat, p.lng)).collect_vec();
+    assert_eq!(coordinates(&shape.points), coordinates(&stop_positions));
+    assert_eq!(
+        coordinates(&ds.get_shape_between_stops(trip.shape_id, stop_times, 1, 3)),
+        coordinates(&stop_positions[1..])
+    );
+
+    let realtime = TripRealTimePositionData::new(ds, trip.trip_id);
+    let third = &stop_times.stop_times[2];
+    assert!(
+        realtime
+            .get_position(third.arrival_offset_with_trip(trip.start_time))
+            .distance_meters(&stop_positions[2])
+            < 1
+    );
+}
+
+#[test]
+fn synthetic_shapes_for_unknown_shape_ids() {
+    // The trips keep `SH1`, but there is no `shapes.txt`.
+    let zip_content = zip_small_feed(|name, content| match name {
+        "shapes.txt" => None,
+        _ => Some(content),
+    });
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+    let ds = parser.try_parse().unwrap();
+
+    assert_eq!(ds.shapes.len(), 1);
+    assert!(ds.shapes[0].synthetic);
+    assert!(ds.trips.iter().all(|trip| trip.shape_id == 0));
+}
+
+#[test]
+fn no_synthetic_shape_for_trips_without_stop_times() {
+    // `T0` has neither a shape nor stop times.
+    let zip_content = zip_small_feed(|name, content| match name {
+        "trips.txt" => {
+            let content = String::from_utf8(content).unwrap();
+            let mut lines = content.lines();
+            let header = lines.next().unwrap();
+            let trips = std::iter::once(header)
+                .chain(std::iter::once("R1,WK,T0,Fourth,0,"))
+                .chain(lines)
+                .join("\n");
+            Some(trips.into_bytes())
+        }
+        _ => Some(content),
+    });
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+    parser.parse();
+    let ds = &parser.dataset;
+
+    assert_eq!(ds.shapes.len(), 1);
+    assert!(ds.shapes.iter().all(|shape| !shape.synthetic));
+}
+
+#[test]
+fn feed_info_parsing() {
+    let feed_info = "feed_publisher_name,feed_publisher_url,feed_lang,feed_start_date,\
+                     feed_end_date,feed_version,feed_contact_email\n\
+                     ACTV,https://actv.avmspa.it,it,20200101,20201231,2020.1,info@actv.it\n";
+    let zip_content =
+        zip_small_feed_adding_files(|_, content| Some(content), &[("feed_info.txt", feed_info)]);
+    let sources = vec![
+        FeedSource::from_zip_bytes(zip_content),
+        FeedSource::from_zip_bytes(zip_small_feed(|_, content| Some(content))),
+    ];
+    let mut parser = RawParser::new_from_sources(sources);
+    parser.parse();
+    let ds = parser.dataset;
+
+    // Feeds without `feed_info.txt` have an empty one.
+    assert_eq!(ds.feeds.len(), 2);
+    let feed = &ds.feeds[0];
+    assert_eq!(feed.dataset_index, 1);
+    assert_eq!(feed.feed_publisher_name, "ACTV");
+    assert_eq!(feed.feed_lang, "it");
+    assert_eq!(feed.feed_version, "2020.1");
+    assert_eq!(feed.feed_contact_email, "info@actv.it");
+    assert!(feed.is_valid_on(&GtfsTime::from_date("20201231")));
+    assert!(!feed.is_valid_on(&GtfsTime::from_date("20210101")));
+    assert_eq!(ds.feeds[1].feed_version, "");
+    assert!(ds.feeds[1].is_valid_on(&GtfsTime::from_date("20210101")));
+
+    assert!(ds
+        .expired_feeds(&GtfsTime::from_date("20201231"))
+        .is_empty());
+    let expired = ds.expired_feeds(&GtfsTime::from_date("20210101"));
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].feed_version, "2020.1");
+
+    let zip_content = zip_small_feed_adding_files(
+        |_, content| Some(content),
+        &[(
+            "feed_info.txt",
+            "feed_publisher_name,feed_start_date,feed_end_date\nACTV,20201231,20200101\n",
+        )],
+    );
+    let error = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)])
+        .try_parse()
+        .unwrap_err();
+    assert_eq!(error.file, "feed_info.txt");
+    assert_eq!(error.field.as_deref(), Some("feed_end_date"));
+}
+
+#[test]
+fn serialized_data_rebuilt_when_feed_version_changes() {
+    let base = std::env::temp_dir().join(format!("fastgtfs_feed_version_{}", std::process::id()));
+    let (feed, out) = (base.join("feed"), base.join("out"));
+    write_small_feed(&feed);
+    fs::create_dir_all(&out).unwrap();
+    let set_version = |version: &str| {
+        let content = format!("feed_publisher_name,feed_version\nACTV,{}\n", version);
+        fs::write(feed.join("feed_info.txt"), content).unwrap();
+    };
+    let out_path = out.to_str().unwrap();
+    let ensure_serialized = || {
+        RawParser::new(vec![feed.to_str().unwrap().to_string()])
+            .ensure_data_serialized_created_in_path(out_path)
+    };
+
+    set_version("1");
+    ensure_serialized();
+    let feeds = RawParser::read_preprocessed_feeds(out_path.to_string());
+    assert_eq!(feeds[0].feed_version, "1");
+
+    // Same version: the data is not generated again.
+    fs::remove_file(out.join("stops")).unwrap();
+    ensure_serialized();
+    assert!(!out.join("stops").exists());
+
+    set_version("2");
+    ensure_serialized();
+    assert!(out.join("stops").exists());
+    let dataset = RawParser::read_preprocessed_data(out_path.to_string());
+    assert_eq!(dataset.feeds[0].feed_version, "2");
+
+    fs::remove_dir_all(&base).unwrap();
+}
+
+#[test]
+fn serialized_data_rebuilt_when_feed_without_version_changes() {
+    let base = std::env::temp_dir().join(format!("fastgtfs_no_version_{}", std::process::id()));
+    let (feed, out) = (base.join("feed"), base.join("out"));
+    write_small_feed(&feed);
+    fs::create_dir_all(&out).unwrap();
+    let out_path = out.to_str().unwrap();
+    let ensure_serialized = || {
+        RawParser::new(vec![feed.to_str().unwrap().to_string()])
+            .ensure_data_serialized_created_in_path(out_path)
+    };
+
+    ensure_serialized();
+    let feeds = RawParser::read_preprocessed_feeds(out_path.to_string());
+    assert!(feeds[0].feed_version.is_empty());
+    assert!(feeds[0].content_hash.is_some());
+
+    // Same content: the data is not generated again.
+    fs::remove_file(out.join("stops")).unwrap();
+    ensure_serialized();
+    assert!(!out.join("stops").exists());
+
+    let stops = fs::read_to_string(feed.join("stops.txt")).unwrap();
+    fs::write(feed.join("stops.txt"), stops.replace("First", "Renamed")).unwrap();
+    ensure_serialized();
+    assert!(out.join("stops").exists());
+    let dataset = RawParser::read_preprocessed_data(out_path.to_string());
+    assert!(dataset.stops.iter().any(|s| s.stop_name == "Renamed"));
+
+    fs::remove_dir_all(&base).unwrap();
+}
+
+#[test]
+fn serialized_data_rebuilt_when_format_changes() {
+    let base = std::env::temp_dir().join(format!("fastgtfs_format_{}", std::process::id()));
+    let (feed, out) = (base.join("feed"), base.join("out"));
+    write_small_feed(&feed);
+    fs::create_dir_all(&out).unwrap();
+    let out_path = out.to_str().unwrap();
+    let ensure_serialized = || {
+        RawParser::new(vec![feed.to_str().unwrap().to_string()])
+            .ensure_data_serialized_created_in_path(out_path)
+    };
+
+    ensure_serialized();
+    assert!(!RawParser::read_preprocessed_feeds(out_path.to_string()).is_empty());
+
+    // Data serialized before the format was versioned.
+    fs::remove_file(out.join("format_version")).unwrap();
+    fs::remove_file(out.join("stops")).unwrap();
+    assert!(RawParser::read_preprocessed_feeds(out_path.to_string()).is_empty());
+    ensure_serialized();
+    assert!(out.join("stops").exists());
+    let dataset = RawParser::read_preprocessed_data(out_path.to_string());
+    assert_eq!(dataset.stops.len(), 4);
+
+    fs::remove_dir_all(&base).unwrap();
+}
+
+#[test]
+fn translations_parsing() {
+    let translations =
+        "table_name,field_name,language,translation,record_id,record_sub_id,field_value\n\
+                        stops,stop_name,en,First stop,S1,,\n\
+                        stops,stop_name,de,Zweite,,,Second\n\
+                        routes,route_long_name,en,Line one in English,R1,,\n\
+                        trips,trip_headsign,en,To the fourth,T1,,\n\
+                        stop_times,stop_headsign,en,Fourth,T1,1,\n\
+                        attributions,organization_name,en,ACTV,A1,,\n";
+    let zip_content = zip_small_feed_adding_files(
+        |_, content| Some(content),
+        &[("translations.txt", translations)],
+    );
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+    parser.parse();
+    let ds = &parser.dataset;
+    let translations = &ds.translations;
+    // The stop times row has a record id, and attributions are not kept.
+    assert_eq!(translations.len(), 4);
+
+    let first = ds.get_stop(parser.stop_name_to_inx["S1"]);
+    let second = ds.get_stop(parser.stop_name_to_inx["S2"]);
+    assert_eq!(first.stop_name_in(translations, "en"), "First stop");
+    assert_eq!(first.stop_name_in(translations, "de"), "First");
+    assert_eq!(second.stop_name_in(translations, "de"), "Zweite");
+    assert_eq!(second.stop_name_in(translations, "en"), "Second");
+
+    let route_id = parser.routes_name_to_inx["R1"];
+    let route = ds.get_route(route_id);
+    assert_eq!(
+        route.route_long_name_in(translations, "en"),
+        "Line one in English"
+    );
+    assert_eq!(route.route_short_name_in(translations, "en"), "1");
+    let trip = ds.get_trip(parser.trip_name_to_inx["T1"]);
+    assert_eq!(trip.trip_headsign_in(translations, "en"), "To the fourth");
+    assert_eq!(
+        ds.get_trip(parser.trip_name_to_inx["T2"])
+            .trip_headsign_in(translations, "en"),
+        "Fourth"
+    );
+
+    let timetable = TimeTable::new(ds, vec![route_id], 0).unwrap();
+    assert_eq!(
+        timetable.get_stop_names(ds, "de"),
+        vec!["First", "Zweite", "Third", "Fourth"]
+    );
+
+    let mut solution = Solution {
+        components: vec![SolutionComponent::Bus(BusSolutionComponent {
+            route: route.clone(),
+            trip: trip.clone(),
+            ..Default::default()
+        })],
+        ..Default::default()
+    };
+    solution.translate(ds, "en");
+    match &solution.components[0] {
+        SolutionComponent::Bus(bus) => {
+            assert_eq!(bus.route.route_long_name, "Line one in English");
+            assert_eq!(bus.trip.trip_headsign, "To the fourth");
+        }
+        SolutionComponent::Walk(_) => unreachable!(),
+    }
+
+    let zip_content = zip_small_feed_adding_files(
+        |_, content| Some(content),
+        &[(
+            "translations.txt",
+            "table_name,field_name,language,translation,record_id\nstops,stop_name,en,X,S9\n",
+        )],
+    );
+    let error = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)])
+        .try_parse()
+        .unwrap_err();
+    assert_eq!(error.file, "translations.txt");
+    assert_eq!(error.field.as_deref(), Some("record_id"));
+}
+
+#[test]
+fn same_ids_in_different_feeds() {
+    let sources = (0..2)
+        .map(|_| FeedSource::from_zip_bytes(zip_small_feed(|_, content| Some(content))))
+        .collect_vec();
+    let mut parser = RawParser::new_from_sources(sources);
+    parser.parse();
+    let ds = parser.dataset;
+
+    let first = ds.lookup_by_source_id(1, SourceIdKind::Stop, "S1").unwrap();
+    let second = ds.lookup_by_source_id(2, SourceIdKind::Stop, "S1").unwrap();
+    assert_ne!(first, second);
+    assert_eq!(ds.get_stop(second).source_id, "S1");
+    assert_eq!(ds.get_stop(second).dataset_index, 2);
+    assert_eq!(ds.lookup_by_source_id(3, SourceIdKind::Stop, "S1"), None);
+
+    // The trips of each feed use only the stops and the shapes of the same feed.
+    for trip in &ds.trips {
+        let stop_times = &ds.get_stop_times(trip.stop_times_id).stop_times;
+        assert!(stop_times
+            .iter()
+            .all(|st| ds.get_stop(st.stop_id).dataset_index == trip.dataset_index));
+        assert_eq!(
+            ds.get_shape(trip.shape_id).dataset_index,
+            trip.dataset_index
+        );
+        let route = ds.get_route(trip.route_id);
+        assert_eq!(route.dataset_index, trip.dataset_index);
+        let service = ds.get_service(trip.service_id.unwrap());
+        assert_eq!(service.dataset_index, trip.dataset_index);
+    }
+    let trip_id = ds.lookup_by_source_id(2, SourceIdKind::Trip, "T1").unwrap();
+    assert_eq!(ds.get_trip(trip_id).source_id, "T1");
+    let route_id = ds
+        .lookup_by_source_id(2, SourceIdKind::Route, "R1")
+        .unwrap();
+    assert_eq!(ds.get_trip(trip_id).route_id, route_id);
+
+    let out = std::env::temp_dir().join(format!("fastgtfs_source_ids_{}", std::process::id()));
+    fs::create_dir_all(&out).unwrap();
+    let out_path = out.to_str().unwrap().to_string();
+    let sources = (0..2)
+        .map(|_| FeedSource::from_zip_bytes(zip_small_feed(|_, content| Some(content))))
+        .collect_vec();
+    RawParser::new_from_sources(sources).generate_serialized_data(&out_path);
+    let serialized = RawParser::read_preprocessed_data(out_path);
+    assert_eq!(
+        serialized.lookup_by_source_id(2, SourceIdKind::Stop, "S1"),
+        Some(second)
+    );
+    assert_eq!(
+        serialized.lookup_by_source_id(2, SourceIdKind::Trip, "T1"),
+        Some(trip_id)
+    );
+    fs::remove_dir_all(&out).unwrap();
+}
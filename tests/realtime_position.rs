@@ -38,6 +38,33 @@ mod tests {
             .for_each(|t| verify_realtime_position_for_trip_id(&dataset, t.trip_id));
     }
 
+    #[test]
+    fn realtime_position_holds_during_dwell() {
+        init();
+        let mut parser = RawParser::new(get_test_paths());
+        parser.parse();
+        let ds = parser.dataset;
+
+        for trip in ds.trips.iter().take(1000) {
+            let realtime_handler = TripRealTimePositionData::new(&ds, trip.trip_id);
+            let stop_times = &ds.get_stop_times(trip.stop_times_id).stop_times;
+            let (first, last) = (stop_times.first().unwrap(), stop_times.last().unwrap());
+            for stop_time in stop_times.iter().filter(|st| {
+                st.departure_time > st.arrival_time + 1
+                    && st.stop_id != first.stop_id
+                    && st.stop_id != last.stop_id
+            }) {
+                let arrival = stop_time.arrival_offset_with_trip(trip.start_time);
+                let departure = stop_time.departure_offset_with_trip(trip.start_time);
+                let at_arrival = realtime_handler.get_position(arrival);
+                let while_waiting = realtime_handler.get_position((arrival + departure) / 2);
+                let at_departure = realtime_handler.get_position(departure);
+                assert!(at_arrival.distance_meters(&while_waiting) < 1);
+                assert!(at_arrival.distance_meters(&at_departure) < 1);
+            }
+        }
+    }
+
     fn verify_realtime_position_for_trip_id(ds: &GtfsData, trip_id: TripId) {
         let trip = ds.get_trip(trip_id);
         let shape = ds.get_shape(trip.shape_id);
@@ -52,7 +79,7 @@ mod tests {
 
         let mut over = 0;
         for (_i, stop_time) in stop_times.stop_times.iter().enumerate() {
-            let real_stop_time = trip.start_time + stop_time.time;
+            let real_stop_time = stop_time.arrival_offset_with_trip(trip.start_time);
             let real_stop_position = &ds.get_stop(stop_time.stop_id).stop_pos;
 
             let dist = realtime_handler
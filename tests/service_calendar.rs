@@ -0,0 +1,136 @@
+use chrono::NaiveDate;
+
+use fastgtfs::gtfs_data::{GtfsTime, Service, ServiceException};
+use fastgtfs::service_calendar::ServiceCalendar;
+use fastgtfs::test_utils::make_dataset;
+
+/// Runs on weekdays in November 2020.
+fn weekdays(service_id: usize, exceptions: Vec<ServiceException>) -> Service {
+    Service {
+        service_id,
+        days: vec![true, true, true, true, true, false, false],
+        start_date: GtfsTime::from_date("20201101"),
+        end_date: GtfsTime::from_date("20201130"),
+        exceptions,
+        ..Default::default()
+    }
+}
+
+fn exception(date: &str, running: bool) -> ServiceException {
+    ServiceException {
+        date: GtfsTime::from_date(date),
+        running,
+    }
+}
+
+fn date(day: u32, month: u32) -> NaiveDate {
+    NaiveDate::from_ymd(2020, month, day)
+}
+
+#[test]
+fn service_dates_are_enforced() {
+    let calendar = ServiceCalendar::new(&[weekdays(0, vec![])]);
+    // A Monday, a Friday and a Saturday.
+    assert!(calendar.is_active(0, date(2, 11)));
+    assert!(calendar.is_active(0, date(27, 11)));
+    assert!(!calendar.is_active(0, date(28, 11)));
+    // Mondays before and after the service dates.
+    assert!(!calendar.is_active(0, date(26, 10)));
+    assert!(calendar.is_active(0, date(30, 11)));
+    assert!(!calendar.is_active(0, date(7, 12)));
+    // Not a service.
+    assert!(!calendar.is_active(1, date(2, 11)));
+}
+
+#[test]
+fn exceptions_add_and_remove_dates() {
+    let services = vec![
+        weekdays(
+            0,
+            vec![exception("20201102", false), exception("20201107", true)],
+        ),
+        // Only in calendar_dates.txt.
+        Service {
+            service_id: 1,
+            days: vec![false; 7],
+            start_date: GtfsTime::from_date("19700101"),
+            end_date: GtfsTime::from_date("19700101"),
+            exceptions: vec![exception("20201225", true)],
+            ..Default::default()
+        },
+    ];
+    let calendar = ServiceCalendar::new(&services);
+    assert!(!calendar.is_active(0, date(2, 11)));
+    assert!(calendar.is_active(0, date(7, 11)));
+    assert!(calendar.is_active(1, date(25, 12)));
+    assert!(!calendar.is_active(1, date(1, 1)));
+
+    assert_eq!(calendar.services_on(date(2, 11)), Vec::<usize>::new());
+    assert_eq!(calendar.services_on(date(3, 11)), vec![0]);
+    assert_eq!(calendar.services_on(date(25, 12)), vec![1]);
+    assert_eq!(calendar.services_on(date(1, 1)), Vec::<usize>::new());
+}
+
+#[test]
+fn services_on_many_services() {
+    let services = (0..130)
+        .map(|i| weekdays(i, vec![]))
+        .collect::<Vec<Service>>();
+    let calendar = ServiceCalendar::new(&services);
+    assert_eq!(
+        calendar.services_on(date(4, 11)),
+        (0..130).collect::<Vec<usize>>()
+    );
+    assert!(calendar.is_active(129, date(4, 11)));
+    assert!(calendar.services_on(date(8, 11)).is_empty());
+}
+
+#[test]
+fn dataset_calendar_matches_services() {
+    let dataset = make_dataset();
+    for day in &["20201101", "20201102", "20201225", "20210615"] {
+        let day = GtfsTime::from_date(day);
+        let active = dataset.service_calendar.services_on(day.service_date());
+        for service in &dataset.services {
+            let in_dates = service.start_date <= day && day <= service.end_date;
+            let expected = match service.exceptions.iter().find(|e| e.date.is_same_day(&day)) {
+                Some(exception) => exception.running,
+                None => in_dates && service.days[day.day_of_week() as usize],
+            };
+            assert_eq!(active.contains(&service.service_id), expected);
+            assert_eq!(
+                dataset
+                    .service_calendar
+                    .is_active(service.service_id, day.service_date()),
+                expected
+            );
+        }
+    }
+}
+
+#[test]
+fn far_end_dates_are_not_expanded() {
+    let services = vec![Service {
+        service_id: 0,
+        days: vec![true, true, true, true, true, false, false],
+        start_date: GtfsTime::from_date("20201101"),
+        end_date: GtfsTime::from_date("99991231"),
+        exceptions: vec![exception("20201225", false), exception("20201226", true)],
+        ..Default::default()
+    }];
+    let calendar = ServiceCalendar::new(&services);
+    // Only a couple of years are expanded.
+    assert!(serde_json::to_vec(&calendar).unwrap().len() < 100_000);
+
+    assert!(!calendar.is_active(0, date(25, 12)));
+    assert!(calendar.is_active(0, date(26, 12)));
+    // A Monday and a Saturday in 2090, and a Monday after the end date.
+    assert!(calendar.is_active(0, NaiveDate::from_ymd(2090, 1, 2)));
+    assert!(!calendar.is_active(0, NaiveDate::from_ymd(2090, 1, 7)));
+    assert_eq!(
+        calendar.services_on(NaiveDate::from_ymd(2090, 1, 2)),
+        vec![0]
+    );
+    assert!(!calendar.is_active(0, NaiveDate::from_ymd(10000, 1, 3)));
+    assert!(!calendar.is_active(0, date(26, 10)));
+}
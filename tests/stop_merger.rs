@@ -0,0 +1,124 @@
+use itertools::Itertools;
+
+use fastgtfs::feed_source::FeedSource;
+use fastgtfs::gtfs_data::{GtfsData, SourceIdKind};
+use fastgtfs::raw_parser::RawParser;
+use fastgtfs::stop_merger::{merge_duplicate_stops, name_similarity, StopMergeParams};
+
+use common::zip_small_feed;
+
+mod common;
+
+/// Two feeds with the same stops: in the second, they are a few meters away and some have a
+/// different name.
+fn parser_with_duplicate_stops() -> RawParser {
+    let second = zip_small_feed(|name, content| match name {
+        "stops.txt" => {
+            let content = String::from_utf8(content).unwrap();
+            let content = content
+                .replace("First", "FIRST.")
+                .replace("Second", "Seconda")
+                .replace("Third", "Terzo")
+                .replace("45.4380,", "45.43805,");
+            Some(content.into_bytes())
+        }
+        _ => Some(content),
+    });
+    RawParser::new_from_sources(vec![
+        FeedSource::from_zip_bytes(zip_small_feed(|_, content| Some(content))),
+        FeedSource::from_zip_bytes(second),
+    ])
+}
+
+#[test]
+fn duplicate_stops_linked_and_merged() {
+    assert_eq!(name_similarity("First", "FIRST."), 1.0);
+    assert!(name_similarity("Second", "Seconda") > 0.8);
+    assert!(name_similarity("Third", "Terzo") < 0.5);
+
+    let mut parser = parser_with_duplicate_stops();
+    parser.parse();
+    let mut ds = parser.dataset;
+    let stop = |ds: &GtfsData, feed, id| {
+        ds.lookup_by_source_id(feed, SourceIdKind::Stop, id)
+            .unwrap()
+    };
+    let number_of_stops = ds.stops.len();
+
+    let merges = merge_duplicate_stops(&mut ds, &StopMergeParams::default());
+    let groups = merges.iter().map(|m| m.stop_ids.clone()).collect_vec();
+    let expected = ["S1", "S2", "S4"]
+        .iter()
+        .map(|id| vec![stop(&ds, 1, id), stop(&ds, 2, id)])
+        .sorted()
+        .collect_vec();
+    assert_eq!(groups.into_iter().sorted().collect_vec(), expected);
+    assert_eq!(ds.stops.len(), number_of_stops + 3);
+    let (first, other_first) = (stop(&ds, 1, "S1"), stop(&ds, 2, "S1"));
+    assert!(!merges[0].merged);
+    assert_eq!(ds.get_station_platforms(first), vec![first, other_first]);
+    assert_eq!(ds.get_stop(stop(&ds, 2, "S3")).parent_station, None);
+
+    let mut parser = parser_with_duplicate_stops();
+    parser.parse();
+    let mut ds = parser.dataset;
+    let params = StopMergeParams {
+        merge: true,
+        ..Default::default()
+    };
+    let merges = merge_duplicate_stops(&mut ds, &params);
+    assert_eq!(merges.len(), 3);
+    assert!(merges.iter().all(|m| m.merged));
+    let (other_first, other_third) = (stop(&ds, 2, "S1"), stop(&ds, 2, "S3"));
+    assert!(ds.get_stop(other_first).routes.is_empty());
+    assert_eq!(ds.get_stop(first).routes.len(), 2);
+    // The trips of the second feed stop at the stops of the first, except the different one.
+    let trip = ds.get_trip(ds.lookup_by_source_id(2, SourceIdKind::Trip, "T1").unwrap());
+    let stop_ids = ds
+        .get_stop_times(trip.stop_times_id)
+        .stop_times
+        .iter()
+        .map(|st| st.stop_id)
+        .collect_vec();
+    assert_eq!(
+        stop_ids,
+        vec![first, stop(&ds, 1, "S2"), other_third, stop(&ds, 1, "S4")]
+    );
+}
+
+#[test]
+fn stops_of_the_same_feed_never_merged() {
+    // In the first feed, `S5` is a second stop near `S1` with the same name: both are duplicates
+    // of `S1` of the second feed, but they must stay apart.
+    let first = zip_small_feed(|name, content| match name {
+        "stops.txt" => {
+            let content = String::from_utf8(content).unwrap();
+            Some((content + "S5,5,First,45.43803,12.3180,Z1,0,\n").into_bytes())
+        }
+        _ => Some(content),
+    });
+    let mut parser = RawParser::new_from_sources(vec![
+        FeedSource::from_zip_bytes(first),
+        FeedSource::from_zip_bytes(zip_small_feed(|_, content| Some(content))),
+    ]);
+    parser.parse();
+    let mut ds = parser.dataset;
+    let stop = |ds: &GtfsData, feed, id| {
+        ds.lookup_by_source_id(feed, SourceIdKind::Stop, id)
+            .unwrap()
+    };
+    let params = StopMergeParams {
+        merge: true,
+        ..Default::default()
+    };
+    let merges = merge_duplicate_stops(&mut ds, &params);
+
+    let (first, other_first) = (stop(&ds, 1, "S1"), stop(&ds, 1, "S5"));
+    assert!(merges
+        .iter()
+        .all(|m| !(m.stop_ids.contains(&first) && m.stop_ids.contains(&other_first))));
+    assert!(merges
+        .iter()
+        .any(|m| m.stop_ids == vec![first, stop(&ds, 2, "S1")]
+            || m.stop_ids == vec![other_first, stop(&ds, 2, "S1")]));
+}
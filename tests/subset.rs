@@ -0,0 +1,246 @@
+use itertools::Itertools;
+
+use fastgtfs::feed_source::FeedSource;
+use fastgtfs::gtfs_data::{GtfsTime, LatLng, RouteType, SourceIdKind};
+use fastgtfs::gtfs_writer::GtfsWriter;
+use fastgtfs::raw_parser::{str_time_to_seconds, RawParser};
+use fastgtfs::subset::{Region, SubsetFilter};
+
+use common::zip_small_feed;
+
+mod common;
+
+/// The small feed, with a station for `S2` with an entrance.
+fn zip_feed_with_station() -> Vec<u8> {
+    zip_small_feed(|name, content| {
+        if name != "stops.txt" {
+            return Some(content);
+        }
+        let content = String::from_utf8(content)
+            .unwrap()
+            .replace("Z1,0,\nS3", "Z1,0,ST\nS3")
+            .trim_end()
+            .to_string()
+            + "\nST,,Station,45.4391,12.3201,,1,\nE1,,Entrance,45.4392,12.3202,,2,ST\n";
+        Some(content.into_bytes())
+    })
+}
+
+#[test]
+fn subset_of_region_dates_and_agencies() {
+    let sources = (0..2)
+        .map(|_| FeedSource::from_zip_bytes(zip_feed_with_station()))
+        .collect_vec();
+    let mut parser = RawParser::new_from_sources(sources);
+    parser.parse();
+    let ds = parser.dataset;
+    let second_agency = ds
+        .lookup_by_source_id(2, SourceIdKind::Agency, "A1")
+        .unwrap();
+
+    // Only the second feed, without `S1`.
+    let filter = SubsetFilter {
+        region: Some(Region::BoundingBox {
+            min: LatLng {
+                lat: 45.4385,
+                lng: 12.3100,
+            },
+            max: LatLng {
+                lat: 45.4500,
+                lng: 12.3300,
+            },
+        }),
+        agency_ids: vec![second_agency].into_iter().collect(),
+        ..Default::default()
+    };
+    let subset = ds.subset(&filter);
+    assert_eq!(subset.agencies.len(), 1);
+    assert_eq!(subset.routes.len(), 1);
+    assert_eq!(subset.trips.len(), 2);
+    assert_eq!(subset.shapes.len(), 1);
+    assert_eq!(subset.services.len(), 1);
+    let stop_ids = subset
+        .stops
+        .iter()
+        .map(|s| (s.source_id.as_str(), s.dataset_index))
+        .collect_vec();
+    assert_eq!(
+        stop_ids,
+        vec![("S2", 2), ("S3", 2), ("S4", 2), ("ST", 2), ("E1", 2)]
+    );
+    let station = subset
+        .lookup_by_source_id(2, SourceIdKind::Stop, "ST")
+        .unwrap();
+    let platform = subset
+        .lookup_by_source_id(2, SourceIdKind::Stop, "S2")
+        .unwrap();
+    let entrance = subset
+        .lookup_by_source_id(2, SourceIdKind::Stop, "E1")
+        .unwrap();
+    assert_eq!(subset.get_stop(platform).parent_station, Some(station));
+    assert_eq!(
+        subset.get_stop(station).children,
+        vec![platform, entrance].into_iter().collect()
+    );
+    assert_eq!(
+        subset.get_stop(platform).routes,
+        vec![0].into_iter().collect()
+    );
+
+    // The trips start at `S2`.
+    for (inx, trip) in subset.trips.iter().enumerate() {
+        assert_eq!(trip.trip_id, inx);
+        assert_eq!(trip.route_id, 0);
+        let stop_times = &subset.get_stop_times(trip.stop_times_id).stop_times;
+        assert_eq!(stop_times.len(), 3);
+        assert_eq!(stop_times[0].stop_id, platform);
+        assert_eq!(stop_times[0].arrival_time, 0);
+    }
+    let trip = subset.get_trip(
+        subset
+            .lookup_by_source_id(2, SourceIdKind::Trip, "T1")
+            .unwrap(),
+    );
+    assert_eq!(trip.start_time, str_time_to_seconds("08:05:00"));
+    let stop_times = &subset.get_stop_times(trip.stop_times_id).stop_times;
+    assert_eq!(
+        stop_times[2].arrival_offset_with_trip(trip.start_time),
+        str_time_to_seconds("08:15:00")
+    );
+
+    // The service is cut to the dates.
+    let filter = SubsetFilter {
+        start_date: Some(GtfsTime::from_date("20210301")),
+        end_date: Some(GtfsTime::from_date("20210331")),
+        route_types: vec![RouteType::Bus],
+        ..Default::default()
+    };
+    let subset = ds.subset(&filter);
+    assert_eq!(subset.trips.len(), 4);
+    assert_eq!(subset.stops.len(), ds.stops.len());
+    let service = &subset.services[0];
+    assert_eq!(service.start_date, GtfsTime::from_date("20210301"));
+    assert_eq!(service.end_date, GtfsTime::from_date("20210331"));
+    assert!(service.exceptions.is_empty());
+    let date = |yyyymmdd| GtfsTime::from_date(yyyymmdd).service_date();
+    assert!(subset.service_calendar.is_active(0, date("20210315")));
+    assert!(!subset.service_calendar.is_active(0, date("20210401")));
+
+    // Nothing left.
+    let filter = SubsetFilter {
+        start_date: Some(GtfsTime::from_date("20350101")),
+        ..Default::default()
+    };
+    assert!(ds.subset(&filter).trips.is_empty());
+    let filter = SubsetFilter {
+        route_types: vec![RouteType::Tram],
+        ..Default::default()
+    };
+    let subset = ds.subset(&filter);
+    assert!(subset.stops.is_empty());
+    assert!(subset.agencies.is_empty());
+
+    // Only `S1` and `S2` are in the triangle.
+    let pos = |lat, lng| LatLng { lat, lng };
+    let filter = SubsetFilter {
+        region: Some(Region::Polygon(vec![
+            pos(45.4370, 12.3170),
+            pos(45.4395, 12.3170),
+            pos(45.4395, 12.3230),
+        ])),
+        ..Default::default()
+    };
+    let subset = ds.subset(&filter);
+    assert_eq!(subset.trips.len(), 4);
+    assert!(subset.stop_times.iter().all(|st| st.stop_times.len() == 2));
+}
+
+#[test]
+fn subset_with_only_exceptions_in_dates() {
+    // Out of its regular dates, `WK` runs only on 2035-01-01.
+    let zip_content = zip_small_feed(|name, content| match name {
+        "calendar_dates.txt" => {
+            let content = String::from_utf8(content).unwrap();
+            Some((content.trim_end().to_string() + "\nWK,20350101,1\n").into_bytes())
+        }
+        _ => Some(content),
+    });
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+    parser.parse();
+    let filter = SubsetFilter {
+        start_date: Some(GtfsTime::from_date("20350101")),
+        end_date: Some(GtfsTime::from_date("20350131")),
+        ..Default::default()
+    };
+    let subset = parser.dataset.subset(&filter);
+
+    let service = &subset.services[0];
+    assert!(service.days.iter().all(|&d| !d));
+    assert!(service.start_date.service_date() <= service.end_date.service_date());
+    assert_eq!(service.exceptions.len(), 1);
+    let date = |yyyymmdd| GtfsTime::from_date(yyyymmdd).service_date();
+    assert_eq!(
+        subset.service_calendar.services_on(date("20350101")),
+        vec![0]
+    );
+    assert!(subset
+        .service_calendar
+        .services_on(date("20350102"))
+        .is_empty());
+
+    // Written with the exception only, without an empty calendar.
+    let bytes = GtfsWriter::new(&subset).to_zip_bytes().unwrap();
+    let written = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(bytes)])
+        .try_parse()
+        .unwrap();
+    assert_eq!(
+        written.service_calendar.services_on(date("20350101")),
+        vec![0]
+    );
+    assert!(written
+        .service_calendar
+        .services_on(date("20350102"))
+        .is_empty());
+}
+
+#[test]
+fn subset_keeps_trips_from_jumping_outside_the_region() {
+    // `T1` goes back to `S2` after `S4`, leaving the region of `S1` and `S2` in between.
+    let zip_content = zip_small_feed(|name, content| match name {
+        "stop_times.txt" => {
+            let content = String::from_utf8(content).unwrap();
+            Some((content + "T1,08:20:00,08:20:00,S2,5\n").into_bytes())
+        }
+        _ => Some(content),
+    });
+    let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+    parser.parse();
+    let filter = SubsetFilter {
+        region: Some(Region::BoundingBox {
+            min: LatLng {
+                lat: 45.4370,
+                lng: 12.3100,
+            },
+            max: LatLng {
+                lat: 45.4395,
+                lng: 12.3300,
+            },
+        }),
+        ..Default::default()
+    };
+    let subset = parser.dataset.subset(&filter);
+
+    let trip = subset.get_trip(
+        subset
+            .lookup_by_source_id(1, SourceIdKind::Trip, "T1")
+            .unwrap(),
+    );
+    assert_eq!(trip.start_time, str_time_to_seconds("08:00:00"));
+    let stop_times = &subset.get_stop_times(trip.stop_times_id).stop_times;
+    let stops = stop_times
+        .iter()
+        .map(|st| subset.get_stop(st.stop_id).source_id.as_str())
+        .collect_vec();
+    assert_eq!(stops, vec!["S1", "S2"]);
+    assert_eq!(stop_times[1].arrival_time, 5 * 60);
+}
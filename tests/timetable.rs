@@ -1,3 +1,5 @@
+mod common;
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashSet;
@@ -7,12 +9,15 @@ mod tests {
     use rayon::iter::IntoParallelRefIterator;
     use rayon::iter::ParallelIterator;
 
-    use fastgtfs::gtfs_data::StopId;
+    use fastgtfs::feed_source::FeedSource;
+    use fastgtfs::gtfs_data::{GtfsTime, StopId};
     use fastgtfs::raw_parser::RawParser;
 
     use fastgtfs::test_utils::get_test_paths;
     use fastgtfs::timetable::TimeTable;
 
+    use crate::common::zip_small_feed;
+
     fn init() {
         let _ = env_logger::builder()
             .is_test(true)
@@ -86,4 +91,29 @@ mod tests {
         print!("Errors: {}", errors);
         assert!(errors < 5, "{} Errors", errors);
     }
+
+    #[test]
+    fn trips_active_on_the_service_date_of_the_route() {
+        // The service of the feed, in Europe/Rome, doesn't run on the 30th of August 2020.
+        let zip_content = zip_small_feed(|name, content| {
+            if name != "calendar_dates.txt" {
+                return Some(content);
+            }
+            Some(b"service_id,date,exception_type\nWK,20200830,2\n".to_vec())
+        });
+        let mut parser = RawParser::new_from_sources(vec![FeedSource::from_zip_bytes(zip_content)]);
+        let ds = parser.try_parse().unwrap();
+        let timetable = TimeTable::new(&ds, vec![0], 0).unwrap();
+
+        // 23:30 UTC is already the 30th in Rome, and 22:30 UTC the next day is the 31st.
+        let before_midnight = GtfsTime::from_date("20200829").new_replacing_time(23 * 3600 + 1800);
+        assert!(timetable
+            .get_trips_active_on_date(&ds, &before_midnight)
+            .is_empty());
+        let late_evening = GtfsTime::from_date("20200830").new_replacing_time(22 * 3600 + 1800);
+        assert_eq!(
+            timetable.get_trips_active_on_date(&ds, &late_evening).len(),
+            2
+        );
+    }
 }
@@ -0,0 +1,120 @@
+use std::fs;
+
+use fastgtfs::feed_source::FeedSource;
+use fastgtfs::gtfs_data::{GtfsTime, LatLng};
+use fastgtfs::raw_models::RawTrip;
+use fastgtfs::raw_parser::RawParser;
+use fastgtfs::test_utils::{get_test_paths, make_dataset};
+use fastgtfs::validation::{validate, IssueKind, RawFeed, Severity, ValidationParams};
+
+use common::write_small_feed;
+
+mod common;
+
+fn raw_feeds() -> Vec<RawFeed> {
+    get_test_paths()
+        .iter()
+        .map(|path| RawFeed::read(&FeedSource::from_path(path)))
+        .collect()
+}
+
+#[test]
+fn test_data_is_valid() {
+    let ds = make_dataset();
+    let report = validate(&ds, &raw_feeds(), &ValidationParams::default());
+    for issue in &report.issues {
+        println!("{}", issue);
+    }
+    assert!(!report.has_errors());
+}
+
+#[test]
+fn broken_data_is_reported() {
+    let mut ds = make_dataset();
+    let mut raw = raw_feeds();
+
+    ds.stops[0].stop_pos = LatLng { lat: 0.0, lng: 0.0 };
+    let stop_times_id = ds.trips[0].stop_times_id;
+    ds.stop_times[stop_times_id].stop_times.reverse();
+    let service_id = raw[0].trips[0].service_id.clone();
+    raw[0].trips.push(RawTrip {
+        trip_id: "broken".to_string(),
+        route_id: "missing_route".to_string(),
+        service_id,
+        ..Default::default()
+    });
+    let params = ValidationParams {
+        feed_start: GtfsTime::from_date("30000101"),
+        feed_end: GtfsTime::from_date("30001231"),
+    };
+
+    let report = validate(&ds, &raw, &params);
+    let has_issue = |severity: Severity, kind: IssueKind| {
+        report
+            .issues
+            .iter()
+            .any(|i| i.severity == severity && i.kind == kind)
+    };
+    assert!(has_issue(
+        Severity::Error,
+        IssueKind::StopWithoutPosition { stop_id: 0 }
+    ));
+    assert!(has_issue(
+        Severity::Error,
+        IssueKind::NonMonotonicStopTimes {
+            trip_id: 0,
+            stop_index: 1
+        }
+    ));
+    assert!(has_issue(
+        Severity::Error,
+        IssueKind::DanglingReference {
+            feed: raw[0].name.clone(),
+            file: "trips.txt".to_string(),
+            id: "broken".to_string(),
+            field: "route_id".to_string(),
+            value: "missing_route".to_string(),
+        }
+    ));
+    // Nothing runs in the year 3000
+    assert_eq!(
+        report
+            .warnings()
+            .filter(|i| matches!(i.kind, IssueKind::ServiceOutsideFeedWindow { .. }))
+            .count(),
+        ds.services.len()
+    );
+}
+
+#[test]
+fn only_unused_platforms_are_reported() {
+    let feed = std::env::temp_dir().join(format!("fastgtfs_station_{}", std::process::id()));
+    write_small_feed(&feed);
+    // `S2` inside the station `ST`, with an entrance and a platform that no route uses.
+    let stops = fs::read_to_string(feed.join("stops.txt"))
+        .unwrap()
+        .replace("Z1,0,\nS3", "Z1,0,ST\nS3")
+        .trim_end()
+        .to_string()
+        + "\nST,,Station,45.4391,12.3201,,1,\n\
+           E1,,Entrance,45.4392,12.3202,,2,ST\n\
+           P1,,Unused platform,45.4393,12.3203,,0,ST\n";
+    fs::write(feed.join("stops.txt"), stops).unwrap();
+    let feed_path = feed.to_str().unwrap().to_string();
+    let mut parser = RawParser::new(vec![feed_path.clone()]);
+    parser.parse();
+    let ds = parser.dataset;
+    let raw = vec![RawFeed::read(&FeedSource::from_path(&feed_path))];
+
+    let report = validate(&ds, &raw, &ValidationParams::default());
+    let unused = report
+        .warnings()
+        .filter_map(|i| match i.kind {
+            IssueKind::UnusedStop { stop_id } => Some(ds.get_stop(stop_id).source_id.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(unused, vec!["P1"]);
+
+    fs::remove_dir_all(&feed).unwrap();
+}